@@ -1,23 +1,74 @@
-pub fn batch_items_by_cpu_count<TItem: Clone>(items: &[TItem]) -> Vec<Vec<TItem>> {
-    let batch_count = num_cpus::get();
-    
-    batch_items(items, batch_count)
+// How many CPU bound tasks to run at once, given the --jobs setting. Defaults to one
+// per CPU.
+pub fn jobs_count(jobs: Option<usize>) -> usize {
+    jobs.unwrap_or_else(num_cpus::get).max(1)
 }
 
+// Splits items into at most batch_count batches whose sizes differ by at most one.
+// Never returns empty batches, so there are fewer batches than requested when there
+// are fewer items than batches.
 pub fn batch_items<TItem: Clone>(items: &[TItem], batch_count: usize) -> Vec<Vec<TItem>> {
-    let items_per_batch = (items.len() as f32 / batch_count as f32).ceil() as usize;
+    let batch_count = batch_count.clamp(1, items.len().max(1));
+    let items_per_batch = items.len() / batch_count;
+    let remainder = items.len() % batch_count;
 
-    let mut batches = Vec::new(); 
+    let mut batches = Vec::new();
+    let mut start = 0;
 
     for i in 0..batch_count {
-        let start = i * items_per_batch;
-        let end = ((i + 1) * items_per_batch).min(items.len());
+        // Spread the remainder over the first batches.
+        let end = start + items_per_batch + if i < remainder { 1 } else { 0 };
 
-        // Use signed isize type to avoid overflow in comparison.
-        if (end as isize) - start as isize > 0 {
+        if end > start {
             batches.push(Vec::from(&items[start..end]));
         }
+
+        start = end;
     }
 
     batches
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{batch_items, jobs_count};
+
+    #[test]
+    fn batching_empty() {
+        assert!(batch_items::<usize>(&[], 4).is_empty());
+        assert!(batch_items::<usize>(&[], 0).is_empty());
+    }
+
+    #[test]
+    fn batching_even_sizes() {
+        let items: Vec<usize> = (0..10).collect();
+
+        let sizes: Vec<usize> = batch_items(&items, 4).iter().map(|batch| batch.len()).collect();
+        assert_eq!(vec![3, 3, 2, 2], sizes);
+
+        let sizes: Vec<usize> = batch_items(&items, 3).iter().map(|batch| batch.len()).collect();
+        assert_eq!(vec![4, 3, 3], sizes);
+    }
+
+    #[test]
+    fn batching_more_batches_than_items() {
+        let items: Vec<usize> = (0..3).collect();
+
+        let batches = batch_items(&items, 8);
+        assert_eq!(vec![vec![0], vec![1], vec![2]], batches);
+    }
+
+    #[test]
+    fn batching_zero_batches() {
+        let items: Vec<usize> = (0..3).collect();
+
+        assert_eq!(vec![vec![0, 1, 2]], batch_items(&items, 0));
+    }
+
+    #[test]
+    fn batching_jobs_count() {
+        assert_eq!(num_cpus::get().max(1), jobs_count(None));
+        assert_eq!(3, jobs_count(Some(3)));
+        assert_eq!(1, jobs_count(Some(0)));
+    }
+}
@@ -0,0 +1,96 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::encoding::{detect_encoding, Encoding};
+
+// How much of the start of a file is sniffed for NUL bytes.
+pub const SNIFF_LENGTH: usize = 8192;
+
+// Extensions of files that are binary often enough that they aren't worth opening.
+const BINARY_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "gif", "bmp", "ico", "webp", "tif", "tiff", "psd",
+    "mp3", "mp4", "wav", "ogg", "flac", "avi", "mov", "mkv", "webm",
+    "zip", "jar", "war", "ear", "gz", "tgz", "bz2", "xz", "7z", "rar", "tar", "zst", "nupkg",
+    "class", "o", "obj", "a", "lib", "so", "dll", "dylib", "exe", "pdb", "pyc", "wasm",
+    "pdf", "doc", "docx", "xls", "xlsx", "ppt", "pptx",
+    "ttf", "otf", "woff", "woff2", "eot",
+    "sqlite", "db", "iso", "dmg",
+];
+
+// What to do with files that look binary when indexing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum BinaryHandling {
+    // Leave them out of the index.
+    #[default]
+    Skip,
+
+    // Index them like any other file.
+    Text,
+
+    // Index their path, but not their contents.
+    NameOnly,
+}
+
+impl BinaryHandling {
+    pub fn from_name(name: &str) -> Option<BinaryHandling> {
+        match name {
+            "skip" => Some(BinaryHandling::Skip),
+            "text" => Some(BinaryHandling::Text),
+            "name-only" => Some(BinaryHandling::NameOnly),
+            _ => None,
+        }
+    }
+}
+
+pub fn has_binary_extension(file_path: &str) -> bool {
+    Path::new(file_path)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .is_some_and(|extension| BINARY_EXTENSIONS.contains(&extension.as_str()))
+}
+
+// Text files practically never contain NUL bytes, except for UTF-16, where every
+// ASCII character has one.
+pub fn looks_binary(first_block: &[u8]) -> bool {
+    let first_block = &first_block[..first_block.len().min(SNIFF_LENGTH)];
+
+    if !first_block.contains(&0) {
+        return false;
+    }
+
+    !matches!(detect_encoding(first_block, Encoding::default()).0, Encoding::Utf16Le | Encoding::Utf16Be)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{has_binary_extension, looks_binary};
+
+    #[test]
+    fn binary_extensions() {
+        assert!(has_binary_extension("assets/logo.PNG"));
+        assert!(has_binary_extension("lib/app.jar"));
+        assert!(!has_binary_extension("src/main.rs"));
+        assert!(!has_binary_extension("Makefile"));
+        assert!(!has_binary_extension("src/png.rs"));
+    }
+
+    #[test]
+    fn binary_sniffing() {
+        assert!(looks_binary(b"\x7fELF\x02\x01\x01\x00\x00\x00"));
+        assert!(looks_binary(b"PK\x03\x04\x14\x00\x00\x00\x08\x00"));
+        assert!(!looks_binary(b"fn main() {}\n"));
+        assert!(!looks_binary("caf\u{e9}".as_bytes()));
+        assert!(!looks_binary(b""));
+
+        // UTF-16 has plenty of NUL bytes but is text.
+        let utf16: Vec<u8> = "resource string".encode_utf16().flat_map(|unit| unit.to_le_bytes()).collect();
+        assert!(!looks_binary(&utf16));
+        assert!(!looks_binary(b"\xff\xfea\x00b\x00"));
+
+        // Only the first block counts.
+        let mut late_nul = vec![b'a'; super::SNIFF_LENGTH];
+        late_nul.push(0);
+        assert!(!looks_binary(&late_nul));
+    }
+}
@@ -10,26 +10,30 @@ pub struct BloomFilter {
 impl BloomFilter {
     pub fn new(inputs: &[u32], filter_size: usize) -> BloomFilter {
 
-        let mut filter_array = Vec::from_iter(
+        let filter_array = Vec::from_iter(
             (0u64..1u64).cycle().take(filter_size));
 
+        let mut filter = BloomFilter {
+            filter_array
+        };
+
         for input in inputs {
-            let (index, bit) = input_to_offset_and_bit(*input, filter_size);
-            filter_array[index] |= bit;
+            filter.insert(*input);
         }
 
-        BloomFilter {
-            filter_array: filter_array
-        }
+        filter
+    }
+
+    pub fn insert(&mut self, input: u32) {
+        let (index, bit) = input_to_offset_and_bit(input, self.filter_array.len());
+        self.filter_array[index] |= bit;
     }
 
-    pub fn from_filters(bloom_filters: &[BloomFilter]) -> BloomFilter {
-        let mut combined = bloom_filters.get(0).unwrap().clone();
+    pub fn from_filters(bloom_filters: &[BloomFilter], filter_size: usize) -> BloomFilter {
+        let mut combined = BloomFilter::new(&[], filter_size);
 
         for filter in bloom_filters {
-            for (i, item) in filter.filter_array.iter().enumerate() {
-                combined.filter_array[i] |= item;
-            }
+            combined.merge(filter);
         }
 
         combined
@@ -46,7 +50,43 @@ impl BloomFilter {
             }
         }
 
-        return true;
+        true
+    }
+
+    // Fraction of bits that are set. The closer to 1, the more likely the
+    // filter is to match any query, and the less useful it is.
+    pub fn fill_ratio(&self) -> f32 {
+        let set_bits: u32 = self.filter_array.iter().map(|item| item.count_ones()).sum();
+        let total_bits = self.filter_array.len() as u32 * u64::BITS;
+
+        if total_bits == 0 {
+            return 0f32;
+        }
+
+        set_bits as f32 / total_bits as f32
+    }
+
+    // Jaccard similarity of the set bits: 1 if identical, 0 if nothing in common.
+    pub fn similarity(&self, other: &BloomFilter) -> f32 {
+        let mut shared_bits = 0;
+        let mut total_bits = 0;
+
+        for (item, other_item) in self.filter_array.iter().zip(other.filter_array.iter()) {
+            shared_bits += (item & other_item).count_ones();
+            total_bits += (item | other_item).count_ones();
+        }
+
+        if total_bits == 0 {
+            return 1f32;
+        }
+
+        shared_bits as f32 / total_bits as f32
+    }
+
+    pub fn merge(&mut self, other: &BloomFilter) {
+        for (item, other_item) in self.filter_array.iter_mut().zip(other.filter_array.iter()) {
+            *item |= other_item;
+        }
     }
 }
 
@@ -54,7 +94,7 @@ fn input_to_offset_and_bit(input: u32, array_length: usize) -> (usize, u64) {
     let offset = ((input / u64::BITS) as usize) % array_length;
     let bit = 1 << (input % u64::BITS) as u64;
 
-    return (offset, bit);
+    (offset, bit)
 }
 
 #[cfg(test)]
@@ -75,6 +115,38 @@ mod tests {
         assert!(!filter.possibly_contains(&non_empty_query));
     }
 
+    #[test]
+    fn bloom_from_no_filters() {
+        let combined = BloomFilter::from_filters(&[], 4);
+
+        assert_eq!(vec![0, 0, 0, 0], combined.filter_array);
+        assert!(combined.possibly_contains(&BloomFilter::new(&[], 4)));
+    }
+
+    #[test]
+    fn bloom_from_filters() {
+        let combined = BloomFilter::from_filters(&[BloomFilter::new(&[1], 4), BloomFilter::new(&[4], 4)], 4);
+
+        assert!(combined.possibly_contains(&BloomFilter::new(&[1, 4], 4)));
+        assert!(!combined.possibly_contains(&BloomFilter::new(&[2], 4)));
+    }
+
+    #[test]
+    fn bloom_fill_ratio() {
+        assert_eq!(0f32, BloomFilter::new(&[], 2).fill_ratio());
+        assert_eq!(0.25f32, BloomFilter::new(&Vec::from_iter(0..32), 2).fill_ratio());
+        assert_eq!(1f32, BloomFilter::new(&Vec::from_iter(0..128), 2).fill_ratio());
+    }
+
+    #[test]
+    fn bloom_similarity() {
+        let filter = BloomFilter::new(&[1, 2, 3, 4], 2);
+
+        assert_eq!(1f32, filter.similarity(&filter));
+        assert_eq!(0.5f32, filter.similarity(&BloomFilter::new(&[1, 2], 2)));
+        assert_eq!(0f32, filter.similarity(&BloomFilter::new(&[5, 6], 2)));
+    }
+
     #[test]
     fn bloom_exact_match() {
         let filter = BloomFilter::new(&[4, 1], 4);
@@ -119,6 +191,12 @@ mod tests {
         assert_eq!(0b1000_0000, filter.filter_array[0]);
 
         let filter = BloomFilter::new(&[8], 1);
+        assert_eq!(1 << 8, filter.filter_array[0]);
+
+        let filter = BloomFilter::new(&[63], 1);
+        assert_eq!(1 << 63, filter.filter_array[0]);
+
+        let filter = BloomFilter::new(&[64], 1);
         assert_eq!(0b0000_0001, filter.filter_array[0]);
     }
 
@@ -157,7 +235,19 @@ mod tests {
         assert_eq!(0b0000_0000, filter.filter_array[1]);
 
         let filter = BloomFilter::new(&[8], 2);
+        assert_eq!(1 << 8, filter.filter_array[0]);
+        assert_eq!(0b0000_0000, filter.filter_array[1]);
+
+        let filter = BloomFilter::new(&[63], 2);
+        assert_eq!(1 << 63, filter.filter_array[0]);
+        assert_eq!(0b0000_0000, filter.filter_array[1]);
+
+        let filter = BloomFilter::new(&[64], 2);
         assert_eq!(0b0000_0000, filter.filter_array[0]);
         assert_eq!(0b0000_0001, filter.filter_array[1]);
+
+        let filter = BloomFilter::new(&[128], 2);
+        assert_eq!(0b0000_0001, filter.filter_array[0]);
+        assert_eq!(0b0000_0000, filter.filter_array[1]);
     }
 }
\ No newline at end of file
@@ -0,0 +1,43 @@
+// Case-insensitive comparison for text that isn't plain ASCII. Folding can change
+// the length of a character (e.g. 'İ' lowers to 'i' plus a combining dot, and 'ß'
+// folds to "ss"); see DecodedText for mapping folded text back to the original.
+
+// Appends the case folded form of c. This is Unicode lowercasing, plus the German
+// sharp s folding to "ss" so that "straße" and "STRASSE" match.
+pub fn push_folded(c: char, folded: &mut String) {
+    for_each_folded(c, |folded_char| folded.push(folded_char));
+}
+
+pub fn for_each_folded(c: char, mut on_char: impl FnMut(char)) {
+    match c {
+        'ß' | 'ẞ' => {
+            on_char('s');
+            on_char('s');
+        },
+        _ => c.to_lowercase().for_each(on_char),
+    }
+}
+
+pub fn fold_case(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        push_folded(c, &mut folded);
+    }
+
+    folded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fold_case;
+
+    #[test]
+    fn case_folding_special_cases() {
+        assert_eq!("abc", fold_case("AbC"));
+        assert_eq!("strasse", fold_case("STRAẞE"));
+        assert_eq!("strasse", fold_case("Straße"));
+        assert_eq!("i\u{307}stanbul", fold_case("İSTANBUL"));
+        assert_eq!("ünïcödé", fold_case("ÜNÏCÖDÉ"));
+    }
+}
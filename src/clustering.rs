@@ -0,0 +1,130 @@
+use std::collections::VecDeque;
+
+use crate::batching::batch_items;
+use crate::bloom::BloomFilter;
+
+// How many upcoming candidates to consider when picking the next member of a group.
+// Bounds the cost of similarity clustering to O(items * window) comparisons.
+const SIMILARITY_WINDOW: usize = 32;
+
+// How files (and then nodes) are grouped when building an IndexTree. Grouping similar
+// files keeps the merged bloom filters of the parent nodes sparse, so more of the
+// tree can be pruned during search.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Clustering {
+    // Group in enumeration order.
+    #[default]
+    None,
+
+    // Group files that live near each other in the directory tree.
+    Directory,
+
+    // Group by directory, then greedily regroup neighbors with the most bloom filter
+    // bits in common.
+    Similarity,
+}
+
+impl Clustering {
+    pub fn from_name(name: &str) -> Option<Clustering> {
+        match name {
+            "none" => Some(Clustering::None),
+            "directory" => Some(Clustering::Directory),
+            "similarity" => Some(Clustering::Similarity),
+            _ => None,
+        }
+    }
+}
+
+// Splits items into batch_count batches with the same sizes as batch_items() would
+// produce, so trees stay balanced, but chooses the members of each batch greedily so
+// that their merged bloom filter stays as sparse as possible. Items are expected to
+// already be in a sensible order (e.g. by path), since only nearby items are considered.
+pub fn batch_items_by_similarity<TItem: Clone>(
+    items: &[TItem],
+    batch_count: usize,
+    bloom_filter: impl Fn(&TItem) -> &BloomFilter) -> Vec<Vec<TItem>> {
+
+    let batch_sizes: Vec<usize> = batch_items(items, batch_count)
+        .iter()
+        .map(|batch| batch.len())
+        .collect();
+
+    let mut remaining: VecDeque<&TItem> = items.iter().collect();
+    let mut batches = Vec::with_capacity(batch_sizes.len());
+
+    for batch_size in batch_sizes {
+        let seed = remaining.pop_front().unwrap();
+        let mut merged = bloom_filter(seed).clone();
+        let mut batch = vec![seed.clone()];
+
+        while batch.len() < batch_size {
+            let window = remaining.len().min(SIMILARITY_WINDOW);
+
+            // Pick the candidate that overlaps most with what's in the batch already.
+            // Ties go to the earliest candidate to preserve the existing order.
+            let mut best = 0;
+            let mut best_similarity = -1f32;
+
+            for (i, candidate) in remaining.iter().take(window).enumerate() {
+                let similarity = merged.similarity(bloom_filter(candidate));
+
+                if similarity > best_similarity {
+                    best = i;
+                    best_similarity = similarity;
+                }
+            }
+
+            let chosen = remaining.remove(best).unwrap();
+            merged.merge(bloom_filter(chosen));
+            batch.push(chosen.clone());
+        }
+
+        batches.push(batch);
+    }
+
+    batches
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bloom::BloomFilter;
+
+    use super::batch_items_by_similarity;
+
+    #[test]
+    fn clustering_groups_similar_items() {
+        // Interleave two kinds of items, then check they get grouped by kind.
+        let items: Vec<(char, BloomFilter)> = (0..8)
+            .map(|i| if i % 2 == 0 {
+                ('a', BloomFilter::new(&[1, 2, 3], 4))
+            } else {
+                ('b', BloomFilter::new(&[100, 101, 102], 4))
+            })
+            .collect();
+
+        let batches = batch_items_by_similarity(&items, 2, |item| &item.1);
+
+        assert_eq!(2, batches.len());
+        assert!(batches[0].iter().all(|item| item.0 == 'a'));
+        assert!(batches[1].iter().all(|item| item.0 == 'b'));
+    }
+
+    #[test]
+    fn clustering_keeps_batch_sizes() {
+        let items: Vec<BloomFilter> = (0..10).map(|i| BloomFilter::new(&[i], 4)).collect();
+
+        let sizes: Vec<usize> = batch_items_by_similarity(&items, 4, |item| item)
+            .iter()
+            .map(|batch| batch.len())
+            .collect();
+
+        assert_eq!(vec![3, 3, 2, 2], sizes);
+    }
+
+    #[test]
+    fn clustering_empty() {
+        let items: Vec<BloomFilter> = Vec::new();
+
+        assert!(batch_items_by_similarity(&items, 4, |item| item).is_empty());
+    }
+}
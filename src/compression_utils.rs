@@ -1,8 +1,10 @@
+use crate::case_folding::fold_case;
+
+// Folds case first so that characters which fold to ASCII (e.g. 'ß' to "ss") are
+// indexed the same way the verifier compares them.
 pub fn lowercase_alphanumeric_only(text: &str) -> String {
-    let normalized_text = String::from_iter(text
-        .chars()
-        .filter(|c| c.is_ascii_alphanumeric())
-        .flat_map(|c| c.to_lowercase()));
+    let mut normalized_text = fold_case(text);
+    normalized_text.retain(|c| c.is_ascii_alphanumeric());
 
-    return normalized_text;
+    normalized_text
 }
@@ -0,0 +1,434 @@
+use serde::{Deserialize, Serialize};
+
+use crate::case_folding::push_folded;
+
+// How many leading bytes to look at when guessing whether a file is UTF-16.
+const UTF16_SAMPLE_LENGTH: usize = 4096;
+
+// How many leading bytes have to be valid UTF-8 for a file to be read as UTF-8.
+// Only looking at the start means files can be decoded as they're read.
+pub const DETECTION_LENGTH: usize = 64 * 1024;
+
+// Text encodings files are decoded from. Files with a BOM or that are valid UTF-8 are
+// detected as such; anything else is assumed to be in a default encoding, Latin-1
+// unless configured otherwise.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+
+    #[default]
+    Latin1,
+}
+
+impl Encoding {
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        match name.to_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "utf-16le" | "utf16le" => Some(Encoding::Utf16Le),
+            "utf-16be" | "utf16be" => Some(Encoding::Utf16Be),
+            "latin1" | "latin-1" | "iso-8859-1" => Some(Encoding::Latin1),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+            Encoding::Latin1 => "latin1",
+        }
+    }
+}
+
+// Returns the encoding of 'bytes' and the length of its BOM, if it has one. Only the
+// first DETECTION_LENGTH bytes are looked at.
+pub fn detect_encoding(bytes: &[u8], default_encoding: Encoding) -> (Encoding, usize) {
+    if bytes.starts_with(&[0xef, 0xbb, 0xbf]) {
+        return (Encoding::Utf8, 3);
+    }
+
+    if bytes.starts_with(&[0xff, 0xfe]) {
+        return (Encoding::Utf16Le, 2);
+    }
+
+    if bytes.starts_with(&[0xfe, 0xff]) {
+        return (Encoding::Utf16Be, 2);
+    }
+
+    if let Some(encoding) = guess_utf16(&bytes[..bytes.len().min(UTF16_SAMPLE_LENGTH)]) {
+        return (encoding, 0);
+    }
+
+    let sample = &bytes[..bytes.len().min(DETECTION_LENGTH)];
+
+    match std::str::from_utf8(sample) {
+        Ok(_) => (Encoding::Utf8, 0),
+
+        // The sample may end partway through a character.
+        Err(error) if error.error_len().is_none() && sample.len() < bytes.len() => (Encoding::Utf8, 0),
+        Err(_) => (default_encoding, 0),
+    }
+}
+
+// UTF-16 without a BOM is recognizable when the text is mostly ASCII, since every
+// other byte is then zero.
+fn guess_utf16(sample: &[u8]) -> Option<Encoding> {
+    let units_count = sample.len() / 2;
+
+    if units_count == 0 {
+        return None;
+    }
+
+    let zeros_at = |parity: usize| sample[..units_count * 2]
+        .iter()
+        .skip(parity)
+        .step_by(2)
+        .filter(|b| **b == 0)
+        .count();
+
+    let even_zeros = zeros_at(0);
+    let odd_zeros = zeros_at(1);
+
+    if odd_zeros * 2 > units_count && even_zeros * 10 < units_count {
+        Some(Encoding::Utf16Le)
+    } else if even_zeros * 2 > units_count && odd_zeros * 10 < units_count {
+        Some(Encoding::Utf16Be)
+    } else {
+        None
+    }
+}
+
+// Decodes text that arrives in chunks, e.g. as a file is read, for when offsets
+// don't matter. Characters may be split across chunks.
+pub struct StreamDecoder {
+    encoding: Encoding,
+
+    // The start of a character that was cut off at the end of the last chunk.
+    pending: Vec<u8>,
+}
+
+impl StreamDecoder {
+    pub fn new(encoding: Encoding) -> StreamDecoder {
+        StreamDecoder {
+            encoding,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn decode(&mut self, chunk: &[u8], mut on_char: impl FnMut(char)) {
+        let mut bytes = std::mem::take(&mut self.pending);
+        bytes.extend_from_slice(chunk);
+
+        // Everything up to the last complete character. Latin-1 has no multi-byte
+        // characters.
+        let complete_length = match self.encoding {
+            Encoding::Utf8 => bytes.len() - incomplete_utf8_suffix_length(&bytes),
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let mut complete_length = bytes.len() - bytes.len() % 2;
+
+                // Keep a high surrogate back until its pair arrives.
+                if complete_length >= 2 {
+                    let last = &bytes[complete_length - 2..complete_length];
+                    let last_unit = if self.encoding == Encoding::Utf16Le {
+                        u16::from_le_bytes([last[0], last[1]])
+                    } else {
+                        u16::from_be_bytes([last[0], last[1]])
+                    };
+
+                    if (0xd800..0xdc00).contains(&last_unit) {
+                        complete_length -= 2;
+                    }
+                }
+
+                complete_length
+            },
+            Encoding::Latin1 => bytes.len(),
+        };
+
+        decode(&bytes[..complete_length], self.encoding, |_, c| on_char(c));
+        self.pending = bytes.split_off(complete_length);
+    }
+
+    // Decodes whatever was left incomplete, once there are no more chunks.
+    pub fn finish(&mut self, mut on_char: impl FnMut(char)) {
+        let pending = std::mem::take(&mut self.pending);
+
+        decode(&pending, self.encoding, |_, c| on_char(c));
+    }
+}
+
+// Length of the start of a multi-byte character at the very end of 'bytes', if
+// it's cut off.
+fn incomplete_utf8_suffix_length(bytes: &[u8]) -> usize {
+    for back in 1..=bytes.len().min(3) {
+        let b = bytes[bytes.len() - back];
+
+        // Skip continuation bytes to find the byte the character starts with.
+        if b & 0xc0 == 0x80 {
+            continue;
+        }
+
+        let character_length = match b {
+            0xf0.. => 4,
+            0xe0.. => 3,
+            0xc0.. => 2,
+            _ => 1,
+        };
+
+        return if character_length > back { back } else { 0 };
+    }
+
+    0
+}
+
+// Calls 'on_char' with each character and the offset in 'bytes' that it starts at.
+// Anything that can't be decoded comes out as U+FFFD.
+fn decode(bytes: &[u8], encoding: Encoding, mut on_char: impl FnMut(usize, char)) {
+    match encoding {
+        Encoding::Utf8 => {
+            let mut offset = 0;
+
+            for chunk in bytes.utf8_chunks() {
+                for (i, c) in chunk.valid().char_indices() {
+                    on_char(offset + i, c);
+                }
+
+                offset += chunk.valid().len();
+
+                if !chunk.invalid().is_empty() {
+                    on_char(offset, char::REPLACEMENT_CHARACTER);
+                    offset += chunk.invalid().len();
+                }
+            }
+        },
+        Encoding::Utf16Le | Encoding::Utf16Be => {
+            let units = bytes
+                .chunks_exact(2)
+                .map(|pair| if encoding == Encoding::Utf16Le {
+                    u16::from_le_bytes([pair[0], pair[1]])
+                } else {
+                    u16::from_be_bytes([pair[0], pair[1]])
+                });
+
+            let mut offset = 0;
+
+            for decoded in char::decode_utf16(units) {
+                match decoded {
+                    Ok(c) => {
+                        on_char(offset, c);
+                        offset += c.len_utf16() * 2;
+                    },
+                    Err(_) => {
+                        on_char(offset, char::REPLACEMENT_CHARACTER);
+                        offset += 2;
+                    },
+                }
+            }
+
+            // A truncated trailing unit.
+            if bytes.len() % 2 == 1 {
+                on_char(bytes.len() - 1, char::REPLACEMENT_CHARACTER);
+            }
+        },
+        Encoding::Latin1 => {
+            for (i, b) in bytes.iter().enumerate() {
+                on_char(i, char::from(*b));
+            }
+        },
+    }
+}
+
+// Text decoded to UTF-8, optionally case folded, that remembers where each of its
+// characters came from. Decoding and folding can both change the length of a
+// character, so positions in 'text' only map back to the original through this.
+pub struct DecodedText {
+    pub text: String,
+
+    // For each byte of 'text', where the character it was decoded from starts in the
+    // original bytes. Has one extra entry, the length of the original.
+    original_offsets: Vec<usize>,
+}
+
+impl DecodedText {
+    pub fn new(bytes: &[u8], encoding: Encoding) -> DecodedText {
+        DecodedText::build(bytes, encoding, |c, text| text.push(c))
+    }
+
+    pub fn folded(bytes: &[u8], encoding: Encoding) -> DecodedText {
+        DecodedText::build(bytes, encoding, push_folded)
+    }
+
+    fn build(bytes: &[u8], encoding: Encoding, push: impl Fn(char, &mut String)) -> DecodedText {
+        let mut text = String::with_capacity(bytes.len());
+        let mut original_offsets = Vec::with_capacity(bytes.len() + 1);
+
+        decode(bytes, encoding, |original_offset, c| {
+            push(c, &mut text);
+            original_offsets.resize(text.len(), original_offset);
+        });
+
+        original_offsets.push(bytes.len());
+
+        DecodedText { text, original_offsets }
+    }
+
+    // Maps a range of 'text' back to the original bytes, widened to whole characters
+    // when it starts or ends partway through what a character decoded to.
+    pub fn original_range(&self, start: usize, end: usize) -> (usize, usize) {
+        let original_start = self.original_offsets[start];
+
+        if end == start {
+            return (original_start, original_start);
+        }
+
+        // The range ends where the character holding its last byte ends, which is
+        // wherever the next character starts.
+        let last = self.original_offsets[end - 1];
+        let mut next = end;
+
+        while self.original_offsets[next] == last {
+            next += 1;
+        }
+
+        (original_start, self.original_offsets[next])
+    }
+
+    // The reverse of original_range(): where the character starting at
+    // 'original_offset' starts in 'text'.
+    pub fn decoded_offset(&self, original_offset: usize) -> usize {
+        self.original_offsets.partition_point(|offset| *offset < original_offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect_encoding, DecodedText, Encoding, StreamDecoder, DETECTION_LENGTH};
+
+    fn utf16le(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(|unit| unit.to_le_bytes()).collect()
+    }
+
+    fn utf16be(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(|unit| unit.to_be_bytes()).collect()
+    }
+
+    #[test]
+    fn encoding_detect_boms() {
+        assert_eq!((Encoding::Utf8, 3), detect_encoding(b"\xef\xbb\xbfabc", Encoding::Latin1));
+        assert_eq!((Encoding::Utf16Le, 2), detect_encoding(b"\xff\xfea\x00", Encoding::Latin1));
+        assert_eq!((Encoding::Utf16Be, 2), detect_encoding(b"\xfe\xff\x00a", Encoding::Latin1));
+    }
+
+    #[test]
+    fn encoding_detect_without_bom() {
+        assert_eq!((Encoding::Utf8, 0), detect_encoding("plain and ünïcödé".as_bytes(), Encoding::Latin1));
+        assert_eq!((Encoding::Utf8, 0), detect_encoding(b"", Encoding::Latin1));
+        assert_eq!((Encoding::Utf16Le, 0), detect_encoding(&utf16le("resource string"), Encoding::Latin1));
+        assert_eq!((Encoding::Utf16Be, 0), detect_encoding(&utf16be("resource string"), Encoding::Latin1));
+
+        // Not valid UTF-8, so the configured default applies.
+        assert_eq!((Encoding::Latin1, 0), detect_encoding(b"caf\xe9", Encoding::Latin1));
+        assert_eq!((Encoding::Utf8, 0), detect_encoding(b"caf\xe9", Encoding::Utf8));
+    }
+
+    fn decode_in_chunks(bytes: &[u8], encoding: Encoding, chunk_length: usize) -> String {
+        let mut decoder = StreamDecoder::new(encoding);
+        let mut text = String::new();
+
+        for chunk in bytes.chunks(chunk_length) {
+            decoder.decode(chunk, |c| text.push(c));
+        }
+
+        decoder.finish(|c| text.push(c));
+
+        text
+    }
+
+    #[test]
+    fn encoding_stream_decoder() {
+        let text = "Grüße 日本 🦀!";
+
+        // However the chunks fall, characters come out whole.
+        for chunk_length in 1..8 {
+            assert_eq!(text, decode_in_chunks(text.as_bytes(), Encoding::Utf8, chunk_length));
+            assert_eq!(text, decode_in_chunks(&utf16le(text), Encoding::Utf16Le, chunk_length));
+            assert_eq!(text, decode_in_chunks(&utf16be(text), Encoding::Utf16Be, chunk_length));
+            assert_eq!("café", decode_in_chunks(b"caf\xe9", Encoding::Latin1, chunk_length));
+
+            // Invalid or truncated input still decodes to replacement characters.
+            assert_eq!("a\u{fffd}b\u{fffd}", decode_in_chunks(b"a\xffb\xe6\x97", Encoding::Utf8, chunk_length));
+            assert_eq!("a\u{fffd}", decode_in_chunks(b"a\x00\x3d\xd8", Encoding::Utf16Le, chunk_length));
+        }
+    }
+
+    #[test]
+    fn encoding_detect_from_start() {
+        // Only the start counts, and may end partway through a character.
+        let mut bytes = vec![b'a'; DETECTION_LENGTH - 1];
+        bytes.extend("é".as_bytes());
+        bytes.push(0xe9);
+
+        assert_eq!((Encoding::Utf8, 0), detect_encoding(&bytes, Encoding::Latin1));
+
+        bytes[0] = 0xe9;
+        assert_eq!((Encoding::Latin1, 0), detect_encoding(&bytes, Encoding::Latin1));
+    }
+
+    #[test]
+    fn encoding_maps_folded_offsets() {
+        let folded = DecodedText::folded("aİbßc".as_bytes(), Encoding::Utf8);
+
+        assert_eq!("ai\u{307}bssc", folded.text);
+
+        // Whole characters map exactly.
+        assert_eq!((1, 3), folded.original_range(1, 4));
+        assert_eq!((4, 6), folded.original_range(5, 7));
+        assert_eq!((0, 7), folded.original_range(0, folded.text.len()));
+
+        // Part of a character's folded form maps to the whole character.
+        assert_eq!((1, 3), folded.original_range(1, 2));
+        assert_eq!((1, 3), folded.original_range(2, 4));
+        assert_eq!((4, 6), folded.original_range(6, 7));
+        assert_eq!((3, 6), folded.original_range(4, 6));
+
+        assert_eq!(4, folded.decoded_offset(3));
+        assert_eq!(8, folded.decoded_offset(7));
+    }
+
+    #[test]
+    fn encoding_maps_utf16_and_latin1_offsets() {
+        // 'ü' is 2 bytes of UTF-16, the crab a surrogate pair of 4.
+        let decoded = DecodedText::new(&utf16le("xü🦀y"), Encoding::Utf16Le);
+
+        assert_eq!("xü🦀y", decoded.text);
+        assert_eq!((2, 4), decoded.original_range(1, 3));
+        assert_eq!((4, 8), decoded.original_range(3, 7));
+        assert_eq!((8, 10), decoded.original_range(7, 8));
+        assert_eq!(3, decoded.decoded_offset(4));
+
+        let decoded = DecodedText::folded(b"CAF\xc9!", Encoding::Latin1);
+
+        assert_eq!("café!", decoded.text);
+        assert_eq!((3, 4), decoded.original_range(3, 5));
+        assert_eq!((0, 5), decoded.original_range(0, 6));
+    }
+
+    #[test]
+    fn encoding_invalid_sequences() {
+        let decoded = DecodedText::folded(b"\xffAB\xfe\xfeC", Encoding::Utf8);
+
+        // Each invalid byte gets its own replacement character.
+        assert_eq!("\u{fffd}ab\u{fffd}\u{fffd}c", decoded.text);
+        assert_eq!((1, 3), decoded.original_range(3, 5));
+        assert_eq!((3, 5), decoded.original_range(5, 11));
+        assert_eq!((5, 6), decoded.original_range(11, 12));
+
+        // An unpaired surrogate and a truncated unit.
+        let decoded = DecodedText::new(b"a\x00\x00\xd8b\x00c", Encoding::Utf16Le);
+        assert_eq!("a\u{fffd}b\u{fffd}", decoded.text);
+        assert_eq!((6, 7), decoded.original_range(decoded.text.len() - 3, decoded.text.len()));
+    }
+}
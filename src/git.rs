@@ -0,0 +1,680 @@
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use crate::git_objects::{from_hex, ObjectDatabase, ObjectId, TreeChanges};
+
+// Reads git repositories straight from the .git folder, without needing a git binary.
+
+const INDEX_SIGNATURE: &[u8] = b"DIRC";
+
+// Extension of a split index, whose entries are mostly in a shared index file.
+const SPLIT_INDEX_EXTENSION: &[u8] = b"link";
+
+// Type bits of an index entry's mode.
+const MODE_TYPE_MASK: u32 = 0o170000;
+const MODE_SYMLINK: u32 = 0o120000;
+const MODE_GITLINK: u32 = 0o160000;
+const MODE_DIRECTORY: u32 = 0o040000;
+
+const FLAG_EXTENDED: u16 = 0x4000;
+const EXTENDED_FLAG_SKIP_WORKTREE: u16 = 0x4000;
+
+// ctime, mtime, dev, ino, mode, uid, gid and size, before the object id.
+const ENTRY_STAT_LENGTH: usize = 40;
+
+pub struct Repository {
+    // The checked out tree.
+    pub worktree: PathBuf,
+
+    // Where the index, objects and refs are. Usually worktree/.git, but submodules
+    // and linked worktrees point elsewhere with a '.git' file.
+    pub git_directory: PathBuf,
+}
+
+impl Repository {
+    // The repository 'path' is in, if any, looking up through its parents.
+    pub fn find(path: &str) -> Option<Repository> {
+        let path = std::fs::canonicalize(path).ok()?;
+
+        path.ancestors().find_map(Repository::open)
+    }
+
+    // The repository whose worktree is exactly 'worktree', if any.
+    pub fn open(worktree: &Path) -> Option<Repository> {
+        let dot_git = worktree.join(".git");
+
+        let git_directory = if dot_git.is_dir() {
+            dot_git
+        } else {
+            let contents = std::fs::read_to_string(&dot_git).ok()?;
+            let target = contents.lines().next()?.strip_prefix("gitdir:")?.trim();
+            worktree.join(target)
+        };
+
+        Some(Repository {
+            worktree: worktree.to_path_buf(),
+            git_directory,
+        })
+    }
+
+    // Where objects, refs and config shared by all worktrees are. Linked worktrees
+    // have their own git folder for HEAD and the index, pointing at the main one.
+    pub fn common_directory(&self) -> PathBuf {
+        match std::fs::read_to_string(self.git_directory.join("commondir")) {
+            Ok(common_directory) => self.git_directory.join(common_directory.trim()),
+            Err(_) => self.git_directory.clone(),
+        }
+    }
+
+    pub fn objects(&self) -> Result<ObjectDatabase, Error> {
+        ObjectDatabase::open(&self.common_directory().join("objects"), self.hash_length())
+    }
+
+    // Resolves a full or abbreviated object id, HEAD, or the name of a branch, tag or
+    // remote branch, the way 'git rev-parse' would.
+    pub fn resolve_revision(&self, revision: &str) -> Result<ObjectId, Error> {
+        let hash_length = self.hash_length();
+
+        if revision.len() == hash_length * 2 {
+            if let Some(id) = from_hex(revision) {
+                return Ok(id);
+            }
+        }
+
+        let candidates = [
+            revision.to_string(),
+            format!("refs/{}", revision),
+            format!("refs/tags/{}", revision),
+            format!("refs/heads/{}", revision),
+            format!("refs/remotes/{}", revision),
+            format!("refs/remotes/{}/HEAD", revision),
+        ];
+
+        if let Some(id) = candidates.iter().find_map(|name| self.read_ref(name, 0)) {
+            return Ok(id);
+        }
+
+        if revision.len() >= 4 && revision.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            let mut matches = self.objects()?.find_by_prefix(revision);
+
+            match matches.len() {
+                1 => return Ok(matches.pop().unwrap()),
+                0 => {},
+                _ => return Err(Error::new(ErrorKind::InvalidInput, format!("'{}' is ambiguous", revision))),
+            }
+        }
+
+        Err(Error::new(ErrorKind::NotFound, format!("unknown revision '{}'", revision)))
+    }
+
+    // Files added, modified and deleted between two commits, relative to the root
+    // of the worktree.
+    pub fn changes_between(&self, old: &[u8], new: &[u8]) -> Result<TreeChanges, Error> {
+        self.objects()?.diff(old, new)
+    }
+
+    // Reads a ref, following symbolic refs, from its own file or from packed-refs.
+    fn read_ref(&self, name: &str, depth: usize) -> Option<ObjectId> {
+        // Refs pointing at each other in a loop.
+        if depth > 8 || name.contains("..") {
+            return None;
+        }
+
+        // HEAD and other per-worktree refs are in the worktree's git folder, shared
+        // refs in the common one.
+        let contents = std::fs::read_to_string(self.git_directory.join(name))
+            .or_else(|_| std::fs::read_to_string(self.common_directory().join(name)))
+            .ok()
+            .filter(|_| name.starts_with("refs/") || name.bytes().all(|byte| byte.is_ascii_uppercase() || byte == b'_'));
+
+        if let Some(contents) = contents {
+            let contents = contents.trim();
+
+            return match contents.strip_prefix("ref:") {
+                Some(target) => self.read_ref(target.trim(), depth + 1),
+                None => from_hex(contents.split_whitespace().next()?),
+            };
+        }
+
+        let packed_refs = std::fs::read_to_string(self.common_directory().join("packed-refs")).ok()?;
+
+        packed_refs
+            .lines()
+            .filter(|line| !line.starts_with('#') && !line.starts_with('^'))
+            .find_map(|line| {
+                let (id, ref_name) = line.split_once(' ')?;
+                (ref_name.trim() == name).then(|| from_hex(id)).flatten()
+            })
+    }
+
+    // Where 'path' is within the worktree, with '/' separators. Empty for the root.
+    pub fn relative_path(&self, path: &str) -> Result<String, Error> {
+        let canonical = std::fs::canonicalize(path)?;
+
+        let relative = canonical
+            .strip_prefix(&self.worktree)
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, format!("'{}' isn't in {}", path, self.worktree.display())))?;
+
+        Ok(relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().to_string())
+            .collect::<Vec<String>>()
+            .join("/"))
+    }
+
+    // Object ids are SHA-1 unless the repository was created with SHA-256.
+    pub fn hash_length(&self) -> usize {
+        let config = std::fs::read_to_string(self.common_directory().join("config")).unwrap_or_default();
+
+        let sha256 = config
+            .lines()
+            .map(|line| line.trim().to_lowercase().replace(' ', ""))
+            .any(|line| line == "objectformat=sha256");
+
+        if sha256 { 32 } else { 20 }
+    }
+
+    // Paths of the files in the index, relative to the worktree and with '/'
+    // separators, like 'git ls-files' lists them. Files in checked out submodules are
+    // included if 'submodules' is set, and symlinks if 'symlinks' is.
+    pub fn tracked_files(&self, submodules: bool, symlinks: bool) -> Result<Vec<String>, Error> {
+        // Repositories nothing was ever added to have no index yet.
+        let entries = match read_index(&self.git_directory.join("index"), self.hash_length()) {
+            Err(error) if error.kind() == ErrorKind::NotFound => Vec::new(),
+            entries => entries?,
+        };
+
+        let mut files = Vec::new();
+
+        for entry in entries {
+            match entry.mode & MODE_TYPE_MASK {
+                MODE_GITLINK if submodules => {
+                    // Submodules that aren't checked out have nothing to index.
+                    if let Some(submodule) = Repository::open(&self.worktree.join(&entry.path)) {
+                        files.extend(submodule
+                            .tracked_files(submodules, symlinks)?
+                            .into_iter()
+                            .map(|file| format!("{}/{}", entry.path, file)));
+                    }
+                },
+                MODE_GITLINK | MODE_DIRECTORY => {},
+                MODE_SYMLINK if !symlinks => {},
+                _ if entry.skip_worktree => {},
+                _ => files.push(entry.path),
+            }
+        }
+
+        Ok(files)
+    }
+}
+
+// Files tracked by the repository 'path' is in that are under 'path', as paths
+// starting with 'path', the way walking the folder would find them.
+pub fn tracked_files(path: &str, submodules: bool, symlinks: bool) -> Result<Vec<String>, Error> {
+    let repository = Repository::find(path)
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("'{}' isn't in a git repository", path)))?;
+
+    let prefix = repository.relative_path(path)?;
+
+    let files = repository
+        .tracked_files(submodules, symlinks)?
+        .into_iter()
+        .filter_map(|file| strip_path_prefix(&file, &prefix))
+        .map(|file| Path::new(path).join(file).to_string_lossy().to_string())
+        .collect();
+
+    Ok(files)
+}
+
+// The files of a commit under a folder, read straight from the object database, e.g. to
+// index or search a branch without checking it out.
+pub struct CommitFiles {
+    objects: ObjectDatabase,
+
+    // Blob ids by path, starting with the folder, the way walking it would find them.
+    blobs: BTreeMap<String, ObjectId>,
+}
+
+impl CommitFiles {
+    // Paths of the files, in order.
+    pub fn paths(&self) -> impl Iterator<Item = &String> {
+        self.blobs.keys()
+    }
+
+    pub fn read(&self, path: &str) -> Result<Vec<u8>, Error> {
+        let id = self.blobs
+            .get(path)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("'{}' isn't in the commit", path)))?;
+
+        Ok(self.objects.read(id)?.data)
+    }
+}
+
+// Files of 'commit' that are under 'path', in the repository 'path' is in. Submodules
+// and symlinks are left out.
+pub fn commit_files(path: &str, commit: &[u8]) -> Result<CommitFiles, Error> {
+    let repository = Repository::find(path)
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("'{}' isn't in a git repository", path)))?;
+
+    let prefix = repository.relative_path(path)?;
+    let objects = repository.objects()?;
+
+    let blobs = objects
+        .files_in(commit)?
+        .into_iter()
+        .filter_map(|(file, id)| Some((strip_path_prefix(&file, &prefix)?, id)))
+        .map(|(file, id)| (Path::new(path).join(file).to_string_lossy().to_string(), id))
+        .collect();
+
+    Ok(CommitFiles { objects, blobs })
+}
+
+// 'path' relative to the folder 'prefix', if it's in it. Both are relative to the
+// root of the worktree, with '/' separators.
+pub fn strip_path_prefix(path: &str, prefix: &str) -> Option<String> {
+    if prefix.is_empty() {
+        return Some(path.to_string());
+    }
+
+    path.strip_prefix(prefix)?.strip_prefix('/').map(String::from)
+}
+
+struct IndexEntry {
+    path: String,
+    mode: u32,
+
+    // Left out of a sparse checkout, so not on disk.
+    skip_worktree: bool,
+}
+
+// Reads the entries of a version 2, 3 or 4 index file. Entries for the stages of a
+// conflicted file are merged into one.
+fn read_index(index_path: &Path, hash_length: usize) -> Result<Vec<IndexEntry>, Error> {
+    let bytes = std::fs::read(index_path)?;
+
+    if bytes.len() < 12 || &bytes[..4] != INDEX_SIGNATURE {
+        return Err(invalid_index("bad signature"));
+    }
+
+    let version = read_u32(&bytes, 4)?;
+    if !(2..=4).contains(&version) {
+        return Err(invalid_index(&format!("unsupported version {}", version)));
+    }
+
+    let entries_count = read_u32(&bytes, 8)? as usize;
+
+    let mut entries: Vec<IndexEntry> = Vec::with_capacity(entries_count);
+    let mut position = 12;
+    let mut previous_path: Vec<u8> = Vec::new();
+
+    for _ in 0..entries_count {
+        let start = position;
+        let mode = read_u32(&bytes, start + 24)?;
+        let flags = read_u16(&bytes, start + ENTRY_STAT_LENGTH + hash_length)?;
+        position += ENTRY_STAT_LENGTH + hash_length + 2;
+
+        let mut skip_worktree = false;
+
+        if flags & FLAG_EXTENDED != 0 {
+            skip_worktree = read_u16(&bytes, position)? & EXTENDED_FLAG_SKIP_WORKTREE != 0;
+            position += 2;
+        }
+
+        let path = if version == 4 {
+            // Paths are stored as how much to drop from the end of the previous path,
+            // then what to append to it.
+            let (drop_length, varint_length) = read_offset_varint(&bytes, position)?;
+            position += varint_length;
+
+            let end = find_nul(&bytes, position)?;
+            let keep_length = previous_path
+                .len()
+                .checked_sub(drop_length)
+                .ok_or_else(|| invalid_index("bad path compression"))?;
+
+            let mut path = previous_path[..keep_length].to_vec();
+            path.extend_from_slice(&bytes[position..end]);
+            position = end + 1;
+            path
+        } else {
+            // Entries are padded with one to eight NULs to a multiple of eight bytes.
+            let end = find_nul(&bytes, position)?;
+            let path = bytes[position..end].to_vec();
+            position = start + ((end - start + 8) & !7);
+            path
+        };
+
+        let entry_path = String::from_utf8_lossy(&path).to_string();
+        previous_path = path;
+
+        if entries.last().is_some_and(|last| last.path == entry_path) {
+            continue;
+        }
+
+        entries.push(IndexEntry {
+            path: entry_path,
+            mode,
+            skip_worktree,
+        });
+    }
+
+    // Extensions follow the entries, up to the checksum. A split index only has the
+    // entries that changed since the shared index was written, so it can't be used
+    // on its own.
+    let extensions_end = bytes.len().saturating_sub(hash_length);
+
+    while position + 8 <= extensions_end {
+        if &bytes[position..position + 4] == SPLIT_INDEX_EXTENSION {
+            return Err(invalid_index("split indexes aren't supported"));
+        }
+
+        position += 8 + read_u32(&bytes, position + 4)? as usize;
+    }
+
+    Ok(entries)
+}
+
+fn invalid_index(reason: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("invalid git index: {}", reason))
+}
+
+fn read_u32(bytes: &[u8], position: usize) -> Result<u32, Error> {
+    bytes
+        .get(position..position + 4)
+        .map(|slice| u32::from_be_bytes(slice.try_into().unwrap()))
+        .ok_or_else(|| invalid_index("truncated"))
+}
+
+fn read_u16(bytes: &[u8], position: usize) -> Result<u16, Error> {
+    bytes
+        .get(position..position + 2)
+        .map(|slice| u16::from_be_bytes(slice.try_into().unwrap()))
+        .ok_or_else(|| invalid_index("truncated"))
+}
+
+fn find_nul(bytes: &[u8], position: usize) -> Result<usize, Error> {
+    bytes
+        .get(position..)
+        .and_then(|rest| rest.iter().position(|byte| *byte == 0))
+        .map(|offset| position + offset)
+        .ok_or_else(|| invalid_index("truncated"))
+}
+
+// Git's variable length integers: seven bits per byte, most significant first, with
+// one added for every continuation byte so that each value has a single encoding.
+// Returns the value and how many bytes it took.
+fn read_offset_varint(bytes: &[u8], position: usize) -> Result<(usize, usize), Error> {
+    let mut length = 0;
+    let mut value = 0usize;
+
+    loop {
+        let byte = *bytes.get(position + length).ok_or_else(|| invalid_index("truncated"))?;
+        length += 1;
+
+        value = (value << 7) | (byte & 127) as usize;
+
+        if byte & 128 == 0 {
+            return Ok((value, length));
+        }
+
+        value += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use uuid::Uuid;
+
+    use super::{commit_files, read_offset_varint, tracked_files, Repository};
+
+    struct TestEntry<'a> {
+        path: &'a str,
+        mode: u32,
+        stage: u16,
+        skip_worktree: bool,
+    }
+
+    fn entry(path: &str, mode: u32) -> TestEntry<'_> {
+        TestEntry { path, mode, stage: 0, skip_worktree: false }
+    }
+
+    // Writes an index file the way git would.
+    fn index_bytes(version: u32, entries: &[TestEntry]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"DIRC");
+        bytes.extend_from_slice(&version.to_be_bytes());
+        bytes.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+
+        let mut previous_path = "";
+
+        for entry in entries {
+            let start = bytes.len();
+
+            bytes.extend_from_slice(&[0; 24]);
+            bytes.extend_from_slice(&entry.mode.to_be_bytes());
+            bytes.extend_from_slice(&[0; 12]);
+            bytes.extend_from_slice(&[0xab; 20]);
+
+            let extended = entry.skip_worktree;
+            let flags = (entry.path.len().min(0xfff) as u16) | (entry.stage << 12) | if extended { 0x4000 } else { 0 };
+            bytes.extend_from_slice(&flags.to_be_bytes());
+
+            if extended {
+                bytes.extend_from_slice(&0x4000u16.to_be_bytes());
+            }
+
+            if version == 4 {
+                let common = previous_path
+                    .bytes()
+                    .zip(entry.path.bytes())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+
+                // Only single byte varints are needed for the paths used here.
+                bytes.push((previous_path.len() - common) as u8);
+                bytes.extend_from_slice(&entry.path.as_bytes()[common..]);
+                bytes.push(0);
+            } else {
+                bytes.extend_from_slice(entry.path.as_bytes());
+                let length = ((bytes.len() - start) + 8) & !7;
+                bytes.resize(start + length, 0);
+            }
+
+            previous_path = entry.path;
+        }
+
+        // Extensions would go here, before the checksum.
+        bytes.extend_from_slice(&[0; 20]);
+        bytes
+    }
+
+    fn test_repository(index: &[u8]) -> String {
+        let root = format!("{}/rust-indexer-git-{}", std::env::temp_dir().display(), Uuid::new_v4());
+        std::fs::create_dir_all(format!("{}/.git", root)).unwrap();
+        std::fs::write(format!("{}/.git/index", root), index).unwrap();
+        root
+    }
+
+    fn relative_files(root: &str, files: Vec<String>) -> Vec<String> {
+        files
+            .into_iter()
+            .map(|file| file.strip_prefix(root).unwrap().trim_start_matches('/').to_string())
+            .collect()
+    }
+
+    #[test]
+    fn git_index_versions() {
+        let entries = [
+            entry("Cargo.toml", 0o100644),
+            entry("src/lib.rs", 0o100644),
+            entry("src/main.rs", 0o100755),
+            entry("src/very/long/path/to/a/module_with_a_long_name.rs", 0o100644),
+        ];
+
+        for version in [2, 3, 4] {
+            let root = test_repository(&index_bytes(version, &entries));
+
+            let files = relative_files(&root, tracked_files(&root, false, false).unwrap());
+            assert_eq!(vec!["Cargo.toml", "src/lib.rs", "src/main.rs", "src/very/long/path/to/a/module_with_a_long_name.rs"], files, "version {}", version);
+
+            // Indexing a subfolder only gets the files under it.
+            std::fs::create_dir_all(format!("{}/src", root)).unwrap();
+            let files = relative_files(&format!("{}/src", root), tracked_files(&format!("{}/src", root), false, false).unwrap());
+            assert_eq!(vec!["lib.rs", "main.rs", "very/long/path/to/a/module_with_a_long_name.rs"], files);
+
+            std::fs::remove_dir_all(&root).unwrap();
+        }
+    }
+
+    #[test]
+    fn git_index_entry_kinds() {
+        let conflicted = |stage| TestEntry { path: "conflicted.rs", mode: 0o100644, stage, skip_worktree: false };
+
+        let entries = [
+            conflicted(1),
+            conflicted(2),
+            conflicted(3),
+            entry("link", 0o120000),
+            TestEntry { path: "sparse.rs", mode: 0o100644, stage: 0, skip_worktree: true },
+            entry("src/main.rs", 0o100644),
+            entry("vendor/lib", 0o160000),
+        ];
+
+        let root = test_repository(&index_bytes(3, &entries));
+        std::fs::create_dir_all(format!("{}/src", root)).unwrap();
+
+        // Conflicts are listed once, the sparse file and the submodule that isn't
+        // checked out are left out, and symlinks only when asked for.
+        let files = relative_files(&root, tracked_files(&root, true, false).unwrap());
+        assert_eq!(vec!["conflicted.rs", "src/main.rs"], files);
+
+        let files = relative_files(&root, tracked_files(&root, true, true).unwrap());
+        assert_eq!(vec!["conflicted.rs", "link", "src/main.rs"], files);
+
+        let files = relative_files(&format!("{}/src", root), tracked_files(&format!("{}/src", root), false, false).unwrap());
+        assert_eq!(vec!["main.rs"], files);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn git_index_submodules() {
+        let root = test_repository(&index_bytes(2, &[entry("README.md", 0o100644), entry("vendor/lib", 0o160000)]));
+
+        // Submodules keep their git folder inside the parent's, and point at it from a
+        // '.git' file.
+        std::fs::create_dir_all(format!("{}/.git/modules/lib", root)).unwrap();
+        std::fs::write(format!("{}/.git/modules/lib/index", root), index_bytes(4, &[entry("lib.rs", 0o100644)])).unwrap();
+        std::fs::create_dir_all(format!("{}/vendor/lib", root)).unwrap();
+        std::fs::write(format!("{}/vendor/lib/.git", root), "gitdir: ../../.git/modules/lib\n").unwrap();
+
+        assert_eq!(vec!["README.md"], relative_files(&root, tracked_files(&root, false, false).unwrap()));
+        assert_eq!(vec!["README.md", "vendor/lib/lib.rs"], relative_files(&root, tracked_files(&root, true, false).unwrap()));
+
+        let submodule = Repository::find(&format!("{}/vendor/lib", root)).unwrap();
+        assert_eq!(vec!["lib.rs"], submodule.tracked_files(false, false).unwrap());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn git_index_invalid() {
+        let root = test_repository(b"not an index");
+        assert!(tracked_files(&root, false, false).is_err());
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let mut truncated = index_bytes(2, &[entry("src/main.rs", 0o100644)]);
+        truncated.truncate(40);
+        let root = test_repository(&truncated);
+        assert!(tracked_files(&root, false, false).is_err());
+        std::fs::remove_dir_all(&root).unwrap();
+
+        // A split index, with the link extension before the checksum.
+        let mut split = index_bytes(2, &[entry("src/main.rs", 0o100644)]);
+        let checksum_start = split.len() - 20;
+        split.splice(checksum_start..checksum_start, [b"link".as_slice(), &20u32.to_be_bytes(), &[0xcd; 20]].concat());
+        let root = test_repository(&split);
+        assert!(tracked_files(&root, false, false).unwrap_err().to_string().contains("split"));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn git_index_missing() {
+        // Nothing was ever added, so there's no index yet.
+        let root = test_repository(b"");
+        std::fs::remove_file(format!("{}/.git/index", root)).unwrap();
+
+        assert!(tracked_files(&root, false, false).unwrap().is_empty());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    // Writes a loose object under a made up id, since nothing checks ids against contents.
+    fn write_object(root: &str, id: u8, kind: &str, data: &[u8]) -> Vec<u8> {
+        let id = vec![id; 20];
+        let hex: String = id.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(format!("{} {}\0", kind, data.len()).as_bytes()).unwrap();
+        encoder.write_all(data).unwrap();
+
+        std::fs::create_dir_all(format!("{}/.git/objects/{}", root, &hex[..2])).unwrap();
+        std::fs::write(format!("{}/.git/objects/{}/{}", root, &hex[..2], &hex[2..]), encoder.finish().unwrap()).unwrap();
+
+        id
+    }
+
+    fn tree_entry(mode: &str, name: &str, id: &[u8]) -> Vec<u8> {
+        [format!("{} {}\0", mode, name).as_bytes(), id].concat()
+    }
+
+    #[test]
+    fn git_commit_files() {
+        let root = test_repository(&index_bytes(2, &[]));
+
+        let readme = write_object(&root, 0x11, "blob", b"# Readme");
+        let main = write_object(&root, 0x12, "blob", b"fn main() {}");
+        let src = write_object(&root, 0x21, "tree", &tree_entry("100644", "main.rs", &main));
+
+        let tree = [
+            tree_entry("100644", "README.md", &readme),
+            tree_entry("120000", "link", &readme),
+            tree_entry("40000", "src", &src),
+            tree_entry("160000", "vendor", &[0x99; 20]),
+        ].concat();
+        let tree = write_object(&root, 0x31, "tree", &tree);
+
+        let commit = format!("tree {}\nauthor A <a@b> 0 +0000\n\nMessage\n", tree.iter().map(|byte| format!("{:02x}", byte)).collect::<String>());
+        let commit = write_object(&root, 0x41, "commit", commit.as_bytes());
+
+        // Symlinks and submodules are left out.
+        let files = commit_files(&root, &commit).unwrap();
+        let paths: Vec<String> = files.paths().cloned().collect();
+        assert_eq!(relative_files(&root, paths), vec!["README.md", "src/main.rs"]);
+        assert_eq!(b"fn main() {}".to_vec(), files.read(&format!("{}/src/main.rs", root)).unwrap());
+        assert!(files.read(&format!("{}/link", root)).is_err());
+
+        // Only files under a subfolder, when given one.
+        std::fs::create_dir_all(format!("{}/src", root)).unwrap();
+        let files = commit_files(&format!("{}/src", root), &commit).unwrap();
+        assert_eq!(relative_files(&format!("{}/src", root), files.paths().cloned().collect()), vec!["main.rs"]);
+
+        // Blobs aren't commits.
+        assert!(commit_files(&root, &readme).is_err());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn git_offset_varint() {
+        assert_eq!((0, 1), read_offset_varint(&[0], 0).unwrap());
+        assert_eq!((127, 1), read_offset_varint(&[127], 0).unwrap());
+        assert_eq!((128, 2), read_offset_varint(&[0x80, 0], 0).unwrap());
+        assert_eq!((16511, 2), read_offset_varint(&[0xff, 0x7f], 0).unwrap());
+        assert!(read_offset_varint(&[0x80], 0).is_err());
+    }
+}
@@ -0,0 +1,714 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use flate2::read::ZlibDecoder;
+
+// Reads objects from a repository's object database: loose objects, and objects in
+// pack files, deltified or not.
+
+const PACK_INDEX_SIGNATURE: &[u8] = b"\xfftOc";
+
+// Object types in pack files.
+const PACK_COMMIT: u8 = 1;
+const PACK_TREE: u8 = 2;
+const PACK_BLOB: u8 = 3;
+const PACK_TAG: u8 = 4;
+const PACK_OFS_DELTA: u8 = 6;
+const PACK_REF_DELTA: u8 = 7;
+
+// Bytes of delta bases kept around, as the files of a commit are often deltas against
+// the same few bases.
+const BASE_CACHE_SIZE: usize = 16 * 1024 * 1024;
+
+// Tree entry modes.
+const MODE_TREE: u32 = 0o040000;
+const MODE_SYMLINK: u32 = 0o120000;
+const MODE_GITLINK: u32 = 0o160000;
+
+// Raw object id bytes, 20 for SHA-1 and 32 for SHA-256.
+pub type ObjectId = Vec<u8>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ObjectKind {
+    Commit,
+    Tree,
+    Blob,
+    Tag,
+}
+
+pub struct Object {
+    pub kind: ObjectKind,
+    pub data: Vec<u8>,
+}
+
+// Paths that differ between two trees, relative to the root of the repository.
+#[derive(Debug, Default, PartialEq)]
+pub struct TreeChanges {
+    pub added: Vec<String>,
+    pub modified: Vec<String>,
+    pub deleted: Vec<String>,
+}
+
+struct TreeEntry {
+    mode: u32,
+    id: ObjectId,
+}
+
+struct Pack {
+    pack_path: PathBuf,
+
+    // Sorted object ids, back to back.
+    ids: Vec<u8>,
+    offsets: Vec<u64>,
+}
+
+pub struct ObjectDatabase {
+    objects_directory: PathBuf,
+    packs: Vec<Pack>,
+    hash_length: usize,
+    base_cache: Mutex<BaseCache>,
+}
+
+// Delta bases by pack and offset, the oldest dropped first once they take more than
+// BASE_CACHE_SIZE bytes.
+#[derive(Default)]
+struct BaseCache {
+    objects: HashMap<(usize, u64), Arc<Object>>,
+    order: VecDeque<(usize, u64)>,
+    size: usize,
+}
+
+impl ObjectDatabase {
+    pub fn open(objects_directory: &Path, hash_length: usize) -> Result<ObjectDatabase, Error> {
+        let mut packs = Vec::new();
+
+        if let Ok(entries) = std::fs::read_dir(objects_directory.join("pack")) {
+            for entry in entries.flatten() {
+                let index_path = entry.path();
+
+                if index_path.extension().is_some_and(|extension| extension == "idx") {
+                    packs.push(read_pack_index(&index_path, hash_length)?);
+                }
+            }
+        }
+
+        Ok(ObjectDatabase {
+            objects_directory: objects_directory.to_path_buf(),
+            packs,
+            hash_length,
+            base_cache: Mutex::new(BaseCache::default()),
+        })
+    }
+
+    pub fn read(&self, id: &[u8]) -> Result<Object, Error> {
+        let hex = to_hex(id);
+        let loose_path = self.objects_directory.join(&hex[..2]).join(&hex[2..]);
+
+        if let Ok(file) = File::open(&loose_path) {
+            return read_loose_object(file);
+        }
+
+        match self.find_packed(id) {
+            Some((pack, offset)) => self.read_packed(pack, offset),
+            None => Err(Error::new(ErrorKind::NotFound, format!("object {} not found", hex))),
+        }
+    }
+
+    // The pack an object is in and its offset there.
+    fn find_packed(&self, id: &[u8]) -> Option<(usize, u64)> {
+        self.packs
+            .iter()
+            .enumerate()
+            .find_map(|(i, pack)| Some((i, pack.offsets[pack.find(id, self.hash_length)?])))
+    }
+
+    // Ids of the objects whose hex form starts with 'prefix'.
+    pub fn find_by_prefix(&self, prefix: &str) -> Vec<ObjectId> {
+        let prefix = prefix.to_lowercase();
+        let mut found = Vec::new();
+
+        if prefix.len() >= 2 {
+            if let Ok(entries) = std::fs::read_dir(self.objects_directory.join(&prefix[..2])) {
+                for entry in entries.flatten() {
+                    let hex = format!("{}{}", &prefix[..2], entry.file_name().to_string_lossy());
+
+                    if hex.starts_with(&prefix) {
+                        found.extend(from_hex(&hex));
+                    }
+                }
+            }
+        }
+
+        for pack in &self.packs {
+            for id in pack.ids.chunks(self.hash_length) {
+                if to_hex(id).starts_with(&prefix) {
+                    found.push(id.to_vec());
+                }
+            }
+        }
+
+        found.sort();
+        found.dedup();
+        found
+    }
+
+    // The tree of a commit, following tags to what they point at.
+    pub fn tree_of(&self, id: &[u8]) -> Result<ObjectId, Error> {
+        let object = self.read(id)?;
+
+        match object.kind {
+            ObjectKind::Tree => Ok(id.to_vec()),
+            ObjectKind::Commit => header_id(&object.data, "tree"),
+            ObjectKind::Tag => self.tree_of(&header_id(&object.data, "object")?),
+            ObjectKind::Blob => Err(Error::new(ErrorKind::InvalidInput, format!("{} is a blob, not a commit", to_hex(id)))),
+        }
+    }
+
+    // Files added, modified and deleted between two commits or trees. Submodules
+    // are left out.
+    pub fn diff(&self, old: &[u8], new: &[u8]) -> Result<TreeChanges, Error> {
+        let mut changes = TreeChanges::default();
+
+        let old_tree = self.tree_of(old)?;
+        let new_tree = self.tree_of(new)?;
+        self.diff_trees(Some(&old_tree), Some(&new_tree), "", &mut changes)?;
+
+        Ok(changes)
+    }
+
+    // Every file in a commit or tree by path, relative to the root of the repository.
+    // Submodules and symlinks are left out, as there's nothing to read for them.
+    pub fn files_in(&self, id: &[u8]) -> Result<BTreeMap<String, ObjectId>, Error> {
+        let mut files = BTreeMap::new();
+
+        let tree = self.tree_of(id)?;
+        self.collect_files(&tree, "", &mut files)?;
+
+        Ok(files)
+    }
+
+    fn collect_files(&self, tree: &[u8], prefix: &str, files: &mut BTreeMap<String, ObjectId>) -> Result<(), Error> {
+        for (name, entry) in self.read_tree(tree)? {
+            let path = format!("{}{}", prefix, name);
+
+            match entry.mode {
+                MODE_TREE => self.collect_files(&entry.id, &format!("{}/", path), files)?,
+                MODE_GITLINK | MODE_SYMLINK => {},
+                _ => {
+                    files.insert(path, entry.id);
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    fn diff_trees(&self, old: Option<&[u8]>, new: Option<&[u8]>, prefix: &str, changes: &mut TreeChanges) -> Result<(), Error> {
+        if old == new {
+            return Ok(());
+        }
+
+        let old_entries = match old {
+            Some(id) => self.read_tree(id)?,
+            None => BTreeMap::new(),
+        };
+
+        let new_entries = match new {
+            Some(id) => self.read_tree(id)?,
+            None => BTreeMap::new(),
+        };
+
+        let mut names: Vec<&String> = old_entries.keys().chain(new_entries.keys()).collect();
+        names.sort();
+        names.dedup();
+
+        for name in names {
+            let path = format!("{}{}", prefix, name);
+            let old_entry = old_entries.get(name).filter(|entry| entry.mode != MODE_GITLINK);
+            let new_entry = new_entries.get(name).filter(|entry| entry.mode != MODE_GITLINK);
+
+            let is_tree = |entry: Option<&TreeEntry>| entry.is_some_and(|entry| entry.mode == MODE_TREE);
+            let old_tree = old_entry.filter(|entry| entry.mode == MODE_TREE).map(|entry| entry.id.as_slice());
+            let new_tree = new_entry.filter(|entry| entry.mode == MODE_TREE).map(|entry| entry.id.as_slice());
+
+            // A folder on either side is compared file by file.
+            if old_tree.is_some() || new_tree.is_some() {
+                self.diff_trees(old_tree, new_tree, &format!("{}/", path), changes)?;
+            }
+
+            let old_file = old_entry.filter(|_| !is_tree(old_entry));
+            let new_file = new_entry.filter(|_| !is_tree(new_entry));
+
+            match (old_file, new_file) {
+                (Some(old_file), Some(new_file)) => {
+                    if old_file.id != new_file.id || old_file.mode != new_file.mode {
+                        changes.modified.push(path);
+                    }
+                },
+                (None, Some(_)) => changes.added.push(path),
+                (Some(_), None) => changes.deleted.push(path),
+                (None, None) => {},
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_tree(&self, id: &[u8]) -> Result<BTreeMap<String, TreeEntry>, Error> {
+        let object = self.read(id)?;
+        if object.kind != ObjectKind::Tree {
+            return Err(invalid_object("expected a tree"));
+        }
+
+        // Each entry is '<octal mode> <name>\0<id>'.
+        let data = &object.data;
+        let mut entries = BTreeMap::new();
+        let mut position = 0;
+
+        while position < data.len() {
+            let space = find_byte(data, position, b' ')?;
+            let nul = find_byte(data, space + 1, 0)?;
+
+            let mode = u32::from_str_radix(&String::from_utf8_lossy(&data[position..space]), 8)
+                .map_err(|_| invalid_object("bad tree entry mode"))?;
+            let name = String::from_utf8_lossy(&data[space + 1..nul]).to_string();
+            let id = data
+                .get(nul + 1..nul + 1 + self.hash_length)
+                .ok_or_else(|| invalid_object("truncated tree"))?
+                .to_vec();
+
+            entries.insert(name, TreeEntry { mode, id });
+            position = nul + 1 + self.hash_length;
+        }
+
+        Ok(entries)
+    }
+
+    fn read_packed(&self, pack: usize, offset: u64) -> Result<Object, Error> {
+        let mut reader = BufReader::new(File::open(&self.packs[pack].pack_path)?);
+        reader.seek(SeekFrom::Start(offset))?;
+
+        // Type and inflated size: three bits of type and four of size, then seven
+        // more bits of size per continuation byte, least significant first.
+        let mut byte = read_byte(&mut reader)?;
+        let pack_type = (byte >> 4) & 7;
+        let mut size = (byte & 15) as usize;
+        let mut shift = 4;
+
+        while byte & 0x80 != 0 {
+            byte = read_byte(&mut reader)?;
+            size |= ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+        }
+
+        let kind = match pack_type {
+            PACK_COMMIT => ObjectKind::Commit,
+            PACK_TREE => ObjectKind::Tree,
+            PACK_BLOB => ObjectKind::Blob,
+            PACK_TAG => ObjectKind::Tag,
+            PACK_OFS_DELTA => {
+                let base_distance = read_offset_varint(&mut reader)?;
+                let delta = inflate(reader, size)?;
+                let base_offset = offset
+                    .checked_sub(base_distance)
+                    .ok_or_else(|| invalid_object("bad delta base offset"))?;
+
+                let base = self.read_base(pack, base_offset)?;
+                return Ok(Object { kind: base.kind, data: apply_delta(&base.data, &delta)? });
+            },
+            PACK_REF_DELTA => {
+                let mut base_id = vec![0; self.hash_length];
+                reader.read_exact(&mut base_id)?;
+                let delta = inflate(reader, size)?;
+
+                let base = match self.find_packed(&base_id) {
+                    Some((base_pack, base_offset)) => self.read_base(base_pack, base_offset)?,
+                    None => Arc::new(self.read(&base_id)?),
+                };
+                return Ok(Object { kind: base.kind, data: apply_delta(&base.data, &delta)? });
+            },
+            _ => return Err(invalid_object(&format!("unknown pack object type {}", pack_type))),
+        };
+
+        Ok(Object { kind, data: inflate(reader, size)? })
+    }
+
+    fn read_base(&self, pack: usize, offset: u64) -> Result<Arc<Object>, Error> {
+        if let Some(base) = self.base_cache.lock().unwrap().objects.get(&(pack, offset)) {
+            return Ok(base.clone());
+        }
+
+        let base = Arc::new(self.read_packed(pack, offset)?);
+        self.base_cache.lock().unwrap().insert((pack, offset), base.clone());
+
+        Ok(base)
+    }
+}
+
+impl BaseCache {
+    fn insert(&mut self, key: (usize, u64), object: Arc<Object>) {
+        if object.data.len() > BASE_CACHE_SIZE || self.objects.contains_key(&key) {
+            return;
+        }
+
+        while self.size + object.data.len() > BASE_CACHE_SIZE {
+            let oldest = self.order.pop_front().unwrap();
+            self.size -= self.objects.remove(&oldest).unwrap().data.len();
+        }
+
+        self.size += object.data.len();
+        self.order.push_back(key);
+        self.objects.insert(key, object);
+    }
+}
+
+impl Pack {
+    fn find(&self, id: &[u8], hash_length: usize) -> Option<usize> {
+        let count = self.offsets.len();
+        let (mut low, mut high) = (0, count);
+
+        while low < high {
+            let middle = (low + high) / 2;
+            let middle_id = &self.ids[middle * hash_length..(middle + 1) * hash_length];
+
+            match middle_id.cmp(id) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => return Some(middle),
+            }
+        }
+
+        None
+    }
+}
+
+// Reads a version 2 pack index: a fan-out table, the sorted ids, their CRCs and their
+// offsets into the pack, with offsets past 2 GB in a table of their own.
+fn read_pack_index(index_path: &Path, hash_length: usize) -> Result<Pack, Error> {
+    let bytes = std::fs::read(index_path)?;
+
+    if bytes.len() < 8 + 256 * 4 || &bytes[..4] != PACK_INDEX_SIGNATURE || read_u32(&bytes, 4)? != 2 {
+        return Err(invalid_object("unsupported pack index"));
+    }
+
+    let count = read_u32(&bytes, 8 + 255 * 4)? as usize;
+    let ids_start = 8 + 256 * 4;
+    let offsets_start = ids_start + count * hash_length + count * 4;
+    let large_offsets_start = offsets_start + count * 4;
+
+    let ids = bytes
+        .get(ids_start..ids_start + count * hash_length)
+        .ok_or_else(|| invalid_object("truncated pack index"))?
+        .to_vec();
+
+    let mut offsets = Vec::with_capacity(count);
+
+    for i in 0..count {
+        let offset = read_u32(&bytes, offsets_start + i * 4)?;
+
+        if offset & 0x8000_0000 == 0 {
+            offsets.push(offset as u64);
+        } else {
+            let position = large_offsets_start + (offset & 0x7fff_ffff) as usize * 8;
+            let high = read_u32(&bytes, position)? as u64;
+            let low = read_u32(&bytes, position + 4)? as u64;
+            offsets.push((high << 32) | low);
+        }
+    }
+
+    Ok(Pack {
+        pack_path: index_path.with_extension("pack"),
+        ids,
+        offsets,
+    })
+}
+
+// Loose objects are a zlib stream of '<type> <size>\0<data>'.
+fn read_loose_object(file: File) -> Result<Object, Error> {
+    let mut bytes = Vec::new();
+    ZlibDecoder::new(BufReader::new(file)).read_to_end(&mut bytes)?;
+
+    let nul = find_byte(&bytes, 0, 0)?;
+    let header = String::from_utf8_lossy(&bytes[..nul]).to_string();
+
+    let kind = match header.split(' ').next() {
+        Some("commit") => ObjectKind::Commit,
+        Some("tree") => ObjectKind::Tree,
+        Some("blob") => ObjectKind::Blob,
+        Some("tag") => ObjectKind::Tag,
+        _ => return Err(invalid_object(&format!("unknown object header '{}'", header))),
+    };
+
+    bytes.drain(..nul + 1);
+
+    Ok(Object { kind, data: bytes })
+}
+
+fn inflate(reader: impl Read, size: usize) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::with_capacity(size);
+    ZlibDecoder::new(reader).take(size as u64).read_to_end(&mut data)?;
+
+    if data.len() != size {
+        return Err(invalid_object("truncated pack object"));
+    }
+
+    Ok(data)
+}
+
+// A delta is the base and result sizes, then instructions that either copy a range of
+// the base or insert the bytes that follow them.
+fn apply_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut position = 0;
+
+    let base_size = read_size_varint(delta, &mut position)?;
+    let result_size = read_size_varint(delta, &mut position)?;
+
+    if base_size != base.len() {
+        return Err(invalid_object("delta base size mismatch"));
+    }
+
+    let mut result = Vec::with_capacity(result_size);
+
+    while position < delta.len() {
+        let instruction = delta[position];
+        position += 1;
+
+        if instruction & 0x80 != 0 {
+            // Which of the four offset and three size bytes are present.
+            let mut offset = 0usize;
+            let mut size = 0usize;
+
+            for i in 0..7 {
+                if instruction & (1 << i) != 0 {
+                    let byte = *delta.get(position).ok_or_else(|| invalid_object("truncated delta"))? as usize;
+                    position += 1;
+
+                    if i < 4 {
+                        offset |= byte << (i * 8);
+                    } else {
+                        size |= byte << ((i - 4) * 8);
+                    }
+                }
+            }
+
+            if size == 0 {
+                size = 0x10000;
+            }
+
+            result.extend_from_slice(base.get(offset..offset + size).ok_or_else(|| invalid_object("delta copies past its base"))?);
+        } else if instruction != 0 {
+            let length = instruction as usize;
+            result.extend_from_slice(delta.get(position..position + length).ok_or_else(|| invalid_object("truncated delta"))?);
+            position += length;
+        } else {
+            return Err(invalid_object("bad delta instruction"));
+        }
+    }
+
+    if result.len() != result_size {
+        return Err(invalid_object("delta result size mismatch"));
+    }
+
+    Ok(result)
+}
+
+// Seven bits per byte, least significant first.
+fn read_size_varint(bytes: &[u8], position: &mut usize) -> Result<usize, Error> {
+    let mut value = 0usize;
+    let mut shift = 0;
+
+    loop {
+        let byte = *bytes.get(*position).ok_or_else(|| invalid_object("truncated delta"))?;
+        *position += 1;
+
+        value |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+// Same encoding as paths in version 4 git indexes, see git::read_offset_varint.
+fn read_offset_varint(reader: &mut impl Read) -> Result<u64, Error> {
+    let mut byte = read_byte(reader)?;
+    let mut value = (byte & 0x7f) as u64;
+
+    while byte & 0x80 != 0 {
+        byte = read_byte(reader)?;
+        value = ((value + 1) << 7) | (byte & 0x7f) as u64;
+    }
+
+    Ok(value)
+}
+
+// The id on a '<name> <hex id>' header line of a commit or tag.
+fn header_id(data: &[u8], name: &str) -> Result<ObjectId, Error> {
+    String::from_utf8_lossy(data)
+        .lines()
+        .take_while(|line| !line.is_empty())
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(' ').and_then(from_hex))
+        .ok_or_else(|| invalid_object(&format!("no '{}' header", name)))
+}
+
+fn read_byte(reader: &mut impl Read) -> Result<u8, Error> {
+    let mut byte = [0u8];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn read_u32(bytes: &[u8], position: usize) -> Result<u32, Error> {
+    bytes
+        .get(position..position + 4)
+        .map(|slice| u32::from_be_bytes(slice.try_into().unwrap()))
+        .ok_or_else(|| invalid_object("truncated pack index"))
+}
+
+fn find_byte(bytes: &[u8], position: usize, byte: u8) -> Result<usize, Error> {
+    bytes
+        .get(position..)
+        .and_then(|rest| rest.iter().position(|candidate| *candidate == byte))
+        .map(|offset| position + offset)
+        .ok_or_else(|| invalid_object("truncated object"))
+}
+
+fn invalid_object(reason: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("invalid git object: {}", reason))
+}
+
+pub fn to_hex(id: &[u8]) -> String {
+    id.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn from_hex(hex: &str) -> Option<ObjectId> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_delta, from_hex, to_hex, ObjectDatabase, PACK_BLOB, PACK_OFS_DELTA, PACK_REF_DELTA};
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    use uuid::Uuid;
+
+    #[test]
+    fn git_objects_hex() {
+        assert_eq!(Some(vec![0x01, 0xab, 0xff]), from_hex("01abFF"));
+        assert_eq!("01abff", to_hex(&[0x01, 0xab, 0xff]));
+        assert_eq!(None, from_hex("abc"));
+        assert_eq!(None, from_hex("zz"));
+    }
+
+    #[test]
+    fn git_objects_apply_delta() {
+        let base = b"fn main() {\n    println!(\"hello\");\n}\n";
+
+        let mut delta = vec![base.len() as u8];
+        let result = b"fn main() {\n    println!(\"goodbye\");\n}\n";
+        delta.push(result.len() as u8);
+
+        // Copy "fn main() {\n    println!(\"", insert "goodbye", copy the rest.
+        delta.extend_from_slice(&[0x80 | 0x10, 26]);
+        delta.push(7);
+        delta.extend_from_slice(b"goodbye");
+        delta.extend_from_slice(&[0x80 | 0x01 | 0x10, 31, 6]);
+
+        assert_eq!(result.to_vec(), apply_delta(base, &delta).unwrap());
+
+        // Sizes that don't add up are rejected.
+        delta[1] += 1;
+        assert!(apply_delta(base, &delta).is_err());
+        assert!(apply_delta(b"short", &delta).is_err());
+    }
+
+    // A pack entry: type and size, whatever the type needs (base distance or id), then
+    // the zlib stream.
+    fn pack_entry(pack_type: u8, extra: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut entry = Vec::new();
+        let mut size = data.len() >> 4;
+        entry.push((pack_type << 4) | (data.len() & 15) as u8 | if size > 0 { 0x80 } else { 0 });
+
+        while size > 0 {
+            entry.push((size & 0x7f) as u8 | if size >> 7 > 0 { 0x80 } else { 0 });
+            size >>= 7;
+        }
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        [entry, extra.to_vec(), encoder.finish().unwrap()].concat()
+    }
+
+    // Inserts 'text' in place of the first 'replaced' bytes of 'base'.
+    fn prefix_delta(base: &[u8], text: &[u8], replaced: usize) -> Vec<u8> {
+        let result_size = text.len() + base.len() - replaced;
+        let mut delta = vec![base.len() as u8, result_size as u8, text.len() as u8];
+        delta.extend_from_slice(text);
+        delta.extend_from_slice(&[0x80 | 0x01 | 0x10, replaced as u8, (base.len() - replaced) as u8]);
+        delta
+    }
+
+    #[test]
+    fn git_objects_packed_deltas() {
+        let objects_directory = format!("{}/rust-indexer-objects-{}", std::env::temp_dir().display(), Uuid::new_v4());
+        std::fs::create_dir_all(format!("{}/pack", objects_directory)).unwrap();
+
+        let base = b"hello world, from a packed blob";
+
+        // A blob, two offset deltas and a ref delta against it.
+        let mut pack = [b"PACK".to_vec(), 2u32.to_be_bytes().to_vec(), 4u32.to_be_bytes().to_vec()].concat();
+        let mut offsets = Vec::new();
+
+        offsets.push(pack.len() as u64);
+        pack.extend(pack_entry(PACK_BLOB, &[], base));
+
+        for text in [b"howdy".as_slice(), b"bye"] {
+            let distance = pack.len() as u64 - offsets[0];
+            offsets.push(pack.len() as u64);
+            pack.extend(pack_entry(PACK_OFS_DELTA, &[distance as u8], &prefix_delta(base, text, 5)));
+        }
+
+        offsets.push(pack.len() as u64);
+        pack.extend(pack_entry(PACK_REF_DELTA, &[0x11; 20], &prefix_delta(base, b"hi", 5)));
+
+        // A version 2 index, made up ids in order.
+        let ids = [0x11u8, 0x22, 0x33, 0x44];
+        let mut index = [b"\xfftOc".to_vec(), 2u32.to_be_bytes().to_vec()].concat();
+
+        for first_byte in 0..256 {
+            index.extend_from_slice(&(ids.iter().filter(|id| (**id as usize) <= first_byte).count() as u32).to_be_bytes());
+        }
+
+        ids.iter().for_each(|id| index.extend_from_slice(&[*id; 20]));
+        ids.iter().for_each(|_| index.extend_from_slice(&[0; 4]));
+        offsets.iter().for_each(|offset| index.extend_from_slice(&(*offset as u32).to_be_bytes()));
+
+        std::fs::write(format!("{}/pack/test.pack", objects_directory), pack).unwrap();
+        std::fs::write(format!("{}/pack/test.idx", objects_directory), index).unwrap();
+
+        let objects = ObjectDatabase::open(std::path::Path::new(&objects_directory), 20).unwrap();
+        assert_eq!(base.to_vec(), objects.read(&[0x11; 20]).unwrap().data);
+        assert_eq!(b"howdy world, from a packed blob".to_vec(), objects.read(&[0x22; 20]).unwrap().data);
+        assert_eq!(b"bye world, from a packed blob".to_vec(), objects.read(&[0x33; 20]).unwrap().data);
+        assert_eq!(b"hi world, from a packed blob".to_vec(), objects.read(&[0x44; 20]).unwrap().data);
+
+        // The base was read once for all three deltas.
+        let base_cache = objects.base_cache.lock().unwrap();
+        assert_eq!(vec![(0, offsets[0])], base_cache.order.iter().copied().collect::<Vec<_>>());
+        assert_eq!(base.len(), base_cache.size);
+        drop(base_cache);
+
+        std::fs::remove_dir_all(&objects_directory).unwrap();
+    }
+}
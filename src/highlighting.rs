@@ -0,0 +1,241 @@
+use crate::language::Language;
+use crate::regions::{Region, RegionSpan};
+
+// What a token in highlighted text is, for picking its color.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TokenKind {
+    Plain,
+    Keyword,
+    Number,
+    Comment,
+    String,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Token {
+    pub start: usize,
+    pub end: usize,
+    pub kind: TokenKind,
+}
+
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern", "false", "fn",
+    "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "self", "Self",
+    "static", "struct", "super", "trait", "true", "type", "unsafe", "use", "where", "while",
+];
+const C_KEYWORDS: &[&str] = &[
+    "auto", "bool", "break", "case", "char", "class", "const", "constexpr", "continue", "default", "delete", "do",
+    "double", "else", "enum", "extern", "false", "float", "for", "goto", "if", "inline", "int", "long", "namespace",
+    "new", "nullptr", "private", "protected", "public", "return", "short", "signed", "sizeof", "static", "struct",
+    "switch", "template", "this", "throw", "true", "try", "typedef", "typename", "union", "unsigned", "using",
+    "virtual", "void", "volatile", "while",
+];
+const JVM_KEYWORDS: &[&str] = &[
+    "abstract", "async", "await", "bool", "boolean", "break", "byte", "case", "catch", "class", "const", "continue",
+    "default", "do", "double", "else", "enum", "extends", "false", "final", "finally", "float", "for", "foreach",
+    "fun", "func", "if", "implements", "import", "in", "int", "interface", "internal", "is", "let", "long",
+    "namespace", "new", "null", "object", "override", "package", "private", "protected", "public", "return",
+    "sealed", "short", "static", "string", "struct", "super", "switch", "this", "throw", "throws", "true", "try",
+    "using", "val", "var", "void", "when", "while",
+];
+const GO_KEYWORDS: &[&str] = &[
+    "break", "case", "chan", "const", "continue", "default", "defer", "else", "fallthrough", "false", "for", "func",
+    "go", "goto", "if", "import", "interface", "map", "nil", "package", "range", "return", "select", "struct",
+    "switch", "true", "type", "var",
+];
+const SCRIPT_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "case", "catch", "class", "const", "continue", "default", "delete", "do",
+    "else", "enum", "export", "extends", "false", "finally", "for", "from", "function", "if", "implements",
+    "import", "in", "instanceof", "interface", "let", "new", "null", "of", "return", "static", "super", "switch",
+    "this", "throw", "true", "try", "type", "typeof", "undefined", "var", "void", "while", "yield",
+];
+const PYTHON_KEYWORDS: &[&str] = &[
+    "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del", "elif", "else", "except",
+    "False", "finally", "for", "from", "global", "if", "import", "in", "is", "lambda", "None", "nonlocal", "not",
+    "or", "pass", "raise", "return", "self", "True", "try", "while", "with", "yield",
+];
+const RUBY_KEYWORDS: &[&str] = &[
+    "begin", "class", "def", "do", "else", "elsif", "end", "ensure", "false", "if", "module", "next", "nil",
+    "require", "rescue", "return", "self", "then", "true", "unless", "until", "when", "while", "yield",
+];
+const SHELL_KEYWORDS: &[&str] = &[
+    "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if", "in", "local", "return",
+    "then", "until", "while",
+];
+
+// Matched ignoring case.
+const SQL_KEYWORDS: &[&str] = &[
+    "and", "as", "by", "create", "delete", "distinct", "drop", "from", "group", "having", "insert", "into", "is",
+    "join", "left", "limit", "not", "null", "on", "or", "order", "primary", "key", "select", "set", "table",
+    "union", "update", "values", "where",
+];
+
+fn keywords_of(language: Language) -> &'static [&'static str] {
+    match language {
+        Language::Rust => RUST_KEYWORDS,
+        Language::C | Language::Cpp => C_KEYWORDS,
+        Language::CSharp | Language::Java | Language::Kotlin | Language::Scala | Language::Swift => JVM_KEYWORDS,
+        Language::Go => GO_KEYWORDS,
+        Language::TypeScript | Language::JavaScript | Language::Php => SCRIPT_KEYWORDS,
+        Language::Python => PYTHON_KEYWORDS,
+        Language::Ruby | Language::Perl | Language::Lua => RUBY_KEYWORDS,
+        Language::Shell | Language::Makefile | Language::Dockerfile | Language::PowerShell => SHELL_KEYWORDS,
+        Language::Sql => SQL_KEYWORDS,
+        _ => &[],
+    }
+}
+
+// Splits 'text' into tokens to color, covering all of it in order. Comments and
+// strings are taken from 'regions', found by classify_regions() on the whole file so
+// that text starting partway through a comment is still colored right. Within code,
+// keywords and numbers are picked out; everything else, and text in no language, is
+// plain.
+pub fn highlight(language: Option<Language>, text: &str, regions: &[RegionSpan]) -> Vec<Token> {
+    let mut tokens: Vec<Token> = Vec::new();
+
+    let Some(language) = language else {
+        if !text.is_empty() {
+            tokens.push(Token { start: 0, end: text.len(), kind: TokenKind::Plain });
+        }
+
+        return tokens;
+    };
+
+    let keywords = keywords_of(language);
+    let ignore_case = language == Language::Sql;
+
+    let mut push = |start: usize, end: usize, kind: TokenKind| {
+        if start >= end {
+            return;
+        }
+
+        // Neighbouring plain text is merged, so that it's printed in one go.
+        match tokens.last_mut() {
+            Some(last) if last.kind == kind && last.end == start => last.end = end,
+            _ => tokens.push(Token { start, end, kind }),
+        }
+    };
+
+    let mut code_start = 0;
+
+    for span in regions {
+        let start = span.start.min(text.len());
+        let end = span.end.min(text.len());
+
+        highlight_code(text, code_start, start, keywords, ignore_case, &mut push);
+
+        let kind = match span.region {
+            Region::Comment => TokenKind::Comment,
+            Region::String => TokenKind::String,
+            Region::Code => TokenKind::Plain,
+        };
+
+        push(start, end, kind);
+        code_start = end;
+    }
+
+    highlight_code(text, code_start, text.len(), keywords, ignore_case, &mut push);
+
+    tokens
+}
+
+fn highlight_code(text: &str, start: usize, end: usize, keywords: &[&str], ignore_case: bool, push: &mut impl FnMut(usize, usize, TokenKind)) {
+    let bytes = text.as_bytes();
+    let mut plain_start = start;
+    let mut i = start;
+
+    while i < end {
+        if !is_word_byte(bytes[i]) {
+            i += 1;
+            continue;
+        }
+
+        let word_end = i + bytes[i..end].iter().take_while(|byte| is_word_byte(**byte)).count();
+        let word = &text[i..word_end];
+
+        let kind = if bytes[i].is_ascii_digit() {
+            TokenKind::Number
+        } else if keywords.iter().any(|keyword| if ignore_case { keyword.eq_ignore_ascii_case(word) } else { *keyword == word }) {
+            TokenKind::Keyword
+        } else {
+            TokenKind::Plain
+        };
+
+        if kind != TokenKind::Plain {
+            push(plain_start, i, TokenKind::Plain);
+            push(i, word_end, kind);
+            plain_start = word_end;
+        }
+
+        i = word_end;
+    }
+
+    push(plain_start, end, TokenKind::Plain);
+}
+
+// Identifiers and numbers, e.g. 'parse_query' or '0x1f'. Bytes of non-ASCII characters
+// are included, so words never end partway through a character.
+fn is_word_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_' || byte >= 0x80
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::language::Language;
+    use crate::regions::{classify_regions, Region, RegionSpan};
+
+    use super::{highlight, TokenKind};
+
+    fn tokens(language: Option<Language>, text: &str) -> Vec<(TokenKind, &str)> {
+        let regions = classify_regions(language, text.as_bytes());
+
+        highlight(language, text, &regions)
+            .into_iter()
+            .map(|token| (token.kind, &text[token.start..token.end]))
+            .collect()
+    }
+
+    #[test]
+    fn highlighting_rust() {
+        assert_eq!(vec![
+            (TokenKind::Keyword, "let"),
+            (TokenKind::Plain, " letter = "),
+            (TokenKind::Number, "42"),
+            (TokenKind::Plain, "; "),
+            (TokenKind::Comment, "// the answer"),
+            (TokenKind::Plain, "\n"),
+            (TokenKind::Keyword, "fn"),
+            (TokenKind::Plain, " f() { "),
+            (TokenKind::String, "\"fn\""),
+            (TokenKind::Plain, " }"),
+        ], tokens(Some(Language::Rust), "let letter = 42; // the answer\nfn f() { \"fn\" }"));
+    }
+
+    #[test]
+    fn highlighting_covers_everything() {
+        let text = "SELECT naïve FROM t WHERE x = 'it''s' -- done\n";
+        let found = tokens(Some(Language::Sql), text);
+
+        assert_eq!((TokenKind::Keyword, "SELECT"), found[0]);
+        assert_eq!(text, found.iter().map(|(_, token)| *token).collect::<String>());
+
+        // No language, no colors.
+        assert_eq!(vec![(TokenKind::Plain, "let x = 1;")], tokens(None, "let x = 1;"));
+        assert!(tokens(None, "").is_empty());
+    }
+
+    #[test]
+    fn highlighting_clipped_regions() {
+        // Context that starts partway through a block comment, as scraping clips it.
+        let text = "still a comment */ fn f() {}";
+        let regions = [RegionSpan { start: 0, end: 18, region: Region::Comment }];
+
+        let found: Vec<(TokenKind, &str)> = highlight(Some(Language::Rust), text, &regions)
+            .into_iter()
+            .map(|token| (token.kind, &text[token.start..token.end]))
+            .collect();
+
+        assert_eq!((TokenKind::Comment, "still a comment */"), found[0]);
+        assert_eq!((TokenKind::Keyword, "fn"), found[2]);
+    }
+}
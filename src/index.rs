@@ -1,17 +1,33 @@
-use crate::batching::{batch_items, batch_items_by_cpu_count};
+use crate::batching::{batch_items, jobs_count};
+use crate::clustering::{batch_items_by_similarity, Clustering};
+use crate::binary::{has_binary_extension, looks_binary, BinaryHandling};
+use crate::encoding::{detect_encoding, Encoding, StreamDecoder, DETECTION_LENGTH};
+use crate::git::CommitFiles;
+use crate::language::{Language, LanguageSet};
 use crate::{bloom::BloomFilter, compression_utils::lowercase_alphanumeric_only};
-use crate::trigram::Trigram;
+use crate::progress::IndexingProgress;
+use crate::query::{Query, QueryMode};
+use crate::symbols::{match_rank, Symbol, SymbolExtractor, SymbolMatch};
+use crate::trigram::{Trigram, TrigramExtractor};
+use crate::walker::{is_walked, walk_directory_parallel, WalkOptions};
 use rmp_serde::Serializer;
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
-use std::io::{Read, Write};
-use std::usize;
-use std::{collections::HashSet, path::Path};
+use std::io::{Cursor, Read, Write};
+use std::{collections::{BTreeMap, HashMap, HashSet}, path::{Component, Path, PathBuf}};
 use std::fs::File;
+use std::sync::{Arc, Condvar, Mutex};
 use tokio::task::JoinSet;
 
 const BLOOM_FILTER_SIZE: usize = 714;
-const CHILDREN_PER_NODE: usize = 2;
+
+// How many discovered files can wait to be indexed before the walk pauses.
+const DISCOVERED_FILES_BUFFER: usize = 4096;
+
+// How much of a file is read at a time when indexing it.
+const READ_CHUNK_LENGTH: usize = 64 * 1024;
+const DEFAULT_FAN_OUT: usize = 2;
+const DEFAULT_LEAF_SIZE: usize = 2;
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct IndexTree {
@@ -19,29 +35,15 @@ pub struct IndexTree {
     child_nodes: Vec<IndexTree>,
     bloom_filter: BloomFilter,
     files_count: usize,
+
+    // Every language in the subtree, so that searches limited to other languages can
+    // skip it without comparing bloom filters.
+    languages: LanguageSet,
 }
 
 impl IndexTree {
     pub fn from_index(index: &Index, output_path: &str) -> IndexTree {
-        // Create a new mini index from each batch.
-        let batches: Vec<Index> = batch_items(&index.files, index.files_count() / CHILDREN_PER_NODE)
-            .into_iter()
-            .map(|batch| Index { files: batch })
-            .collect();
-
-        let mut nodes: Vec<IndexTree> = batch_items(&batches, batches.len() / CHILDREN_PER_NODE)
-            .into_iter()
-            .map(|batch| Self::from_nodes(&batch, &[], output_path))
-            .collect();
-
-        while nodes.len() > CHILDREN_PER_NODE {
-            nodes = batch_items(&nodes, nodes.len() / CHILDREN_PER_NODE)
-                .into_iter()
-                .map(|batch| Self::from_nodes(&[], &batch, output_path))
-                .collect();
-        }
-
-        IndexTree::from_nodes(&[], &nodes, output_path)
+        IndexTreeBuilder::new().build(index, output_path)
     }
 
     pub fn from_nodes(child_indexes: &[Index], nodes: &[IndexTree], output_path: &str) -> IndexTree {
@@ -70,11 +72,14 @@ impl IndexTree {
             .iter().map(|node| node.files_count).sum::<usize>() +
             child_indexes.iter().map(|index|index.files_count()).sum::<usize>();
 
+        let languages = languages_of(nodes, child_indexes);
+
         IndexTree {
             child_indexes: lazy_indexes,
             child_nodes: Vec::from(nodes),
-            bloom_filter: BloomFilter::from_filters(&combined),
-            files_count
+            bloom_filter: BloomFilter::from_filters(&combined, BLOOM_FILTER_SIZE),
+            files_count,
+            languages,
         }
     }
 
@@ -83,7 +88,13 @@ impl IndexTree {
         let mut index_read = File::open(path).unwrap();
         index_read.read_to_end(&mut buf).unwrap();
 
-        rmp_serde::from_slice(&buf).unwrap()
+        let mut tree: IndexTree = rmp_serde::from_slice(&buf).unwrap();
+
+        // Shards live next to the root file, wherever it was moved to.
+        let index_directory = Path::new(path).parent().unwrap_or(Path::new(""));
+        tree.set_index_directory(&index_directory.to_string_lossy());
+
+        tree
     }
 
     pub fn save(&self, path: &str) {
@@ -94,48 +105,409 @@ impl IndexTree {
         file.write_all(&buf).unwrap();
     }
 
-    pub fn search_files(&self, query: &str) -> (HashSet<String>, usize) {
-        let mut files = HashSet::new();
+    pub fn search_files(&self, query: &str) -> SearchResults {
+        self.search_query(&Query::literal(query))
+    }
 
-        let trigrams = Trigram::from_str(&&lowercase_alphanumeric_only(&query));
+    pub fn search_query(&self, query: &Query) -> SearchResults {
+        let mut results = SearchResults::default();
 
-        let u32s: Vec<u32> = trigrams
-            .iter()
-            .map(|t| t.to_u32())
-            .collect();
-    
-        let query_bloom_filter = BloomFilter::new(&u32s, BLOOM_FILTER_SIZE);
+        let query_filters = QueryFilters::new(query);
+
+        results.bloom_filters_checked = Self::search_node_for_files(&query_filters, &mut results, self);
+
+        results
+    }
+
+    pub fn parallel_search_files(&self, query: &str, threads: usize) -> SearchResults {
+        self.parallel_search_query(&Query::literal(query), threads)
+    }
+
+    // Same as search_query(), but walks subtrees and loads shards from disk on up to
+    // 'threads' threads at once. Returns exactly the same results.
+    pub fn parallel_search_query(&self, query: &Query, threads: usize) -> SearchResults {
+        let query_filters = QueryFilters::new(query);
+        let queue = SearchQueue::new(self);
+
+        let worker_results: Vec<SearchResults> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads.max(1))
+                .map(|_| scope.spawn(|| queue.run_worker(&query_filters)))
+                .collect();
+
+            workers
+                .into_iter()
+                .map(|worker| worker.join().unwrap())
+                .collect()
+        });
 
-        let bloom_filters_checked = Self::search_node_for_files(&query_bloom_filter, &mut files, self);
+        let mut results = SearchResults::default();
 
-        (files, bloom_filters_checked)
+        for worker_results in worker_results {
+            results.files.extend(worker_results.files);
+            results.name_only_files.extend(worker_results.name_only_files);
+            results.bloom_filters_checked += worker_results.bloom_filters_checked;
+        }
+
+        results
     }
 
     pub fn files_count(&self) -> usize {
         self.files_count
     }
 
-    fn search_node_for_files(query: &BloomFilter, files: &mut HashSet<String>, node: &IndexTree) -> usize {
+    // Definitions whose names match 'query', best matches first. Bloom filters can't
+    // help with fuzzy matches, so every shard is loaded, on up to 'threads' threads
+    // at once.
+    pub fn find_symbols(&self, query: &str, threads: usize) -> Vec<SymbolMatch> {
+        let mut shards = Vec::new();
+        self.collect_shards(&mut shards);
+
+        let shards_per_thread = shards.len().div_ceil(threads.max(1)).max(1);
+
+        let mut matches: Vec<SymbolMatch> = std::thread::scope(|scope| {
+            let workers: Vec<_> = shards
+                .chunks(shards_per_thread)
+                .map(|shards| scope.spawn(move || {
+                    let mut matches = Vec::new();
+
+                    for file in shards.iter().flat_map(|shard| shard.get().files) {
+                        for symbol in file.symbols {
+                            if let Some(rank) = match_rank(query, &symbol.name) {
+                                matches.push(SymbolMatch { file_path: file.file_path.clone(), symbol, rank });
+                            }
+                        }
+                    }
+
+                    matches
+                }))
+                .collect();
+
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().unwrap())
+                .collect()
+        });
+
+        matches.sort_by(|a, b| {
+            (a.rank, a.symbol.name.len(), &a.file_path, a.symbol.line)
+                .cmp(&(b.rank, b.symbol.name.len(), &b.file_path, b.symbol.line))
+        });
+
+        matches
+    }
+
+    // Walks the whole tree, loading every shard, so this is slow on big indexes.
+    pub fn stats(&self) -> IndexTreeStats {
+        let mut stats = IndexTreeStats {
+            files_count: self.files_count,
+            shards_count: 0,
+            levels: Vec::new(),
+            encodings: BTreeMap::new(),
+            languages: BTreeMap::new(),
+        };
+
+        let mut file_fill_ratios = Vec::new();
+        let mut level: Vec<&IndexTree> = vec![self];
+
+        while !level.is_empty() {
+            stats.levels.push(LevelStats {
+                fill_ratios: level.iter().map(|node| node.bloom_filter.fill_ratio()).collect(),
+            });
+
+            for node in &level {
+                for index in &node.child_indexes {
+                    stats.shards_count += 1;
+
+                    for file in index.get().files {
+                        file_fill_ratios.push(file.bloom_filter.fill_ratio());
+                        let encoding = if file.binary { "binary" } else { file.encoding.name() };
+                        *stats.encodings.entry(encoding).or_default() += 1;
+                        let language = file.language.map_or("other", |language| language.name());
+                        *stats.languages.entry(language).or_default() += 1;
+                    }
+                }
+            }
+
+            level = level
+                .iter()
+                .flat_map(|node| node.child_nodes.iter())
+                .collect();
+        }
+
+        stats.levels.push(LevelStats { fill_ratios: file_fill_ratios });
+
+        stats
+    }
+
+    // Deletes shard files in the index directory that are not referenced by this
+    // tree, e.g. those left behind by the previous generation of the index. Must
+    // only be called while holding the index's exclusive lock.
+    pub fn remove_unreferenced_shards(&self, index_directory: &str) -> Result<usize, std::io::Error> {
+        let mut referenced = HashSet::new();
+        self.collect_shard_names(&mut referenced);
+
+        let mut removed = 0;
+
+        for entry in std::fs::read_dir(index_directory)? {
+            let entry = entry?;
+            let file_name = entry.file_name().to_string_lossy().to_string();
+
+            // Shards are named by UUID, so anything else is not ours to delete.
+            if Uuid::parse_str(&file_name).is_ok() && !referenced.contains(&file_name) {
+                std::fs::remove_file(entry.path())?;
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+
+    // Moves the tree's shards from wherever they were written to 'index_directory',
+    // e.g. once a tree built in a staging folder is ready to replace the index.
+    pub fn move_shards(&mut self, index_directory: &str) -> Result<(), std::io::Error> {
+        for index in &mut self.child_indexes {
+            std::fs::rename(index.path(), format!("{}/{}", index_directory, index.file_name))?;
+            index.directory = index_directory.to_string();
+        }
+
+        for child_node in &mut self.child_nodes {
+            child_node.move_shards(index_directory)?;
+        }
+
+        Ok(())
+    }
+
+    fn set_index_directory(&mut self, index_directory: &str) {
+        for index in &mut self.child_indexes {
+            index.directory = index_directory.to_string();
+        }
+
+        for child_node in &mut self.child_nodes {
+            child_node.set_index_directory(index_directory);
+        }
+    }
+
+    // Indexes the files at 'paths' again, in place. Paths are relative to 'root_path'
+    // with '/' separators, like the paths in the index. Files that are gone or are now
+    // skipped are removed, files that are new are added to the shard holding their
+    // nearest neighbor by path, and only the shards that change are written out, to
+    // 'index_directory', along with new bloom filters for their ancestors. The shards
+    // they replace are left for remove_unreferenced_shards(). Files are read from
+    // options.git_commit_files instead of disk if it's set. Otherwise files that walking
+    // the folder would leave out, e.g. symlinks or files in excluded folders, are too.
+    //
+    // Every shard may have to be read to find the files, but unchanged ones aren't
+    // written. Shards can grow past the leaf size, until the next full index.
+    pub fn update_files(&mut self, root_path: &str, paths: &[String], options: &IndexOptions, index_directory: &str) -> UpdateStats {
+        let progress = IndexingProgress::default();
+
+        let commit_files = options.git_commit_files.as_deref();
+        let walk_options = walk_options(options);
+
+        let updates = paths
+            .iter()
+            .map(|path| {
+                let file_path = resolve_indexed_path(root_path, path).to_string_lossy().to_string();
+
+                let entry = if commit_files.is_none() && !is_walked(root_path, &file_path, &walk_options) {
+                    None
+                } else {
+                    match bloom_index_file(root_path, &file_path, options, &progress, commit_files) {
+                        Ok(IndexedFile::Contents(entry) | IndexedFile::BinaryName(entry) | IndexedFile::BinaryContents(entry)) => Some(entry),
+                        _ => None,
+                    }
+                };
+
+                (path.clone(), entry)
+            })
+            .collect();
+
+        let mut update = NodeUpdate {
+            updates,
+            ..Default::default()
+        };
+
+        // Replace and remove files where they are.
+        self.update_node(&mut Vec::new(), &mut update, index_directory);
+
+        // Whatever is left wasn't in the index yet.
+        let mut additions: Vec<FileEntry> = std::mem::take(&mut update.updates)
+            .into_values()
+            .flatten()
+            .collect();
+
+        if additions.is_empty() {
+            return update.stats;
+        }
+
+        additions.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+        update.shard_starts.sort();
+
+        if update.shard_starts.is_empty() {
+            // No shards left at all, so start one on the leftmost node.
+            let mut location = Vec::new();
+            let mut node: &IndexTree = self;
+
+            while let Some(child_node) = node.child_nodes.first() {
+                location.push(0);
+                node = child_node;
+            }
+
+            update.addition_locations.push(location.clone());
+            update.new_shard = Some((location, additions));
+        } else {
+            for file in additions {
+                let after = update.shard_starts.partition_point(|(first_path, _, _)| *first_path <= file.file_path);
+                let (_, location, file_name) = &update.shard_starts[after.saturating_sub(1)];
+
+                update.addition_locations.push(location.clone());
+                update.additions.entry(file_name.clone()).or_default().push(file);
+            }
+        }
+
+        self.update_node(&mut Vec::new(), &mut update, index_directory);
+
+        update.stats
+    }
+
+    // Applies 'update' to this node's shards and the nodes below it, at 'location'
+    // (child node indexes from the root). Returns true if anything changed, in which
+    // case the node's bloom filter and count are recomputed.
+    fn update_node(&mut self, location: &mut Vec<usize>, update: &mut NodeUpdate, index_directory: &str) -> bool {
+        let mut changed = false;
+
+        for i in 0..self.child_nodes.len() {
+            location.push(i);
+
+            if update.visits(location) {
+                changed |= self.child_nodes[i].update_node(location, update, index_directory);
+            }
+
+            location.pop();
+        }
+
+        let mut shards = Vec::new();
+        let mut shards_changed = false;
+
+        for lazy_index in &mut self.child_indexes {
+            let mut index = lazy_index.get();
+            let mut shard_changed = false;
+
+            index.files.retain_mut(|file| match update.updates.remove(&file.file_path) {
+                Some(Some(entry)) => {
+                    update.stats.reindexed_count += 1;
+                    shard_changed = true;
+                    *file = entry;
+                    true
+                },
+                Some(None) => {
+                    update.stats.removed_count += 1;
+                    shard_changed = true;
+                    false
+                },
+                None => true,
+            });
+
+            if let Some(added) = update.additions.remove(&lazy_index.file_name) {
+                update.stats.added_count += added.len();
+                shard_changed = true;
+                index.files.extend(added);
+            }
+
+            // Emptied shards are dropped below rather than written.
+            if shard_changed && !index.files.is_empty() {
+                *lazy_index = LazyIndex::from_index(&index, index_directory);
+                update.stats.shards_written += 1;
+            }
+
+            if let Some(first_path) = index.files.iter().map(|file| &file.file_path).min() {
+                update.shard_starts.push((first_path.clone(), location.clone(), lazy_index.file_name.clone()));
+            }
+
+            shards_changed |= shard_changed;
+            shards.push(index);
+        }
+
+        if update.new_shard.as_ref().is_some_and(|(new_shard_location, _)| new_shard_location == location) {
+            let (_, files) = update.new_shard.take().unwrap();
+            let index = Index { files };
+
+            update.stats.added_count += index.files_count();
+            update.stats.shards_written += 1;
+            self.child_indexes.push(LazyIndex::from_index(&index, index_directory));
+            shards.push(index);
+            shards_changed = true;
+        }
+
+        if shards_changed {
+            let mut kept = shards.iter().map(|index| !index.files.is_empty());
+            self.child_indexes.retain(|_| kept.next().unwrap());
+            shards.retain(|index| !index.files.is_empty());
+        }
+
+        if changed || shards_changed {
+            let mut bloom_filter = BloomFilter::new(&[], BLOOM_FILTER_SIZE);
+
+            for child_node in &self.child_nodes {
+                bloom_filter.merge(&child_node.bloom_filter);
+            }
+
+            for file in shards.iter().flat_map(|index| index.files.iter()) {
+                bloom_filter.merge(&file.bloom_filter);
+            }
+
+            self.bloom_filter = bloom_filter;
+            self.languages = languages_of(&self.child_nodes, &shards);
+            self.files_count = self.child_nodes.iter().map(|node| node.files_count).sum::<usize>() +
+                shards.iter().map(|index| index.files_count()).sum::<usize>();
+        }
+
+        changed || shards_changed
+    }
+
+    fn collect_shards<'a>(&'a self, shards: &mut Vec<&'a LazyIndex>) {
+        shards.extend(self.child_indexes.iter());
+
+        for child_node in &self.child_nodes {
+            child_node.collect_shards(shards);
+        }
+    }
+
+    fn collect_shard_names(&self, names: &mut HashSet<String>) {
+        for index in &self.child_indexes {
+            names.insert(index.file_name.clone());
+        }
+
+        for child_node in &self.child_nodes {
+            child_node.collect_shard_names(names);
+        }
+    }
+
+    fn search_node_for_files(query: &QueryFilters, results: &mut SearchResults, node: &IndexTree) -> usize {
         let mut bloom_filters_checked = 0;
 
+        if !query.wants_any_of(node.languages) {
+            return bloom_filters_checked;
+        }
+
         // Check if the merged bloom filter is a match. If so, there may be relevant children.
-        if !node.bloom_filter.possibly_contains(query) {
+        if !query.possibly_matches(&node.bloom_filter) {
             bloom_filters_checked += 1;
             return bloom_filters_checked;
         }
 
         // Search relevant child nodes.
         for child_node in &node.child_nodes {
-            bloom_filters_checked += Self::search_node_for_files(query, files, child_node);
+            bloom_filters_checked += Self::search_node_for_files(query, results, child_node);
         }
 
         // Search any direct children.
         for index in &node.child_indexes {
-            for file in &index.get().files {
+            for file in index.get().files.iter().filter(|file| query.wants(file.language)) {
                 bloom_filters_checked += 1;
 
-                if file.bloom_filter.possibly_contains(query) {
-                    files.insert(file.file_path.clone());
+                if query.possibly_matches(&file.bloom_filter) {
+                    results.add(file);
                 }
             }
         }
@@ -144,182 +516,1682 @@ impl IndexTree {
     }
 }
 
-#[derive(Clone, Serialize, Deserialize)]
-pub struct LazyIndex {
-    file_name: String,
+fn languages_of(nodes: &[IndexTree], shards: &[Index]) -> LanguageSet {
+    let mut languages = LanguageSet::default();
+
+    for node in nodes {
+        languages.merge(node.languages);
+    }
+
+    for language in shards.iter().flat_map(|index| index.files.iter()).filter_map(|file| file.language) {
+        languages.insert(language);
+    }
+
+    languages
 }
 
-impl LazyIndex {
-    pub fn from_file(path: &str) -> LazyIndex {
-        LazyIndex {
-            file_name: path.to_string(),
-        }
+fn trigram_u32s(text: &str) -> Vec<u32> {
+    Trigram::from_str(&lowercase_alphanumeric_only(text))
+        .iter()
+        .map(|t| t.to_u32())
+        .collect()
+}
+
+// Bloom filters for a query. A file can only match if its filter contains at least
+// one of them.
+struct QueryFilters {
+    filters: Vec<BloomFilter>,
+    languages: Option<LanguageSet>,
+}
+
+impl QueryFilters {
+    fn new(query: &Query) -> QueryFilters {
+        let filters = match query.mode {
+            // Every term must be present, so one filter with all of their trigrams.
+            // Trigrams are taken per term so they never span two terms.
+            QueryMode::All => {
+                let u32s: Vec<u32> = query.terms.iter().flat_map(|term| trigram_u32s(term)).collect();
+                vec![BloomFilter::new(&u32s, BLOOM_FILTER_SIZE)]
+            },
+            QueryMode::Any => query.terms
+                .iter()
+                .map(|term| BloomFilter::new(&trigram_u32s(term), BLOOM_FILTER_SIZE))
+                .collect(),
+        };
+
+        QueryFilters { filters, languages: query.languages }
     }
 
-    pub fn from_index(index: &Index, output_path: &str) -> LazyIndex {
-        let file_name = format!("{}/{}", output_path, Uuid::new_v4());
-        index.save(&file_name);
+    // Checked before bloom filters, since it's much cheaper.
+    fn wants_any_of(&self, languages: LanguageSet) -> bool {
+        self.languages.is_none_or(|wanted| wanted.intersects(languages))
+    }
 
-        LazyIndex {
-            file_name,
+    fn wants(&self, language: Option<Language>) -> bool {
+        match self.languages {
+            Some(wanted) => language.is_some_and(|language| wanted.contains(language)),
+            None => true,
         }
     }
 
-    pub fn get(&self) -> Index {
-        Index::from_file(&self.file_name)
+    fn possibly_matches(&self, bloom_filter: &BloomFilter) -> bool {
+        self.filters.iter().any(|filter| bloom_filter.possibly_contains(filter))
     }
 }
 
-pub async fn parallel_index_directory(path: &str) -> Index {
-    let files = enumerate_directory(path);
+// Files whose bloom filters pass a query, so they possibly match it.
+#[derive(Debug, Default, PartialEq)]
+pub struct SearchResults {
+    pub files: HashSet<String>,
 
-    let mut set = JoinSet::new();
-
-    for batch in batch_items_by_cpu_count(&files) {
-        set.spawn(
-            async move {
-                Vec::from_iter(batch.iter().map(|file| bloom_index_file(&file)))
-            });
-    }
+    // Those of 'files' that are binaries indexed by name only, for which only the
+    // path can match.
+    pub name_only_files: HashSet<String>,
 
-    let mut all_matches = Vec::new();
+    pub bloom_filters_checked: usize,
+}
 
-    while let Some(res) = set.join_next().await {
-        for item in res.unwrap() {
-            if let Ok(ok_item) = item {
-                all_matches.push(ok_item);
-            }
+impl SearchResults {
+    fn add(&mut self, file: &FileEntry) {
+        if file.name_only {
+            self.name_only_files.insert(file.file_path.clone());
         }
+
+        self.files.insert(file.file_path.clone());
     }
+}
 
-    let mut index = Index::new();
+enum SearchWork<'a> {
+    Node(&'a IndexTree),
+    Shard(&'a LazyIndex),
+}
 
-    for item in all_matches {
-        index.add_file(item)
-    }
+// Work queue shared by the parallel_search_files() threads. Nodes that pass the
+// query filter add their children to the queue, so different subtrees and shard
+// loads proceed in parallel.
+struct SearchQueue<'a> {
+    state: Mutex<SearchState<'a>>,
+    changed: Condvar,
+}
+
+struct SearchState<'a> {
+    pending: Vec<SearchWork<'a>>,
+
+    // How many items are currently being worked on.
+    in_flight: usize,
+
+    // A worker panicked, so the others give up rather than wait for it.
+    stopped: bool,
+}
 
-    index
+// Hands a worker's new work back to the queue when it's done with an item, or stops
+// the queue if it panics partway through, so that other workers never wait on it.
+struct SearchWorkGuard<'q, 'a> {
+    queue: &'q SearchQueue<'a>,
+    new_work: Vec<SearchWork<'a>>,
 }
 
-fn enumerate_directory(path: &str) -> Vec<String> {
-    let mut file_paths = Vec::new();
-    let files = std::fs::read_dir(path).unwrap();
+impl Drop for SearchWorkGuard<'_, '_> {
+    fn drop(&mut self) {
+        let mut state = self.queue.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
 
-    for file in files {
-        let unwrapped_file = file.unwrap();
+        if std::thread::panicking() {
+            state.stopped = true;
+        } else {
+            state.pending.append(&mut self.new_work);
+        }
 
-        if unwrapped_file.file_type().unwrap().is_file() {
-            file_paths.push(unwrapped_file.path().to_str().unwrap().to_string());
-        } else if unwrapped_file.file_type().unwrap().is_dir() {
-            let file_path_buffer = unwrapped_file.path();
-            let file_path = file_path_buffer.to_str().unwrap();
+        state.in_flight -= 1;
 
-            // Exclude the dot git folder in repos.
-            if !file_path.ends_with(".git") {
-                for path in enumerate_directory(unwrapped_file.path().to_str().unwrap()) {
-                    file_paths.push(path.to_string());
-                }
-            }
+        self.queue.changed.notify_all();
+    }
+}
+
+impl<'a> SearchQueue<'a> {
+    fn new(root: &'a IndexTree) -> SearchQueue<'a> {
+        SearchQueue {
+            state: Mutex::new(SearchState { pending: vec![SearchWork::Node(root)], in_flight: 0, stopped: false }),
+            changed: Condvar::new(),
         }
     }
 
-    file_paths
-}
+    fn run_worker(&self, query: &QueryFilters) -> SearchResults {
+        let mut results = SearchResults::default();
+
+        while let Some(work) = self.take() {
+            let mut guard = SearchWorkGuard { queue: self, new_work: Vec::new() };
+            let new_work = &mut guard.new_work;
+
+            match work {
+                SearchWork::Node(node) => {
+                    // Mirrors search_node_for_files(), including what it counts.
+                    if query.wants_any_of(node.languages) {
+                        if !query.possibly_matches(&node.bloom_filter) {
+                            results.bloom_filters_checked += 1;
+                        } else {
+                            new_work.extend(node.child_nodes.iter().map(SearchWork::Node));
+                            new_work.extend(node.child_indexes.iter().map(SearchWork::Shard));
+                        }
+                    }
+                },
+                SearchWork::Shard(index) => {
+                    for file in index.get().files.iter().filter(|file| query.wants(file.language)) {
+                        results.bloom_filters_checked += 1;
+
+                        if query.possibly_matches(&file.bloom_filter) {
+                            results.add(file);
+                        }
+                    }
+                },
+            }
+        }
 
-fn bloom_index_file(file_path: &str) -> Result<FileEntry, std::io::Error> {
+        results
+    }
 
-    let file_text = std::fs::read_to_string(Path::new(file_path))?;
+    // Waits for work. Returns None once the queue is empty and nobody is working
+    // on anything that could add more, or once a worker has panicked.
+    fn take(&self) -> Option<SearchWork<'a>> {
+        let mut state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
 
-    let trigrams = Trigram::from_str(&&lowercase_alphanumeric_only(&file_text));
+        loop {
+            if state.stopped {
+                return None;
+            }
 
-    let u32s: Vec<u32> = trigrams
-        .iter()
-        .map(|t| t.to_u32())
-        .collect();
+            if let Some(work) = state.pending.pop() {
+                state.in_flight += 1;
+                return Some(work);
+            }
 
-    let bloom_filter = BloomFilter::new(&u32s, BLOOM_FILTER_SIZE);
+            if state.in_flight == 0 {
+                return None;
+            }
 
-    Ok(
-        FileEntry {
-            file_path: file_path.to_string(),
-            bloom_filter,
+            state = self.changed.wait(state).unwrap_or_else(|poisoned| poisoned.into_inner());
         }
-    )
+    }
 }
 
-#[derive(Clone, Serialize, Deserialize)]
-pub struct Index {
-    files: Vec<FileEntry>
+// Builds balanced IndexTrees: files are split into shards ('leaves') of at most
+// leaf_size files, and each node has at most fan_out children. Every level is split
+// as evenly as possible, so all shards end up at the same depth and sibling sizes
+// differ by at most one.
+#[derive(Clone)]
+pub struct IndexTreeBuilder {
+    fan_out: usize,
+    leaf_size: usize,
+    clustering: Clustering,
 }
 
-impl Index {
-    pub fn new() -> Index {
-        Index {
-            files: Vec::new()
-        }
+impl Default for IndexTreeBuilder {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
-    pub fn from_file(path: &str) -> Index {
-        let mut buf = Vec::new();
-        let mut index_read = File::open(path).unwrap();
-        index_read.read_to_end(&mut buf).unwrap();
-
-        rmp_serde::from_slice(&buf).unwrap()
+impl IndexTreeBuilder {
+    pub fn new() -> IndexTreeBuilder {
+        IndexTreeBuilder {
+            fan_out: DEFAULT_FAN_OUT,
+            leaf_size: DEFAULT_LEAF_SIZE,
+            clustering: Clustering::None,
+        }
     }
 
-    pub fn add_file(&mut self, file: FileEntry) {
-        self.files.push(file);
+    // Maximum number of shards or child nodes per node. At least 2, or the tree
+    // would never converge on a single root.
+    pub fn fan_out(mut self, fan_out: usize) -> IndexTreeBuilder {
+        self.fan_out = fan_out.max(2);
+        self
     }
 
-    pub fn files_count(&self) -> usize {
-        self.files.len()
+    // Maximum number of files per shard.
+    pub fn leaf_size(mut self, leaf_size: usize) -> IndexTreeBuilder {
+        self.leaf_size = leaf_size.max(1);
+        self
     }
 
-    pub fn save(&self, path: &str) {
-        let mut buf = Vec::new();
-        self.serialize(&mut Serializer::new(&mut buf)).unwrap();
-    
-        let mut file = File::create(path).unwrap();
-        file.write_all(&buf).unwrap();
+    pub fn clustering(mut self, clustering: Clustering) -> IndexTreeBuilder {
+        self.clustering = clustering;
+        self
     }
 
-    pub async fn search_files(&self, query: &str) -> HashSet<String> {
-        let query_trigrams = Trigram::from_str(&lowercase_alphanumeric_only(query));
+    pub fn build(&self, index: &Index, output_path: &str) -> IndexTree {
+        let mut files = index.files.clone();
 
-        let u32s: Vec<u32> = query_trigrams
-            .iter()
-            .map(|t| t.to_u32())
+        if self.clustering != Clustering::None {
+            files.sort_by(|a, b| a.file_path.split('/').cmp(b.file_path.split('/')));
+        }
+
+        // Pair each shard with its merged filter, so shards can be clustered too.
+        let shards: Vec<(Index, BloomFilter)> = self.split(&files, self.leaf_size, |file| &file.bloom_filter)
+            .into_iter()
+            .map(|batch| {
+                let filters: Vec<BloomFilter> = batch.iter().map(|file| file.bloom_filter.clone()).collect();
+                (Index { files: batch }, BloomFilter::from_filters(&filters, BLOOM_FILTER_SIZE))
+            })
             .collect();
-        
-        let query_bloom_filter = BloomFilter::new(&u32s, BLOOM_FILTER_SIZE);
 
-        let mut set = JoinSet::new();
+        let mut nodes: Vec<IndexTree> = self.split(&shards, self.fan_out, |shard| &shard.1)
+            .into_iter()
+            .map(|batch| {
+                let batch: Vec<Index> = batch.into_iter().map(|shard| shard.0).collect();
+                IndexTree::from_nodes(&batch, &[], output_path)
+            })
+            .collect();
 
-        for batch in batch_items_by_cpu_count(&self.files) {
-            let task_bloom_filter = query_bloom_filter.clone();
-            set.spawn(
-                async move {
-                    Vec::from_iter(batch
-                        .iter()
-                        .map(|file| file.clone())
-                        .filter(|file| file.bloom_filter.possibly_contains(&task_bloom_filter)))
-                });
-        }
-        
-        let mut all_matches: HashSet<String> = HashSet::new();
-    
-        while let Some(res) = set.join_next().await {
-            for item in res.unwrap() {
-                all_matches.insert(item.file_path.clone());
-            }
+        while nodes.len() > 1 {
+            nodes = self.split(&nodes, self.fan_out, |node| &node.bloom_filter)
+                .into_iter()
+                .map(|batch| IndexTree::from_nodes(&[], &batch, output_path))
+                .collect();
+        }
+
+        // No files at all gets an empty root, which matches nothing but the empty query.
+        nodes
+            .pop()
+            .unwrap_or_else(|| IndexTree::from_nodes(&[], &[], output_path))
+    }
+
+    // Starts building a tree from files as they arrive, see IndexTreeWriter.
+    pub fn writer(&self, output_path: &str) -> IndexTreeWriter {
+        IndexTreeWriter {
+            builder: self.clone(),
+            output_path: output_path.to_string(),
+            pending_files: Vec::new(),
+            levels: Vec::new(),
+        }
+    }
+
+    // Splits items into the fewest batches of at most max_batch_size items, with batch
+    // sizes differing by at most one.
+    fn split<TItem: Clone>(
+        &self,
+        items: &[TItem],
+        max_batch_size: usize,
+        bloom_filter: impl Fn(&TItem) -> &BloomFilter) -> Vec<Vec<TItem>> {
+
+        let batch_count = items.len().div_ceil(max_batch_size);
+
+        match self.clustering {
+            Clustering::Similarity => batch_items_by_similarity(items, batch_count, bloom_filter),
+            Clustering::None | Clustering::Directory => batch_items(items, batch_count),
         }
-    
-        all_matches
     }
 }
 
-#[derive(Clone, Serialize, Deserialize)]
-struct FileEntry {
-    file_path: String,
-    bloom_filter: BloomFilter,
+// Builds an IndexTree from files as they arrive, for corpora whose file entries are
+// too many to hold in memory. Files are buffered until there are enough for fan_out
+// full shards, which are then clustered, written out and replaced by the node above
+// them, so at most one batch of fan_out * leaf_size file entries is held at a time.
+// The nodes themselves, each with a bloom filter, stay in memory until the tree is
+// finished, so memory use still grows with the number of shards.
+//
+// Unlike IndexTreeBuilder::build(), the last node of each level may have fewer
+// children than the others, and clustering only sees one batch of files at a time.
+// A level left with a single node when the files run out passes it up as it is,
+// rather than under a parent of its own, so its shards end up higher than the rest.
+pub struct IndexTreeWriter {
+    builder: IndexTreeBuilder,
+    output_path: String,
+    pending_files: Vec<FileEntry>,
+
+    // Nodes waiting to be grouped under a parent, lowest level first.
+    levels: Vec<Vec<IndexTree>>,
+}
+
+impl IndexTreeWriter {
+    pub fn add_file(&mut self, file: FileEntry) {
+        self.pending_files.push(file);
+
+        if self.pending_files.len() >= self.builder.leaf_size * self.builder.fan_out {
+            self.flush_files();
+        }
+    }
+
+    pub fn finish(mut self) -> IndexTree {
+        if !self.pending_files.is_empty() {
+            self.flush_files();
+        }
+
+        // Group whatever is left on each level under one last parent, until a single
+        // node remains at the top. A lone node joins the level above as it is, since
+        // a parent with one child would only add a level to search through.
+        let mut level = 0;
+
+        while level < self.levels.len() {
+            let mut nodes = std::mem::take(&mut self.levels[level]);
+
+            if level == self.levels.len() - 1 && nodes.len() == 1 {
+                return nodes.pop().unwrap();
+            }
+
+            match nodes.len() {
+                0 => {},
+                1 => self.add_node(level + 1, nodes.pop().unwrap()),
+                _ => self.add_node(level + 1, IndexTree::from_nodes(&[], &nodes, &self.output_path)),
+            }
+
+            level += 1;
+        }
+
+        // No files at all gets an empty root, which matches nothing but the empty query.
+        IndexTree::from_nodes(&[], &[], &self.output_path)
+    }
+
+    fn flush_files(&mut self) {
+        let mut files = std::mem::take(&mut self.pending_files);
+
+        if self.builder.clustering != Clustering::None {
+            files.sort_by(|a, b| a.file_path.split('/').cmp(b.file_path.split('/')));
+        }
+
+        let shards: Vec<Index> = self.builder.split(&files, self.builder.leaf_size, |file| &file.bloom_filter)
+            .into_iter()
+            .map(|batch| Index { files: batch })
+            .collect();
+
+        self.add_node(0, IndexTree::from_nodes(&shards, &[], &self.output_path));
+    }
+
+    fn add_node(&mut self, level: usize, node: IndexTree) {
+        if self.levels.len() <= level {
+            self.levels.push(Vec::new());
+        }
+
+        self.levels[level].push(node);
+
+        if self.levels[level].len() >= self.builder.fan_out {
+            let nodes = std::mem::take(&mut self.levels[level]);
+            self.add_node(level + 1, IndexTree::from_nodes(&[], &nodes, &self.output_path));
+        }
+    }
+}
+
+// What IndexTree::update_files() did.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct UpdateStats {
+    // Files that were in the index and were indexed again.
+    pub reindexed_count: usize,
+    pub added_count: usize,
+    pub removed_count: usize,
+    pub shards_written: usize,
+}
+
+#[derive(Default)]
+struct NodeUpdate {
+    // New entries for files, by path, or None to remove them. Taken as they're found.
+    updates: HashMap<String, Option<FileEntry>>,
+
+    // Files to add to existing shards, by shard file name.
+    additions: HashMap<String, Vec<FileEntry>>,
+
+    // Files for a shard to create at a node, when there's nowhere else to put them.
+    new_shard: Option<(Vec<usize>, Vec<FileEntry>)>,
+
+    // Nodes holding shards that files are added to.
+    addition_locations: Vec<Vec<usize>>,
+
+    // The first path of every shard seen, with where the shard is, to place new files
+    // next to their neighbors.
+    shard_starts: Vec<(String, Vec<usize>, String)>,
+
+    stats: UpdateStats,
+}
+
+impl NodeUpdate {
+    // Whether the node at 'location' could have anything to do. Once every update has
+    // been found, only nodes on the way to additions are.
+    fn visits(&self, location: &[usize]) -> bool {
+        !self.updates.is_empty() ||
+            self.addition_locations.iter().any(|addition_location| addition_location.starts_with(location))
+    }
+}
+
+// Shape of an IndexTree and how full its bloom filters are at each level.
+pub struct IndexTreeStats {
+    pub files_count: usize,
+    pub shards_count: usize,
+
+    // Root first. The last level is the per-file filters.
+    pub levels: Vec<LevelStats>,
+
+    // Number of files decoded from each encoding, by encoding name. Binary files
+    // are counted as "binary" instead.
+    pub encodings: BTreeMap<&'static str, usize>,
+
+    // Number of files in each language, by language name. Files in no language we
+    // know of are counted as "other".
+    pub languages: BTreeMap<&'static str, usize>,
+}
+
+pub struct LevelStats {
+    fill_ratios: Vec<f32>,
+}
+
+impl LevelStats {
+    pub fn filters_count(&self) -> usize {
+        self.fill_ratios.len()
+    }
+
+    pub fn average_fill_ratio(&self) -> f32 {
+        if self.fill_ratios.is_empty() {
+            return 0f32;
+        }
+
+        self.fill_ratios.iter().sum::<f32>() / self.fill_ratios.len() as f32
+    }
+
+    // Estimated fraction of this level's filters that a query made up of the given
+    // number of distinct trigrams would get past. Each trigram sets one bit, so
+    // a filter matches with probability of about fill ^ trigrams. The lower this is
+    // towards the root, the more of the tree gets pruned.
+    pub fn estimated_pass_rate(&self, query_trigrams: usize) -> f32 {
+        if self.fill_ratios.is_empty() {
+            return 0f32;
+        }
+
+        self.fill_ratios
+            .iter()
+            .map(|fill_ratio| fill_ratio.powi(query_trigrams as i32))
+            .sum::<f32>() / self.fill_ratios.len() as f32
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LazyIndex {
+    // Shard file name relative to the index directory, so that the index
+    // directory can be moved or downloaded as a whole.
+    file_name: String,
+
+    // Where the index directory is right now. Filled in on load.
+    #[serde(skip)]
+    directory: String,
+}
+
+impl LazyIndex {
+    pub fn from_file(path: &str) -> LazyIndex {
+        let path = Path::new(path);
+
+        LazyIndex {
+            file_name: path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+            directory: path.parent().unwrap_or(Path::new("")).to_string_lossy().to_string(),
+        }
+    }
+
+    pub fn from_index(index: &Index, output_path: &str) -> LazyIndex {
+        let file_name = Uuid::new_v4().to_string();
+        index.save(&format!("{}/{}", output_path, file_name));
+
+        LazyIndex {
+            file_name,
+            directory: output_path.to_string(),
+        }
+    }
+
+    pub fn get(&self) -> Index {
+        Index::from_file(&self.path())
+    }
+
+    fn path(&self) -> String {
+        format!("{}/{}", self.directory, self.file_name)
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct IndexOptions {
+    // Folders to leave out of the index, e.g. the index folder itself when
+    // it lives inside the source tree.
+    pub excluded_directories: Vec<String>,
+
+    // Assumed for files without a BOM that aren't valid UTF-8.
+    pub default_encoding: Encoding,
+
+    // What to do with files that look binary.
+    pub binary_handling: BinaryHandling,
+
+    // Files bigger than this, in bytes, are left out of the index.
+    pub max_file_size: Option<u64>,
+
+    // See WalkOptions.
+    pub follow_symlinks: bool,
+    pub one_file_system: bool,
+
+    // How many threads walk folders and how many files are indexed at once.
+    // Defaults to one per CPU.
+    pub jobs: Option<usize>,
+
+    // Index these files instead of walking the folder, e.g. the ones git tracks as
+    // tracked_files() lists them, so that untracked build outputs are left out.
+    // Resolved up front, so that an unreadable git index is reported before
+    // indexing starts.
+    pub git_tracked_files: Option<Arc<Vec<String>>>,
+
+    // Index the files of a commit, read from the git object database, instead of
+    // the folder, e.g. to index a branch that isn't checked out. Paths are still
+    // relative to the folder, which has to be in the repository. Listed up front, so
+    // that a commit whose objects can't be read is reported before indexing starts.
+    pub git_commit_files: Option<Arc<CommitFiles>>,
+}
+
+// What happened while indexing a folder.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct IndexingStats {
+    // Files that made it into the index.
+    pub files_count: usize,
+
+    // Files that looked binary, however they were handled.
+    pub binary_files_count: usize,
+
+    // Files left out of the index, e.g. binaries when skipping them.
+    pub skipped_files_count: usize,
+
+    // Files left out for being bigger than the maximum file size.
+    pub too_large_files_count: usize,
+}
+
+// How a single file was handled.
+enum IndexedFile {
+    Contents(FileEntry),
+    BinaryName(FileEntry),
+    BinaryContents(FileEntry),
+    SkippedBinary,
+    SkippedTooLarge,
+}
+
+pub async fn parallel_index_directory(path: &str, options: &IndexOptions) -> (Index, IndexingStats) {
+    let mut index = Index::new();
+
+    let progress = Arc::new(IndexingProgress::default());
+    let stats = stream_index_directory(path, options, &progress, |entry| index.add_file(entry)).await;
+
+    // Files arrive in whatever order the walk and indexing finish them in.
+    index.files.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+
+    (index, stats)
+}
+
+// Indexes a folder, handing each file's entry to 'on_entry' as soon as it's ready
+// instead of collecting them, e.g. to feed an IndexTreeWriter. Entries arrive in no
+// particular order. 'progress' is kept up to date as indexing goes.
+pub async fn stream_index_directory(
+    path: &str,
+    options: &IndexOptions,
+    progress: &Arc<IndexingProgress>,
+    mut on_entry: impl FnMut(FileEntry)) -> IndexingStats {
+
+    let walk_options = walk_options(options);
+
+    let jobs = jobs_count(options.jobs);
+
+    // Files are indexed as the walk finds them. The walk and indexing both block on
+    // the file system and keep CPUs busy, so they run on the blocking pool, leaving
+    // the runtime free for coordination. Bounded channels keep the walk from
+    // getting too far ahead of indexing, and indexing from getting too far ahead
+    // of 'on_entry'.
+    let (sender, receiver) = std::sync::mpsc::sync_channel::<String>(DISCOVERED_FILES_BUFFER);
+    let receiver = Arc::new(Mutex::new(receiver));
+
+    let commit_files = options.git_commit_files.clone();
+
+    let root_path = path.to_string();
+    let walk_progress = progress.clone();
+    let walk_commit_files = commit_files.clone();
+    let git_tracked_files = options.git_tracked_files.clone();
+    let walk = tokio::task::spawn_blocking(move || {
+        let on_file = |file| {
+            walk_progress.file_discovered();
+
+            // Only fails if indexing has stopped listening.
+            let _ = sender.send(file);
+        };
+
+        if let Some(commit_files) = walk_commit_files {
+            commit_files.paths().cloned().for_each(on_file);
+        } else if let Some(git_tracked_files) = git_tracked_files {
+            git_tracked_files.iter().cloned().for_each(on_file);
+        } else {
+            walk_directory_parallel(&root_path, &walk_options, jobs, on_file);
+        }
+
+        walk_progress.walk_finished();
+    });
+
+    let (indexed_sender, mut indexed_receiver) = tokio::sync::mpsc::channel::<IndexedFile>(DISCOVERED_FILES_BUFFER);
+
+    let mut set = JoinSet::new();
+
+    for _ in 0..jobs {
+        let receiver = receiver.clone();
+        let indexed_sender = indexed_sender.clone();
+        let root_path = path.to_string();
+        let task_options = options.clone();
+        let task_progress = progress.clone();
+        let task_commit_files = commit_files.clone();
+
+        set.spawn_blocking(move || {
+            loop {
+                // The lock is only held while waiting for the next file.
+                let Ok(file) = receiver.lock().unwrap().recv() else {
+                    break;
+                };
+
+                let indexed = bloom_index_file(&root_path, &file, &task_options, &task_progress, task_commit_files.as_deref());
+                task_progress.file_indexed();
+
+                // Files that can't be read are left out.
+                match indexed {
+                    Ok(indexed) => {
+                        if indexed_sender.blocking_send(indexed).is_err() {
+                            break;
+                        }
+                    },
+                    Err(_) => task_progress.error(),
+                }
+            }
+        });
+    }
+
+    // The channel closes once every indexing task is done with its sender.
+    drop(indexed_sender);
+
+    let mut stats = IndexingStats::default();
+
+    while let Some(indexed) = indexed_receiver.recv().await {
+        match indexed {
+            IndexedFile::Contents(entry) => {
+                stats.files_count += 1;
+                on_entry(entry);
+            },
+            IndexedFile::BinaryName(entry) | IndexedFile::BinaryContents(entry) => {
+                stats.files_count += 1;
+                stats.binary_files_count += 1;
+                on_entry(entry);
+            },
+            IndexedFile::SkippedBinary => {
+                stats.binary_files_count += 1;
+                stats.skipped_files_count += 1;
+            },
+            IndexedFile::SkippedTooLarge => {
+                stats.too_large_files_count += 1;
+                stats.skipped_files_count += 1;
+            },
+        }
+    }
+
+    while let Some(res) = set.join_next().await {
+        res.unwrap();
+    }
+
+    walk.await.unwrap();
+
+    stats
+}
+
+fn walk_options(options: &IndexOptions) -> WalkOptions {
+    WalkOptions {
+        excluded_directories: options.excluded_directories
+            .iter()
+            .filter_map(|directory| std::fs::canonicalize(directory).ok())
+            .collect(),
+        follow_symlinks: options.follow_symlinks,
+        one_file_system: options.one_file_system,
+    }
+}
+
+// Reads the file from 'commit_files' if given, otherwise from disk.
+fn bloom_index_file(
+    root_path: &str,
+    file_path: &str,
+    options: &IndexOptions,
+    progress: &IndexingProgress,
+    commit_files: Option<&CommitFiles>) -> Result<IndexedFile, std::io::Error> {
+
+    let portable_path = to_portable_path(root_path, file_path);
+    let binary_handling = options.binary_handling;
+
+    // Known binary types aren't worth reading at all, unless they're wanted as text.
+    if binary_handling != BinaryHandling::Text && has_binary_extension(file_path) {
+        return Ok(match binary_handling {
+            BinaryHandling::NameOnly => IndexedFile::BinaryName(name_only_entry(portable_path)),
+            _ => IndexedFile::SkippedBinary,
+        });
+    }
+
+    // Blobs have to be inflated whole anyway.
+    let (mut file, file_size): (Box<dyn Read>, u64) = match commit_files {
+        Some(commit_files) => {
+            let blob = commit_files.read(file_path)?;
+            let blob_size = blob.len() as u64;
+            (Box::new(Cursor::new(blob)), blob_size)
+        },
+        None => {
+            let file = File::open(Path::new(file_path))?;
+            let file_size = file.metadata()?.len();
+            (Box::new(file), file_size)
+        },
+    };
+
+    if options.max_file_size.is_some_and(|max_file_size| file_size > max_file_size) {
+        return Ok(IndexedFile::SkippedTooLarge);
+    }
+
+    // Read a block up front to tell whether the file is binary and how it's encoded,
+    // then stream the rest, so huge files don't have to fit in memory. One byte more
+    // than detection looks at, so it can tell whether the file goes on.
+    let mut first_block = Vec::new();
+    (&mut file).take(DETECTION_LENGTH as u64 + 1).read_to_end(&mut first_block)?;
+    progress.bytes_read(first_block.len());
+
+    let binary = has_binary_extension(file_path) || looks_binary(&first_block);
+
+    match binary_handling {
+        BinaryHandling::Skip if binary => return Ok(IndexedFile::SkippedBinary),
+        BinaryHandling::NameOnly if binary => return Ok(IndexedFile::BinaryName(name_only_entry(portable_path))),
+        _ => {},
+    }
+
+    let (encoding, bom_length) = detect_encoding(&first_block, options.default_encoding);
+
+    let mut decoder = StreamDecoder::new(encoding);
+    let mut extractor = TrigramExtractor::new();
+
+    // Binaries indexed as text aren't written in anything.
+    let language = if binary { None } else { Language::detect(file_path, &first_block) };
+
+    let mut symbol_extractor = language
+        .filter(|language| SymbolExtractor::supports(*language))
+        .map(SymbolExtractor::new);
+
+    let mut on_char = |c| {
+        extractor.push_char(c);
+
+        if let Some(symbol_extractor) = &mut symbol_extractor {
+            symbol_extractor.push_char(c);
+        }
+    };
+
+    decoder.decode(&first_block[bom_length..], &mut on_char);
+
+    let mut buffer = first_block;
+    buffer.resize(READ_CHUNK_LENGTH, 0);
+
+    loop {
+        let read_length = match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read_length) => read_length,
+            Err(error) if error.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        };
+
+        progress.bytes_read(read_length);
+        decoder.decode(&buffer[..read_length], &mut on_char);
+    }
+
+    decoder.finish(&mut on_char);
+
+    let bloom_filter = extractor.bloom_filter(BLOOM_FILTER_SIZE);
+
+    let entry = FileEntry {
+        file_path: portable_path,
+        bloom_filter,
+        encoding,
+        binary,
+        name_only: false,
+        language,
+        symbols: symbol_extractor.map(SymbolExtractor::finish).unwrap_or_default(),
+    };
+
+    Ok(if binary { IndexedFile::BinaryContents(entry) } else { IndexedFile::Contents(entry) })
+}
+
+// An entry for a binary file that can be found by its path but not its contents.
+fn name_only_entry(portable_path: String) -> FileEntry {
+    FileEntry {
+        bloom_filter: BloomFilter::new(&trigram_u32s(&portable_path), BLOOM_FILTER_SIZE),
+        file_path: portable_path,
+        encoding: Encoding::default(),
+        binary: true,
+        name_only: true,
+        language: None,
+        symbols: Vec::new(),
+    }
+}
+
+// Store paths relative to the indexed folder, with '/' separators, so the
+// index stays valid if the source folder is moved or checked out on another
+// machine. See resolve_indexed_path() for the reverse.
+fn to_portable_path(root_path: &str, file_path: &str) -> String {
+    let relative_path = Path::new(file_path)
+        .strip_prefix(root_path)
+        .unwrap_or(Path::new(file_path));
+
+    relative_path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy().to_string()),
+            _ => None,
+        })
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+// Maps a path as stored in the index to where the file lives under the
+// given copy of the source folder.
+pub fn resolve_indexed_path(source_path: &str, indexed_path: &str) -> PathBuf {
+    let mut resolved = PathBuf::from(source_path);
+
+    for part in indexed_path.split('/').filter(|part| !part.is_empty()) {
+        resolved.push(part);
+    }
+
+    resolved
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Index {
+    files: Vec<FileEntry>
+}
+
+impl Index {
+    pub fn new() -> Index {
+        Index {
+            files: Vec::new()
+        }
+    }
+
+    pub fn from_file(path: &str) -> Index {
+        let mut buf = Vec::new();
+        let mut index_read = File::open(path).unwrap();
+        index_read.read_to_end(&mut buf).unwrap();
+
+        rmp_serde::from_slice(&buf).unwrap()
+    }
+
+    pub fn add_file(&mut self, file: FileEntry) {
+        self.files.push(file);
+    }
+
+    pub fn files_count(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn save(&self, path: &str) {
+        let mut buf = Vec::new();
+        self.serialize(&mut Serializer::new(&mut buf)).unwrap();
+    
+        let mut file = File::create(path).unwrap();
+        file.write_all(&buf).unwrap();
+    }
+
+    // Checking bloom filters is CPU bound, so batches are checked on the blocking pool.
+    pub async fn search_files(&self, query: &str, jobs: Option<usize>) -> HashSet<String> {
+        let query_filters = Arc::new(QueryFilters::new(&Query::literal(query)));
+
+        let mut set = JoinSet::new();
+
+        for batch in batch_items(&self.files, jobs_count(jobs)) {
+            let task_query_filters = query_filters.clone();
+            set.spawn_blocking(move || {
+                Vec::from_iter(batch
+                    .into_iter()
+                    .filter(|file| task_query_filters.possibly_matches(&file.bloom_filter))
+                    .map(|file| file.file_path))
+            });
+        }
+
+        let mut all_matches: HashSet<String> = HashSet::new();
+
+        while let Some(res) = set.join_next().await {
+            all_matches.extend(res.unwrap());
+        }
+
+        all_matches
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FileEntry {
+    file_path: String,
+    bloom_filter: BloomFilter,
+
+    // What the file was decoded from when it was indexed.
+    encoding: Encoding,
+
+    // True if the file looked binary. Unless binaries were indexed as text, only
+    // its path is in the bloom filter.
+    binary: bool,
+
+    // True if only the path is in the bloom filter, so the contents can't be searched.
+    name_only: bool,
+
+    // What the file is written in, if we could tell.
+    language: Option<Language>,
+
+    // Definitions of functions, methods and types, for languages we know.
+    symbols: Vec<Symbol>,
+}
+
+impl FileEntry {
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    pub fn is_binary(&self) -> bool {
+        self.binary
+    }
+
+    pub fn is_name_only(&self) -> bool {
+        self.name_only
+    }
+
+    pub fn language(&self) -> Option<Language> {
+        self.language
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+    use std::sync::Arc;
+
+    use uuid::Uuid;
+
+    use crate::clustering::Clustering;
+    use crate::language::LanguageSet;
+    use crate::progress::IndexingProgress;
+    use crate::query::Query;
+    use crate::symbols::{MatchRank, SymbolKind};
+    use crate::text_scraping::{stream_scrape_files, Match, ScrapeOptions};
+
+    use super::{resolve_indexed_path, BinaryHandling, Encoding, IndexOptions, IndexingStats, to_portable_path, BloomFilter, FileEntry, Index, IndexTree, IndexTreeBuilder, SearchResults, UpdateStats, BLOOM_FILTER_SIZE, parallel_index_directory, stream_index_directory};
+
+    fn temp_directory() -> String {
+        let directory = format!("{}/rust-indexer-test-{}", std::env::temp_dir().display(), Uuid::new_v4());
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn file_entry(file_path: &str) -> FileEntry {
+        FileEntry {
+            file_path: file_path.to_string(),
+            bloom_filter: BloomFilter::new(&[], BLOOM_FILTER_SIZE),
+            encoding: Encoding::Utf8,
+            binary: false,
+            name_only: false,
+            language: None,
+            symbols: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn index_portable_paths() {
+        assert_eq!("src/main.rs", to_portable_path("/repo", "/repo/src/main.rs"));
+        assert_eq!("src/main.rs", to_portable_path("/repo/", "/repo/src/main.rs"));
+        assert_eq!("src/main.rs", to_portable_path(".", "./src/main.rs"));
+        assert_eq!("main.rs", to_portable_path("repo", "repo/main.rs"));
+    }
+
+    #[test]
+    fn index_resolve_paths() {
+        assert_eq!(
+            std::path::Path::new("/other/place").join("src").join("main.rs"),
+            resolve_indexed_path("/other/place", "src/main.rs"));
+    }
+
+    #[test]
+    fn index_directory_is_relocatable() {
+        let original_directory = temp_directory();
+
+        let mut index = Index::new();
+        for i in 0..8 {
+            index.add_file(file_entry(&format!("src/{}.rs", i)));
+        }
+
+        let tree = IndexTree::from_index(&index, &original_directory);
+        tree.save(&format!("{}/root.dat", original_directory));
+
+        // Move the whole index somewhere else, as if it was downloaded.
+        let moved_directory = format!("{}-moved", original_directory);
+        std::fs::rename(&original_directory, &moved_directory).unwrap();
+
+        let moved_tree = IndexTree::from_file(&format!("{}/root.dat", moved_directory));
+        let files = moved_tree.search_files("").files;
+
+        assert_eq!(8, files.len());
+        assert!(files.contains("src/0.rs"));
+        assert!(files.contains("src/7.rs"));
+
+        std::fs::remove_dir_all(&moved_directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn index_decodes_other_encodings() {
+        let source_directory = temp_directory();
+
+        let mut utf16_bytes = vec![0xff, 0xfe];
+        utf16_bytes.extend("fn résumé_parser()".encode_utf16().flat_map(|unit| unit.to_le_bytes()));
+        std::fs::write(format!("{}/strings.rc", source_directory), utf16_bytes).unwrap();
+        std::fs::write(format!("{}/legacy.c", source_directory), b"/* caf\xe9 */ int legacy_handler;").unwrap();
+        std::fs::write(format!("{}/main.rs", source_directory), "fn main() {}").unwrap();
+
+        let (index, _) = super::parallel_index_directory(&source_directory, &IndexOptions::default()).await;
+
+        let mut encodings: Vec<(String, Encoding)> = index.files
+            .iter()
+            .map(|file| (file.file_path.clone(), file.encoding()))
+            .collect();
+        encodings.sort_by(|a, b| a.0.cmp(&b.0));
+
+        assert_eq!(vec![
+            ("legacy.c".to_string(), Encoding::Latin1),
+            ("main.rs".to_string(), Encoding::Utf8),
+            ("strings.rc".to_string(), Encoding::Utf16Le),
+        ], encodings);
+
+        // Their contents are searchable too.
+        let index_directory = temp_directory();
+        let tree = IndexTree::from_index(&index, &index_directory);
+        assert!(tree.search_files("sumé_PARSER").files.contains("strings.rc"));
+        assert!(tree.search_files("legacy_handler").files.contains("legacy.c"));
+
+        std::fs::remove_dir_all(&source_directory).unwrap();
+        std::fs::remove_dir_all(&index_directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn index_binary_handling() {
+        let source_directory = temp_directory();
+        std::fs::write(format!("{}/logo.png", source_directory), b"not really a png").unwrap();
+        std::fs::write(format!("{}/tool", source_directory), b"\x7fELF\x00\x00 embedded_symbol").unwrap();
+        std::fs::write(format!("{}/main.rs", source_directory), "fn main() {}").unwrap();
+
+        let index_with = |binary_handling: BinaryHandling| {
+            let options = IndexOptions { binary_handling, ..Default::default() };
+            let source_directory = source_directory.clone();
+            async move { super::parallel_index_directory(&source_directory, &options).await }
+        };
+
+        let (index, stats) = index_with(BinaryHandling::Skip).await;
+        assert_eq!(IndexingStats { files_count: 1, binary_files_count: 2, skipped_files_count: 2, ..Default::default() }, stats);
+        assert_eq!("main.rs", index.files[0].file_path);
+
+        let index_directory = temp_directory();
+
+        // Name only binaries can be found by path, not contents.
+        let (index, stats) = index_with(BinaryHandling::NameOnly).await;
+        assert_eq!(IndexingStats { files_count: 3, binary_files_count: 2, skipped_files_count: 0, ..Default::default() }, stats);
+        assert_eq!(2, index.files.iter().filter(|file| file.is_binary()).count());
+
+        let tree = IndexTree::from_index(&index, &index_directory);
+        assert!(tree.search_files("logo.png").files.contains("logo.png"));
+        assert!(!tree.search_files("embedded_symbol").files.contains("tool"));
+
+        // Scraping matches their paths instead of reading them.
+        let results = tree.search_files("logo");
+        assert_eq!(HashSet::from(["logo.png".to_string()]), results.name_only_files);
+
+        let logo_path = resolve_indexed_path(&source_directory, "logo.png").to_string_lossy().to_string();
+        let name_only_paths = HashMap::from([(logo_path.clone(), "logo.png".to_string())]);
+
+        let mut matches: Vec<Match> = Vec::new();
+        stream_scrape_files(std::slice::from_ref(&logo_path), &name_only_paths, &Query::literal("logo"), &ScrapeOptions::default(), |found| {
+            matches.extend_from_slice(found);
+        }).await;
+
+        assert_eq!(1, matches.len());
+        assert!(matches[0].in_path);
+        assert_eq!((logo_path.as_str(), "logo.png", 0, 4), (matches[0].file_path.as_str(), matches[0].text.as_str(), matches[0].context_offset, matches[0].context_length));
+
+        let (index, stats) = index_with(BinaryHandling::Text).await;
+        assert_eq!(IndexingStats { files_count: 3, binary_files_count: 2, skipped_files_count: 0, ..Default::default() }, stats);
+
+        let tree = IndexTree::from_index(&index, &index_directory);
+        assert!(tree.search_files("embedded_symbol").files.contains("tool"));
+        assert!(tree.search_files("embedded_symbol").name_only_files.is_empty());
+
+        std::fs::remove_dir_all(&source_directory).unwrap();
+        std::fs::remove_dir_all(&index_directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn index_large_files() {
+        let source_directory = temp_directory();
+
+        // Several read chunks' worth, with a multi-byte character across each
+        // chunk boundary and something to find at the very end.
+        let mut large_text = format!("x{}", "é".repeat(3 * super::READ_CHUNK_LENGTH / 2));
+        large_text.push_str("Straße end_of_large_file");
+        std::fs::write(format!("{}/large.log", source_directory), &large_text).unwrap();
+        std::fs::write(format!("{}/small.rs", source_directory), "fn small() {}").unwrap();
+
+        let (index, stats) = super::parallel_index_directory(&source_directory, &IndexOptions::default()).await;
+        assert_eq!(2, stats.files_count);
+
+        let index_directory = temp_directory();
+        let tree = IndexTree::from_index(&index, &index_directory);
+        assert!(tree.search_files("strasse END_OF_LARGE_FILE").files.contains("large.log"));
+
+        let options = IndexOptions { max_file_size: Some(1024), ..Default::default() };
+        let (index, stats) = super::parallel_index_directory(&source_directory, &options).await;
+
+        assert_eq!(IndexingStats { files_count: 1, skipped_files_count: 1, too_large_files_count: 1, ..Default::default() }, stats);
+        assert_eq!("small.rs", index.files[0].file_path);
+
+        std::fs::remove_dir_all(&source_directory).unwrap();
+        std::fs::remove_dir_all(&index_directory).unwrap();
+    }
+
+    // Returns the depth of every shard in the tree.
+    fn shard_depths(node: &IndexTree, depth: usize, depths: &mut Vec<usize>) {
+        for _ in &node.child_indexes {
+            depths.push(depth);
+        }
+
+        for child_node in &node.child_nodes {
+            shard_depths(child_node, depth + 1, depths);
+        }
+    }
+
+    fn check_no_lone_child_nodes(node: &IndexTree) {
+        assert!(node.child_nodes.len() != 1 || !node.child_indexes.is_empty(), "node with a single child node");
+
+        for child_node in &node.child_nodes {
+            check_no_lone_child_nodes(child_node);
+        }
+    }
+
+    fn check_tree_shape(node: &IndexTree, fan_out: usize, leaf_size: usize) {
+        let children = node.child_indexes.len() + node.child_nodes.len();
+        assert!(children <= fan_out, "{} children with fan-out {}", children, fan_out);
+
+        // Nodes hold either shards or other nodes, never both.
+        assert!(node.child_indexes.is_empty() || node.child_nodes.is_empty());
+
+        let mut files_count = 0;
+
+        for index in &node.child_indexes {
+            let shard_size = index.get().files_count();
+            assert!(shard_size > 0 && shard_size <= leaf_size);
+            files_count += shard_size;
+        }
+
+        for child_node in &node.child_nodes {
+            check_tree_shape(child_node, fan_out, leaf_size);
+            files_count += child_node.files_count;
+        }
+
+        assert_eq!(files_count, node.files_count);
+    }
+
+    // Property test over a grid of shapes: every indexed file is reachable, the
+    // counts are exact and the tree is balanced, for any number of files.
+    #[test]
+    fn index_tree_builder_properties() {
+        for files_count in [0, 1, 2, 3, 4, 5, 7, 8, 9, 16, 17, 31, 64, 100] {
+            for fan_out in 2..=5 {
+                for leaf_size in 1..=4 {
+                    let directory = temp_directory();
+
+                    let mut index = Index::new();
+                    for i in 0..files_count {
+                        index.add_file(file_entry(&format!("{}.rs", i)));
+                    }
+
+                    // Cycle through the clustering modes rather than multiplying the grid by them.
+                    let clustering = [Clustering::None, Clustering::Directory, Clustering::Similarity][(fan_out + leaf_size) % 3];
+
+                    let tree = IndexTreeBuilder::new()
+                        .fan_out(fan_out)
+                        .leaf_size(leaf_size)
+                        .clustering(clustering)
+                        .build(&index, &directory);
+
+                    assert_eq!(files_count, tree.files_count());
+
+                    // Everything matches the empty query, so this reaches every file.
+                    let files = tree.search_files("").files;
+                    assert_eq!(files_count, files.len());
+                    for i in 0..files_count {
+                        assert!(files.contains(&format!("{}.rs", i)));
+                    }
+
+                    check_tree_shape(&tree, fan_out, leaf_size);
+
+                    let mut depths = Vec::new();
+                    shard_depths(&tree, 0, &mut depths);
+                    assert!(depths.windows(2).all(|pair| pair[0] == pair[1]), "unbalanced: {:?}", depths);
+                    assert_eq!(files_count.div_ceil(leaf_size), depths.len());
+
+                    std::fs::remove_dir_all(&directory).unwrap();
+                }
+            }
+        }
+    }
+
+    // Same grid for trees built from a stream of files: not every node is full, but
+    // everything is still reachable and no node has a lone child node.
+    #[test]
+    fn index_tree_writer_properties() {
+        for files_count in [0, 1, 2, 3, 4, 5, 7, 8, 9, 16, 17, 31, 64, 100] {
+            for fan_out in 2..=5 {
+                for leaf_size in 1..=4 {
+                    let directory = temp_directory();
+
+                    let clustering = [Clustering::None, Clustering::Directory, Clustering::Similarity][(fan_out + leaf_size) % 3];
+
+                    let mut writer = IndexTreeBuilder::new()
+                        .fan_out(fan_out)
+                        .leaf_size(leaf_size)
+                        .clustering(clustering)
+                        .writer(&directory);
+
+                    for i in 0..files_count {
+                        writer.add_file(file_entry(&format!("{}.rs", i)));
+                    }
+
+                    let tree = writer.finish();
+
+                    assert_eq!(files_count, tree.files_count());
+
+                    let files = tree.search_files("").files;
+                    assert_eq!(files_count, files.len());
+                    for i in 0..files_count {
+                        assert!(files.contains(&format!("{}.rs", i)));
+                    }
+
+                    check_tree_shape(&tree, fan_out, leaf_size);
+
+                    check_no_lone_child_nodes(&tree);
+
+                    let mut depths = Vec::new();
+                    shard_depths(&tree, 0, &mut depths);
+                    assert_eq!(files_count.div_ceil(leaf_size), depths.len());
+
+                    // No deeper than a balanced tree of the same shards.
+                    let mut height = 0;
+                    while fan_out.pow(height) < depths.len() {
+                        height += 1;
+                    }
+                    assert!(depths.iter().all(|depth| *depth <= height as usize), "too deep: {:?}", depths);
+
+                    std::fs::remove_dir_all(&directory).unwrap();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn index_tree_writer_bounded_buffers() {
+        let directory = temp_directory();
+        let (fan_out, leaf_size) = (3, 4);
+
+        let mut writer = IndexTreeBuilder::new().fan_out(fan_out).leaf_size(leaf_size).writer(&directory);
+
+        // Files are written out batch by batch, and each level only holds the nodes
+        // still waiting for siblings.
+        for i in 0..1000 {
+            writer.add_file(file_entry(&format!("{}.rs", i)));
+
+            assert!(writer.pending_files.len() < fan_out * leaf_size);
+            assert!(writer.levels.iter().all(|level| level.len() < fan_out));
+        }
+
+        assert_eq!(1000, writer.finish().files_count());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn index_tree_writer_moves_shards() {
+        let source_directory = temp_directory();
+        for i in 0..10 {
+            std::fs::write(format!("{}/{}.rs", source_directory, i), format!("fn function_{}() {{}}", i)).unwrap();
+        }
+
+        let index_directory = temp_directory();
+        let staging_directory = temp_directory();
+
+        let mut writer = IndexTreeBuilder::new().writer(&staging_directory);
+        let progress = Arc::new(IndexingProgress::default());
+        let stats = stream_index_directory(&source_directory, &IndexOptions::default(), &progress, |entry| writer.add_file(entry)).await;
+        let mut tree = writer.finish();
+
+        assert_eq!(10, stats.files_count);
+
+        let progress = progress.snapshot();
+        assert_eq!(10, progress.files_discovered);
+        assert_eq!(10, progress.files_indexed);
+        assert_eq!(10 * "fn function_0() {}".len() as u64, progress.bytes_processed);
+        assert_eq!(0, progress.errors);
+        assert!(progress.walk_finished);
+
+        tree.move_shards(&index_directory).unwrap();
+        assert_eq!(0, std::fs::read_dir(&staging_directory).unwrap().count());
+
+        tree.save(&format!("{}/root.dat", index_directory));
+        let tree = IndexTree::from_file(&format!("{}/root.dat", index_directory));
+
+        assert_eq!(10, tree.files_count());
+        assert!(tree.search_files("function_7").files.contains("7.rs"));
+
+        std::fs::remove_dir_all(&source_directory).unwrap();
+        std::fs::remove_dir_all(&index_directory).unwrap();
+        std::fs::remove_dir_all(&staging_directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn index_tree_update_files() {
+        let source_directory = temp_directory();
+        std::fs::create_dir_all(format!("{}/src", source_directory)).unwrap();
+        for i in 0..20 {
+            std::fs::write(format!("{}/src/{:02}.rs", source_directory, i), format!("fn original_{}() {{}}", i)).unwrap();
+        }
+
+        let index_directory = temp_directory();
+        let (index, _) = parallel_index_directory(&source_directory, &IndexOptions::default()).await;
+        let mut tree = IndexTreeBuilder::new().fan_out(3).leaf_size(4).build(&index, &index_directory);
+
+        let mut shards_before = HashSet::new();
+        tree.collect_shard_names(&mut shards_before);
+
+        std::fs::write(format!("{}/src/03.rs", source_directory), "fn modified_three() {}").unwrap();
+        std::fs::remove_file(format!("{}/src/15.rs", source_directory)).unwrap();
+        std::fs::write(format!("{}/src/07b.rs", source_directory), "fn added_next_to_seven() {}").unwrap();
+
+        let paths: Vec<String> = ["src/03.rs", "src/15.rs", "src/07b.rs", "src/never_existed.rs"]
+            .iter()
+            .map(|path| path.to_string())
+            .collect();
+        let stats = tree.update_files(&source_directory, &paths, &IndexOptions::default(), &index_directory);
+
+        assert_eq!(UpdateStats { reindexed_count: 1, added_count: 1, removed_count: 1, shards_written: 3 }, stats);
+        assert_eq!(20, tree.files_count());
+
+        assert!(tree.search_files("modified_three").files.contains("src/03.rs"));
+        assert!(!tree.search_files("original_3").files.contains("src/03.rs"));
+        assert!(!tree.search_files("").files.contains("src/15.rs"));
+        assert!(tree.search_files("added_next_to_seven").files.contains("src/07b.rs"));
+        assert!(tree.search_files("original_12").files.contains("src/12.rs"));
+
+        // Only the shards that changed were replaced.
+        let mut shards_after = HashSet::new();
+        tree.collect_shard_names(&mut shards_after);
+        assert_eq!(3, shards_after.difference(&shards_before).count());
+        assert_eq!(shards_before.len(), shards_after.len());
+
+        // Counts up the tree still add up.
+        check_tree_shape(&tree, 3, 5);
+
+        std::fs::remove_dir_all(&source_directory).unwrap();
+        std::fs::remove_dir_all(&index_directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn index_tree_find_symbols() {
+        let source_directory = temp_directory();
+        std::fs::write(format!("{}/server.rs", source_directory), "struct Server;\n\nimpl Server {\n    fn start(&self) {}\n}\n").unwrap();
+        std::fs::write(format!("{}/tool.py", source_directory), "def start_server():\n    pass\n").unwrap();
+        std::fs::write(format!("{}/notes.txt", source_directory), "fn start() {}\n").unwrap();
+
+        let index_directory = temp_directory();
+        let (index, _) = parallel_index_directory(&source_directory, &IndexOptions::default()).await;
+        let tree = IndexTreeBuilder::new().leaf_size(1).build(&index, &index_directory);
+
+        // Exact matches first, then prefixes, then fuzzy ones. Text files aren't
+        // looked at.
+        let found: Vec<(String, String, u32, MatchRank)> = tree
+            .find_symbols("start", 2)
+            .into_iter()
+            .map(|found| (found.file_path, found.symbol.name, found.symbol.line, found.rank))
+            .collect();
+
+        assert_eq!(vec![
+            ("server.rs".to_string(), "start".to_string(), 4, MatchRank::Exact),
+            ("tool.py".to_string(), "start_server".to_string(), 1, MatchRank::Prefix),
+        ], found);
+
+        let found = tree.find_symbols("srvr", 1);
+        assert_eq!(vec!["Server", "start_server"], found.iter().map(|found| found.symbol.name.as_str()).collect::<Vec<&str>>());
+        assert_eq!(SymbolKind::Type, found[0].symbol.kind);
+
+        assert!(tree.find_symbols("missing", 1).is_empty());
+
+        std::fs::remove_dir_all(&source_directory).unwrap();
+        std::fs::remove_dir_all(&index_directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn index_tree_search_languages() {
+        let source_directory = temp_directory();
+        std::fs::write(format!("{}/server.rs", source_directory), "fn shared_marker() {}\n").unwrap();
+        std::fs::write(format!("{}/tool.py", source_directory), "def shared_marker():\n    pass\n").unwrap();
+        std::fs::write(format!("{}/Makefile", source_directory), "all:\n\techo shared_marker\n").unwrap();
+        std::fs::write(format!("{}/release", source_directory), "#!/bin/sh\necho shared_marker\n").unwrap();
+        std::fs::write(format!("{}/notes.txt", source_directory), "shared_marker\n").unwrap();
+
+        let index_directory = temp_directory();
+        let (index, _) = parallel_index_directory(&source_directory, &IndexOptions::default()).await;
+        let tree = IndexTreeBuilder::new().fan_out(2).leaf_size(1).build(&index, &index_directory);
+
+        let all_results = tree.search_files("shared_marker");
+        assert_eq!(5, all_results.files.len());
+
+        let query = Query::literal("shared_marker").in_languages(LanguageSet::from_names("rust,sh").unwrap());
+        let results = tree.search_query(&query);
+
+        assert_eq!(HashSet::from(["server.rs".to_string(), "release".to_string()]), results.files);
+        assert!(results.bloom_filters_checked < all_results.bloom_filters_checked);
+
+        for threads in [1, 4] {
+            assert_eq!(results, tree.parallel_search_query(&query, threads));
+        }
+
+        let query = Query::literal("shared_marker").in_languages(LanguageSet::from_names("go").unwrap());
+        assert_eq!(SearchResults::default(), tree.search_query(&query));
+
+        let stats = tree.stats();
+        assert_eq!(Some(&1), stats.languages.get("rust"));
+        assert_eq!(Some(&1), stats.languages.get("python"));
+        assert_eq!(Some(&1), stats.languages.get("makefile"));
+        assert_eq!(Some(&1), stats.languages.get("shell"));
+        assert_eq!(Some(&1), stats.languages.get("other"));
+
+        std::fs::remove_dir_all(&source_directory).unwrap();
+        std::fs::remove_dir_all(&index_directory).unwrap();
+    }
+
+    #[test]
+    fn index_tree_update_files_into_empty_tree() {
+        let source_directory = temp_directory();
+        std::fs::write(format!("{}/new.rs", source_directory), "fn brand_new() {}").unwrap();
+
+        let index_directory = temp_directory();
+        let mut tree = IndexTreeBuilder::new().build(&Index::new(), &index_directory);
+
+        let stats = tree.update_files(&source_directory, &["new.rs".to_string()], &IndexOptions::default(), &index_directory);
+
+        assert_eq!(1, stats.added_count);
+        assert_eq!(1, tree.files_count());
+        assert!(tree.search_files("brand_new").files.contains("new.rs"));
+
+        std::fs::remove_dir_all(&source_directory).unwrap();
+        std::fs::remove_dir_all(&index_directory).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn index_tree_update_files_symlinks() {
+        let source_directory = temp_directory();
+        std::fs::write(format!("{}/real.rs", source_directory), "fn linked_to() {}").unwrap();
+        std::os::unix::fs::symlink(format!("{}/real.rs", source_directory), format!("{}/link.rs", source_directory)).unwrap();
+
+        let index_directory = temp_directory();
+        let paths = ["link.rs".to_string()];
+
+        // Left out, as walking the folder would, unless symlinks are followed.
+        let mut tree = IndexTreeBuilder::new().build(&Index::new(), &index_directory);
+        assert_eq!(0, tree.update_files(&source_directory, &paths, &IndexOptions::default(), &index_directory).added_count);
+
+        let options = IndexOptions { follow_symlinks: true, ..Default::default() };
+        assert_eq!(1, tree.update_files(&source_directory, &paths, &options, &index_directory).added_count);
+
+        std::fs::remove_dir_all(&source_directory).unwrap();
+        std::fs::remove_dir_all(&index_directory).unwrap();
+    }
+
+    #[test]
+    fn index_tree_update_files_excluded_directories() {
+        let source_directory = temp_directory();
+        std::fs::create_dir_all(format!("{}/out", source_directory)).unwrap();
+        std::fs::write(format!("{}/out/generated.rs", source_directory), "fn generated() {}").unwrap();
+        std::fs::write(format!("{}/main.rs", source_directory), "fn main() {}").unwrap();
+
+        let index_directory = temp_directory();
+        let paths = ["out/generated.rs".to_string(), "main.rs".to_string()];
+
+        // Files in excluded folders are left out, as walking the folder would.
+        let options = IndexOptions { excluded_directories: vec![format!("{}/out", source_directory)], ..Default::default() };
+        let mut tree = IndexTreeBuilder::new().build(&Index::new(), &index_directory);
+        assert_eq!(1, tree.update_files(&source_directory, &paths, &options, &index_directory).added_count);
+        assert!(tree.search_files("").files.contains("main.rs"));
+
+        std::fs::remove_dir_all(&source_directory).unwrap();
+        std::fs::remove_dir_all(&index_directory).unwrap();
+    }
+
+    #[test]
+    fn index_tree_builder_no_files() {
+        let directory = temp_directory();
+
+        let tree = IndexTreeBuilder::new().build(&Index::new(), &directory);
+
+        assert_eq!(0, tree.files_count());
+        assert!(tree.search_files("").files.is_empty());
+        assert!(tree.search_files("abc").files.is_empty());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn index_tree_similarity_clustering_sparser_parents() {
+        let directory = temp_directory();
+
+        // Two kinds of files with nothing in common, interleaved.
+        let mut index = Index::new();
+        for i in 0..32u32 {
+            let trigrams: Vec<u32> = if i % 2 == 0 { (0..200).collect() } else { (20000..20200).collect() };
+
+            index.add_file(FileEntry {
+                file_path: format!("{}.rs", i),
+                bloom_filter: BloomFilter::new(&trigrams, BLOOM_FILTER_SIZE),
+                encoding: Encoding::Utf8,
+                binary: false,
+                name_only: false,
+                language: None,
+                symbols: Vec::new(),
+            });
+        }
+
+        let unclustered = IndexTreeBuilder::new()
+            .leaf_size(4)
+            .build(&index, &directory)
+            .stats();
+
+        let clustered = IndexTreeBuilder::new()
+            .leaf_size(4)
+            .clustering(Clustering::Similarity)
+            .build(&index, &directory)
+            .stats();
+
+        assert_eq!(unclustered.levels.len(), clustered.levels.len());
+        assert_eq!(32, clustered.files_count);
+        assert_eq!(8, clustered.shards_count);
+
+        // Below the root, each node now only covers one kind of file.
+        let level = clustered.levels.len() - 2;
+        assert!(clustered.levels[level].average_fill_ratio() < unclustered.levels[level].average_fill_ratio());
+        assert!(clustered.levels[level].estimated_pass_rate(1) < unclustered.levels[level].estimated_pass_rate(1));
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn index_tree_parallel_search_matches_sequential() {
+        let directory = temp_directory();
+
+        // Files with pseudo-random trigrams, so queries prune different subtrees.
+        let mut seed: u32 = 7;
+        let mut next_random = move || {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            seed >> 8
+        };
+
+        let mut index = Index::new();
+        for i in 0..200 {
+            let trigrams: Vec<u32> = (0..50).map(|_| next_random() % 2000).collect();
+
+            index.add_file(FileEntry {
+                file_path: format!("{}.rs", i),
+                bloom_filter: BloomFilter::new(&trigrams, BLOOM_FILTER_SIZE),
+                encoding: Encoding::Utf8,
+                binary: false,
+                name_only: false,
+                language: None,
+                symbols: Vec::new(),
+            });
+        }
+
+        let tree = IndexTreeBuilder::new()
+            .fan_out(3)
+            .leaf_size(4)
+            .build(&index, &directory);
+
+        for query in ["", "a", "abc", "abcdef", "zzzzzz", "0a1", "x1y2z3"] {
+            let sequential = tree.search_files(query);
+
+            for threads in [1, 2, 8] {
+                assert_eq!(sequential, tree.parallel_search_files(query, threads));
+            }
+        }
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn index_tree_parallel_search_stops_when_a_worker_panics() {
+        let directory = temp_directory();
+
+        let mut index = Index::new();
+        for i in 0..20 {
+            index.add_file(FileEntry {
+                file_path: format!("{}.rs", i),
+                bloom_filter: BloomFilter::new(&[], BLOOM_FILTER_SIZE),
+                encoding: Encoding::Utf8,
+                binary: false,
+                name_only: false,
+                language: None,
+                symbols: Vec::new(),
+            });
+        }
+
+        let tree = IndexTreeBuilder::new().fan_out(2).leaf_size(1).build(&index, &directory);
+
+        // One shard goes missing, so the worker that loads it panics. The others
+        // must give up instead of waiting for it forever.
+        let shard = std::fs::read_dir(&directory).unwrap().next().unwrap().unwrap().path();
+        std::fs::remove_file(shard).unwrap();
+
+        for threads in [2, 8] {
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| tree.parallel_search_files("", threads)));
+            assert!(result.is_err());
+        }
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
 }
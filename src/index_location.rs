@@ -0,0 +1,153 @@
+use std::io::Error;
+use std::path::{Path, PathBuf};
+
+use uuid::Uuid;
+
+pub const INDEX_DIR_ENV_VAR: &str = "RUST_INDEXER_INDEX_DIR";
+
+const CACHE_FOLDER_NAME: &str = "rust-indexer";
+
+// Picks the folder that holds the index for the given source folder. In order of
+// precedence: an explicitly requested folder, the RUST_INDEXER_INDEX_DIR environment
+// variable, then a per-source folder in the user's cache directory. Indexes are kept
+// out of the source tree by default so they don't pollute repos and can be built
+// for read-only checkouts.
+pub fn resolve_index_directory(source_path: &str, explicit_index_directory: Option<&str>) -> Result<String, Error> {
+    if let Some(index_directory) = explicit_index_directory {
+        return Ok(index_directory.to_string());
+    }
+
+    if let Ok(index_directory) = std::env::var(INDEX_DIR_ENV_VAR) {
+        if !index_directory.is_empty() {
+            return Ok(index_directory);
+        }
+    }
+
+    let canonical_source_path = std::fs::canonicalize(source_path)?;
+
+    match cache_directory() {
+        Some(cache_directory) => Ok(cache_directory
+            .join(CACHE_FOLDER_NAME)
+            .join(cache_key(&canonical_source_path))
+            .to_string_lossy()
+            .to_string()),
+
+        // No idea where the cache lives, fall back to the old in-tree location.
+        None => Ok(format!("{}/.index", source_path)),
+    }
+}
+
+// Stable, human-recognizable folder name for a source folder, e.g. 'my-repo-1b2c3d4e5f6a7b8c'.
+// The hash keeps two checkouts with the same folder name apart.
+pub fn cache_key(canonical_source_path: &Path) -> String {
+    let name = canonical_source_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| String::from("root"));
+
+    format!("{}-{:016x}", name, fnv1a_hash(canonical_source_path.to_string_lossy().as_bytes()))
+}
+
+// Where the index of a git branch, tag or commit is kept, next to the folder's own
+// index. Keyed by the name it was indexed as, e.g. 'main' and 'refs/heads/main' are
+// indexed separately. '/' is escaped so that every ref gets a folder of its own.
+pub fn ref_index_directory(index_directory: &str, ref_name: &str) -> String {
+    format!("{}/refs/{}", index_directory, ref_name.replace('%', "%25").replace('/', "%2F"))
+}
+
+// A folder of its own in the index directory for shards written while indexing, so
+// that they can't be mistaken for leftovers of the current index and cleaned up by
+// another process replacing it in the meantime. Removed with whatever is still in it
+// when dropped, so that indexing that fails or panics partway through leaves nothing
+// behind.
+pub struct StagingDirectory {
+    pub path: String,
+}
+
+impl StagingDirectory {
+    pub fn create(index_directory: &str) -> Result<StagingDirectory, Error> {
+        let path = format!("{}/staging-{}", index_directory, Uuid::new_v4());
+        std::fs::create_dir_all(&path)?;
+
+        Ok(StagingDirectory { path })
+    }
+}
+
+impl Drop for StagingDirectory {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+fn cache_directory() -> Option<PathBuf> {
+    let from_env = |name: &str| std::env::var(name)
+        .ok()
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from);
+
+    from_env("XDG_CACHE_HOME")
+        .or_else(|| from_env("HOME").map(|home| home.join(".cache")))
+        .or_else(|| from_env("LOCALAPPDATA"))
+}
+
+// std's hashers are not guaranteed to be stable between releases, and this one ends
+// up on disk, so use FNV-1a.
+fn fnv1a_hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use uuid::Uuid;
+
+    use super::{cache_key, ref_index_directory, resolve_index_directory, StagingDirectory};
+
+    #[test]
+    fn index_location_explicit_wins() {
+        let index_directory = resolve_index_directory("does-not-exist", Some("/tmp/explicit")).unwrap();
+        assert_eq!("/tmp/explicit", index_directory);
+    }
+
+    #[test]
+    fn index_location_cache_key_is_stable() {
+        assert_eq!("repo-f8019f51f4609529", cache_key(Path::new("/src/repo")));
+        assert_eq!(cache_key(Path::new("/src/repo")), cache_key(Path::new("/src/repo")));
+        assert_ne!(cache_key(Path::new("/src/repo")), cache_key(Path::new("/other/repo")));
+    }
+
+    #[test]
+    fn index_location_ref_directories() {
+        assert_eq!("/index/refs/main", ref_index_directory("/index", "main"));
+        assert_eq!("/index/refs/release%2F1.0", ref_index_directory("/index", "release/1.0"));
+        assert_ne!(ref_index_directory("/index", "a/b"), ref_index_directory("/index", "a%2Fb"));
+    }
+
+    #[test]
+    fn index_location_staging_directory_removed_on_drop() {
+        let index_directory = format!("{}/rust-indexer-test-{}", std::env::temp_dir().display(), Uuid::new_v4());
+
+        let staging = StagingDirectory::create(&index_directory).unwrap();
+        std::fs::write(format!("{}/shard", staging.path), "partial").unwrap();
+
+        let staging_path = staging.path.clone();
+        let result = std::panic::catch_unwind(move || {
+            let _staging = staging;
+            panic!("indexing failed");
+        });
+
+        assert!(result.is_err());
+        assert!(!Path::new(&staging_path).exists());
+        assert!(Path::new(&index_directory).exists());
+
+        std::fs::remove_dir_all(&index_directory).unwrap();
+    }
+}
@@ -0,0 +1,307 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+// What a file is written in, as far as its name or shebang line tell. Definitions are
+// extracted from some of these, see symbols.rs.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub enum Language {
+    Rust,
+    C,
+    Cpp,
+    CSharp,
+    Java,
+    Kotlin,
+    Scala,
+    Swift,
+    Go,
+    TypeScript,
+    JavaScript,
+    Python,
+    Ruby,
+    Php,
+    Perl,
+    Lua,
+    Shell,
+    PowerShell,
+    Sql,
+    Html,
+    Css,
+    Json,
+    Yaml,
+    Toml,
+    Xml,
+    Markdown,
+    Protobuf,
+    Makefile,
+    Dockerfile,
+    CMake,
+}
+
+const ALL_LANGUAGES: &[Language] = &[
+    Language::Rust, Language::C, Language::Cpp, Language::CSharp, Language::Java, Language::Kotlin,
+    Language::Scala, Language::Swift, Language::Go, Language::TypeScript, Language::JavaScript,
+    Language::Python, Language::Ruby, Language::Php, Language::Perl, Language::Lua, Language::Shell,
+    Language::PowerShell, Language::Sql, Language::Html, Language::Css, Language::Json, Language::Yaml,
+    Language::Toml, Language::Xml, Language::Markdown, Language::Protobuf, Language::Makefile,
+    Language::Dockerfile, Language::CMake,
+];
+
+impl Language {
+    // By file name, e.g. 'Makefile', then by extension, then by the interpreter on
+    // the shebang line, if the start of the file is given.
+    pub fn detect(file_path: &str, first_block: &[u8]) -> Option<Language> {
+        Language::from_path(file_path).or_else(|| Language::from_shebang(first_block))
+    }
+
+    pub fn from_path(file_path: &str) -> Option<Language> {
+        let file_name = Path::new(file_path).file_name()?.to_string_lossy().to_string();
+
+        let by_name = match file_name.as_str() {
+            "Makefile" | "makefile" | "GNUmakefile" => Some(Language::Makefile),
+            "Dockerfile" | "Containerfile" => Some(Language::Dockerfile),
+            "CMakeLists.txt" => Some(Language::CMake),
+            "Rakefile" | "Gemfile" => Some(Language::Ruby),
+            ".bashrc" | ".bash_profile" | ".zshrc" | ".profile" => Some(Language::Shell),
+            name if name.starts_with("Dockerfile.") => Some(Language::Dockerfile),
+            _ => None,
+        };
+
+        if by_name.is_some() {
+            return by_name;
+        }
+
+        let extension = Path::new(&file_name)
+            .extension()?
+            .to_string_lossy()
+            .to_lowercase();
+
+        match extension.as_str() {
+            "rs" => Some(Language::Rust),
+            "c" | "h" => Some(Language::C),
+            "cc" | "cpp" | "cxx" | "c++" | "hh" | "hpp" | "hxx" | "h++" | "inl" => Some(Language::Cpp),
+            "cs" | "csx" => Some(Language::CSharp),
+            "java" => Some(Language::Java),
+            "kt" | "kts" => Some(Language::Kotlin),
+            "scala" | "sc" => Some(Language::Scala),
+            "swift" => Some(Language::Swift),
+            "go" => Some(Language::Go),
+            "ts" | "tsx" | "mts" | "cts" => Some(Language::TypeScript),
+            "js" | "jsx" | "mjs" | "cjs" => Some(Language::JavaScript),
+            "py" | "pyi" => Some(Language::Python),
+            "rb" | "rake" | "gemspec" => Some(Language::Ruby),
+            "php" => Some(Language::Php),
+            "pl" | "pm" => Some(Language::Perl),
+            "lua" => Some(Language::Lua),
+            "sh" | "bash" | "zsh" | "ksh" => Some(Language::Shell),
+            "ps1" | "psm1" | "psd1" => Some(Language::PowerShell),
+            "sql" => Some(Language::Sql),
+            "html" | "htm" | "xhtml" => Some(Language::Html),
+            "css" | "scss" | "sass" | "less" => Some(Language::Css),
+            "json" | "jsonc" => Some(Language::Json),
+            "yaml" | "yml" => Some(Language::Yaml),
+            "toml" => Some(Language::Toml),
+            "xml" | "xsd" | "xsl" | "xslt" | "csproj" | "vcxproj" | "props" | "targets" | "resx" => Some(Language::Xml),
+            "md" | "markdown" => Some(Language::Markdown),
+            "proto" => Some(Language::Protobuf),
+            "mk" | "mak" => Some(Language::Makefile),
+            "dockerfile" => Some(Language::Dockerfile),
+            "cmake" => Some(Language::CMake),
+            _ => None,
+        }
+    }
+
+    // E.g. '#!/usr/bin/env python3' or '#!/bin/bash -e'.
+    pub fn from_shebang(first_block: &[u8]) -> Option<Language> {
+        let rest = first_block.strip_prefix(b"#!")?;
+        let line = String::from_utf8_lossy(&rest[..rest.iter().position(|byte| *byte == b'\n').unwrap_or(rest.len())]).to_string();
+
+        let mut words = line.split_whitespace();
+        let mut program = Path::new(words.next()?).file_name()?.to_string_lossy().to_string();
+
+        // 'env' runs whatever comes after its own options.
+        if program == "env" {
+            program = words.find(|word| !word.starts_with('-'))?.to_string();
+        }
+
+        // Versioned interpreters, e.g. 'python3.12'.
+        match program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.') {
+            "python" => Some(Language::Python),
+            "node" | "nodejs" => Some(Language::JavaScript),
+            "deno" | "ts-node" => Some(Language::TypeScript),
+            "sh" | "bash" | "zsh" | "ksh" | "dash" | "ash" => Some(Language::Shell),
+            "ruby" => Some(Language::Ruby),
+            "perl" => Some(Language::Perl),
+            "php" => Some(Language::Php),
+            "lua" => Some(Language::Lua),
+            "pwsh" => Some(Language::PowerShell),
+            "make" => Some(Language::Makefile),
+            _ => None,
+        }
+    }
+
+    // The name or a common short name, ignoring case, e.g. 'rust', 'rs' or 'ts'.
+    pub fn from_name(name: &str) -> Option<Language> {
+        let name = name.to_lowercase();
+
+        let by_alias = match name.as_str() {
+            "rs" => Some(Language::Rust),
+            "c++" | "cxx" => Some(Language::Cpp),
+            "cs" | "c#" => Some(Language::CSharp),
+            "kt" => Some(Language::Kotlin),
+            "golang" => Some(Language::Go),
+            "ts" => Some(Language::TypeScript),
+            "js" => Some(Language::JavaScript),
+            "py" => Some(Language::Python),
+            "rb" => Some(Language::Ruby),
+            "pl" => Some(Language::Perl),
+            "sh" | "bash" | "zsh" => Some(Language::Shell),
+            "ps1" | "pwsh" => Some(Language::PowerShell),
+            "yml" => Some(Language::Yaml),
+            "md" => Some(Language::Markdown),
+            "proto" => Some(Language::Protobuf),
+            "make" => Some(Language::Makefile),
+            "docker" => Some(Language::Dockerfile),
+            _ => None,
+        };
+
+        by_alias.or_else(|| ALL_LANGUAGES.iter().copied().find(|language| language.name() == name))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Language::Rust => "rust",
+            Language::C => "c",
+            Language::Cpp => "cpp",
+            Language::CSharp => "csharp",
+            Language::Java => "java",
+            Language::Kotlin => "kotlin",
+            Language::Scala => "scala",
+            Language::Swift => "swift",
+            Language::Go => "go",
+            Language::TypeScript => "typescript",
+            Language::JavaScript => "javascript",
+            Language::Python => "python",
+            Language::Ruby => "ruby",
+            Language::Php => "php",
+            Language::Perl => "perl",
+            Language::Lua => "lua",
+            Language::Shell => "shell",
+            Language::PowerShell => "powershell",
+            Language::Sql => "sql",
+            Language::Html => "html",
+            Language::Css => "css",
+            Language::Json => "json",
+            Language::Yaml => "yaml",
+            Language::Toml => "toml",
+            Language::Xml => "xml",
+            Language::Markdown => "markdown",
+            Language::Protobuf => "protobuf",
+            Language::Makefile => "makefile",
+            Language::Dockerfile => "dockerfile",
+            Language::CMake => "cmake",
+        }
+    }
+}
+
+// A set of languages, one bit each, small enough to keep on every index tree node so
+// that searches limited to some languages can skip whole subtrees.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct LanguageSet(u64);
+
+impl LanguageSet {
+    // Comma separated names, e.g. 'rust,ts'. Err with the first name that isn't a
+    // language.
+    pub fn from_names(names: &str) -> Result<LanguageSet, String> {
+        let mut languages = LanguageSet::default();
+
+        for name in names.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            languages.insert(Language::from_name(name).ok_or_else(|| name.to_string())?);
+        }
+
+        Ok(languages)
+    }
+
+    pub fn insert(&mut self, language: Language) {
+        self.0 |= 1 << language as u64;
+    }
+
+    pub fn merge(&mut self, other: LanguageSet) {
+        self.0 |= other.0;
+    }
+
+    pub fn contains(&self, language: Language) -> bool {
+        self.0 & (1 << language as u64) != 0
+    }
+
+    pub fn intersects(&self, other: LanguageSet) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Language, LanguageSet, ALL_LANGUAGES};
+
+    #[test]
+    fn language_from_path() {
+        assert_eq!(Some(Language::Rust), Language::from_path("src/main.rs"));
+        assert_eq!(Some(Language::Cpp), Language::from_path("include/Widget.HPP"));
+        assert_eq!(Some(Language::TypeScript), Language::from_path("app/view.tsx"));
+        assert_eq!(Some(Language::Python), Language::from_path("tools/build.py"));
+        assert_eq!(Some(Language::Markdown), Language::from_path("README.md"));
+        assert_eq!(Some(Language::Makefile), Language::from_path("Makefile"));
+        assert_eq!(Some(Language::Dockerfile), Language::from_path("docker/Dockerfile.release"));
+        assert_eq!(Some(Language::CMake), Language::from_path("CMakeLists.txt"));
+        assert_eq!(None, Language::from_path("LICENSE"));
+        assert_eq!(None, Language::from_path("notes.txt"));
+    }
+
+    #[test]
+    fn language_from_shebang() {
+        assert_eq!(Some(Language::Python), Language::from_shebang(b"#!/usr/bin/env python3\nimport os\n"));
+        assert_eq!(Some(Language::Python), Language::from_shebang(b"#!/usr/bin/python3.12 -u"));
+        assert_eq!(Some(Language::Shell), Language::from_shebang(b"#!/bin/bash -e\n"));
+        assert_eq!(Some(Language::JavaScript), Language::from_shebang(b"#!/usr/bin/env -S node --no-warnings\n"));
+        assert_eq!(None, Language::from_shebang(b"#!/usr/bin/env\n"));
+        assert_eq!(None, Language::from_shebang(b"# not a shebang\n"));
+
+        // The name wins over the shebang.
+        assert_eq!(Some(Language::Ruby), Language::detect("tools/release.rb", b"#!/bin/sh\n"));
+        assert_eq!(Some(Language::Shell), Language::detect("bin/release", b"#!/bin/sh\n"));
+    }
+
+    #[test]
+    fn language_names() {
+        for language in ALL_LANGUAGES {
+            assert_eq!(Some(*language), Language::from_name(language.name()));
+        }
+
+        assert_eq!(Some(Language::TypeScript), Language::from_name("TS"));
+        assert_eq!(Some(Language::CSharp), Language::from_name("c#"));
+        assert_eq!(None, Language::from_name("klingon"));
+    }
+
+    #[test]
+    fn language_sets() {
+        let languages = LanguageSet::from_names("rust, ts,").unwrap();
+        assert!(languages.contains(Language::Rust));
+        assert!(languages.contains(Language::TypeScript));
+        assert!(!languages.contains(Language::JavaScript));
+
+        let mut other = LanguageSet::default();
+        assert!(other.is_empty());
+        assert!(!languages.intersects(other));
+
+        other.insert(Language::CMake);
+        assert!(!languages.intersects(other));
+        other.merge(languages);
+        assert!(languages.intersects(other));
+
+        assert_eq!(Err("klingon".to_string()), LanguageSet::from_names("rust,klingon"));
+    }
+}
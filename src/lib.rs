@@ -1,6 +1,23 @@
 pub mod index;
+pub mod index_location;
+pub mod language;
+pub mod locking;
+pub mod manifest;
+pub mod progress;
+pub mod query;
+pub mod ranking;
+pub mod regions;
+pub mod symbols;
 mod bloom;
+mod case_folding;
 mod compression_utils;
-mod batching;
+pub mod encoding;
+pub mod highlighting;
+pub mod git;
+pub mod git_objects;
+pub mod batching;
+pub mod binary;
+pub mod clustering;
 pub mod text_scraping;
 mod trigram;
+pub mod walker;
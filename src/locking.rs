@@ -0,0 +1,165 @@
+use std::fs::{File, OpenOptions};
+use std::io::Error;
+
+const LOCK_FILE_NAME: &str = "lock";
+const GENERATION_FILE_NAME: &str = "generation";
+
+// Advisory lock over an index directory. Writers hold it exclusively while
+// replacing the index, readers hold it shared while loading the root or any
+// of its shards. The lock is released when the value is dropped.
+pub struct IndexLock {
+    index_directory: String,
+    file: File,
+}
+
+impl IndexLock {
+    // Fails with NotFound if the index directory doesn't exist, rather than
+    // creating it, since there's nothing to read.
+    pub fn shared(index_directory: &str) -> Result<IndexLock, Error> {
+        let file = open_lock_file(index_directory, false)?;
+        file.lock_shared()?;
+
+        Ok(IndexLock { index_directory: index_directory.to_string(), file })
+    }
+
+    pub fn exclusive(index_directory: &str) -> Result<IndexLock, Error> {
+        let file = open_lock_file(index_directory, true)?;
+        file.lock()?;
+
+        Ok(IndexLock { index_directory: index_directory.to_string(), file })
+    }
+
+    // For updating an index in place: fails with NotFound like shared() if
+    // there's no index directory, rather than creating an empty one.
+    pub fn exclusive_existing(index_directory: &str) -> Result<IndexLock, Error> {
+        let file = open_lock_file(index_directory, false)?;
+        file.lock()?;
+
+        Ok(IndexLock { index_directory: index_directory.to_string(), file })
+    }
+
+    // Tries to take the exclusive lock without blocking, returning None if
+    // another reader or writer currently holds it.
+    pub fn try_exclusive(index_directory: &str) -> Result<Option<IndexLock>, Error> {
+        let file = open_lock_file(index_directory, true)?;
+
+        match file.try_lock() {
+            Ok(()) => Ok(Some(IndexLock { index_directory: index_directory.to_string(), file })),
+            Err(std::fs::TryLockError::WouldBlock) => Ok(None),
+            Err(std::fs::TryLockError::Error(error)) => Err(error),
+        }
+    }
+
+    // The generation is bumped every time a writer replaces the index, so
+    // long-lived readers can tell when their in-memory tree is stale.
+    pub fn generation(&self) -> u64 {
+        read_generation(&self.index_directory)
+    }
+
+    pub fn bump_generation(&self) -> Result<u64, Error> {
+        let generation = self.generation() + 1;
+        let generation_path = format!("{}/{}", self.index_directory, GENERATION_FILE_NAME);
+        let temp_path = format!("{}.tmp", generation_path);
+
+        // Write then rename so readers never observe a partially written value.
+        std::fs::write(&temp_path, generation.to_string())?;
+        std::fs::rename(&temp_path, &generation_path)?;
+
+        Ok(generation)
+    }
+}
+
+impl Drop for IndexLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+pub fn read_generation(index_directory: &str) -> u64 {
+    std::fs::read_to_string(format!("{}/{}", index_directory, GENERATION_FILE_NAME))
+        .ok()
+        .and_then(|text| text.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+fn open_lock_file(index_directory: &str, create_directory: bool) -> Result<File, Error> {
+    if create_directory {
+        std::fs::create_dir_all(index_directory)?;
+    }
+
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(format!("{}/{}", index_directory, LOCK_FILE_NAME))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+    use std::path::Path;
+
+    use uuid::Uuid;
+
+    use super::{read_generation, IndexLock};
+
+    fn temp_index_directory() -> String {
+        format!("{}/rust-indexer-test-{}", std::env::temp_dir().display(), Uuid::new_v4())
+    }
+
+    #[test]
+    fn lock_shared_readers_coexist() {
+        let directory = temp_index_directory();
+
+        // Readers and updates don't create the directory.
+        assert_eq!(ErrorKind::NotFound, IndexLock::shared(&directory).err().unwrap().kind());
+        assert_eq!(ErrorKind::NotFound, IndexLock::exclusive_existing(&directory).err().unwrap().kind());
+        assert!(!Path::new(&directory).exists());
+
+        std::fs::create_dir_all(&directory).unwrap();
+
+        let first = IndexLock::shared(&directory).unwrap();
+        let second = IndexLock::shared(&directory).unwrap();
+
+        // A writer can't get in while readers are active.
+        assert!(IndexLock::try_exclusive(&directory).unwrap().is_none());
+
+        drop(first);
+        drop(second);
+
+        assert!(IndexLock::try_exclusive(&directory).unwrap().is_some());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn lock_writer_is_exclusive() {
+        let directory = temp_index_directory();
+
+        let writer = IndexLock::exclusive(&directory).unwrap();
+        assert!(IndexLock::try_exclusive(&directory).unwrap().is_none());
+
+        drop(writer);
+        assert!(IndexLock::try_exclusive(&directory).unwrap().is_some());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn lock_generation_bumps() {
+        let directory = temp_index_directory();
+
+        assert_eq!(0, read_generation(&directory));
+
+        let writer = IndexLock::exclusive(&directory).unwrap();
+        assert_eq!(1, writer.bump_generation().unwrap());
+        assert_eq!(2, writer.bump_generation().unwrap());
+        drop(writer);
+
+        assert_eq!(2, read_generation(&directory));
+        assert_eq!(2, IndexLock::shared(&directory).unwrap().generation());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -1,57 +1,395 @@
 use colored::{ColoredString, Colorize};
-use rust_indexer::{index::IndexTree, text_scraping::{self}};
-use std::env::args;
+use rust_indexer::{batching::jobs_count, binary::BinaryHandling, clustering::Clustering, encoding::Encoding, highlighting::{self, TokenKind}, git::{commit_files, strip_path_prefix, tracked_files, CommitFiles, Repository}, git_objects::{from_hex, to_hex, ObjectId}, index::{resolve_indexed_path, FileEntry, IndexOptions, IndexingStats, IndexTree, IndexTreeBuilder, IndexTreeStats}, index_location::{ref_index_directory, resolve_index_directory, StagingDirectory}, language::LanguageSet, locking::IndexLock, manifest::IndexManifest, progress::{IndexingProgress, ProgressSnapshot}, query::Query, ranking, regions::Region, symbols::SymbolMatch, text_scraping::{self, Match, ScrapeOptions, ScrapeSummary}};
+use std::{collections::{HashMap, HashSet}, env::args, io::{ErrorKind, IsTerminal}, sync::{mpsc::TrySendError, Arc}, time::{Duration, Instant}};
+
+// Options that are followed by a value, either as '--name value' or '--name=value'.
+// Anything else starting with '--' is treated as an on/off switch.
+const VALUE_OPTIONS: &[&str] = &["--index-dir", "--fan-out", "--leaf-size", "--cluster", "--max-results", "--max-files", "--encoding", "--binary", "--max-file-size", "--jobs", "--since", "--ref", "--lang", "--in"];
+
+// Files bigger than this are left out of the index unless --max-file-size says otherwise.
+const DEFAULT_MAX_FILE_SIZE: &str = "100M";
+
+// How often the progress bar is redrawn when indexing in a terminal, and how often a
+// progress line is logged otherwise.
+const PROGRESS_REDRAW_INTERVAL: Duration = Duration::from_millis(500);
+const PROGRESS_LOG_INTERVAL: Duration = Duration::from_secs(10);
+const PROGRESS_BAR_WIDTH: usize = 20;
+
+// How many definitions 'symbol' prints unless --max-results says otherwise.
+const DEFAULT_MAX_SYMBOLS: usize = 50;
+
+// How many indexed files can wait for the index tree writer.
+const WRITER_BUFFER: usize = 4096;
+
+// Number of distinct trigrams in a short query, about 5 characters, used to
+// estimate how well the index prunes in 'stats'.
+const TYPICAL_QUERY_TRIGRAMS: usize = 3;
 
 #[tokio_macros::main]
 async fn main() {
-    let cmd_args: Vec<String> = args().collect();
+    let cmd_args = CommandLine::parse(args().skip(1));
 
-    if cmd_args.len() < 2 {
+    if cmd_args.positional.len() < 2 || cmd_args.switch("--help") {
         print_help();
         return;
     }
 
-    let command = cmd_args.get(1).unwrap();
-    let path = cmd_args.get(2).unwrap();
+    let Some(jobs) = parse_jobs(&cmd_args) else {
+        return;
+    };
 
-    let index_directory = format!("{}/.index", path);
-    let index_root_path = format!("{}/root.dat", index_directory);
+    let Some(languages) = parse_languages(&cmd_args) else {
+        return;
+    };
+
+    let Some(max_results) = parse_number(&cmd_args, "--max-results", 0) else {
+        return;
+    };
+
+    let Some(max_files) = parse_number(&cmd_args, "--max-files", 0) else {
+        return;
+    };
+
+    let Some(region) = parse_region(&cmd_args) else {
+        return;
+    };
 
-    std::fs::create_dir_all(&index_directory).unwrap();
+    let command = &cmd_args.positional[0];
+    let path = &cmd_args.positional[1];
+
+    let index_directory = resolve_index_directory(path, cmd_args.option("--index-dir")).unwrap();
+
+    // Branches, tags and commits indexed with --ref each have an index of their own.
+    let git_ref = cmd_args.option("--ref");
+    let index_directory = match git_ref {
+        Some(name) => ref_index_directory(&index_directory, name),
+        None => index_directory,
+    };
+    let index_root_path = format!("{}/root.dat", index_directory);
 
     if command == "index" {
-        print_with_color("Indexing...".cyan());
-        let index = rust_indexer::index::parallel_index_directory(path).await;
-        let index_tree = IndexTree::from_index(&index, &index_directory);
+        let quiet = cmd_args.switch("--quiet");
+
+        if !quiet {
+            print_with_color("Indexing...".cyan());
+        }
+
+        let Some(default_encoding) = parse_encoding(&cmd_args) else {
+            return;
+        };
+
+        let Some(binary_handling) = parse_binary_handling(&cmd_args) else {
+            return;
+        };
+
+        let Some(max_file_size) = parse_max_file_size(&cmd_args) else {
+            return;
+        };
+
+        let git_submodules = cmd_args.switch("--git-submodules");
+        let git_tracked_only = git_submodules || cmd_args.switch("--git-tracked");
+
+        if (git_tracked_only || git_ref.is_some()) && Repository::find(path).is_none() {
+            print_with_color(format!("'{}' isn't in a git repository.", path).red());
+            return;
+        }
+
+        // With --ref, the commit is read from the object database instead of the folder.
+        let git_commit = match git_ref {
+            Some(name) => match resolve_git_ref(path, name) {
+                Some(commit) => Some(commit),
+                None => return,
+            },
+            None => None,
+        };
+
+        let git_commit_files = match git_ref.zip(git_commit.as_ref()) {
+            Some((name, commit)) => match read_commit_files(path, name, commit) {
+                Some(files) => Some(files),
+                None => return,
+            },
+            None => None,
+        };
+
+        let follow_symlinks = cmd_args.switch("--follow-symlinks");
+        let one_file_system = cmd_args.switch("--one-file-system");
+
+        // Read before anything is written, so that a git index we can't read is
+        // reported rather than failing partway through indexing.
+        let git_tracked_files = if git_tracked_only && git_commit.is_none() {
+            match tracked_files(path, git_submodules, follow_symlinks) {
+                Ok(files) => Some(Arc::new(files)),
+                Err(error) => {
+                    print_with_color(format!("Couldn't read the files git tracks in '{}': {}", path, error).red());
+                    return;
+                },
+            }
+        } else {
+            None
+        };
+
+        // Don't index our own output if the index lives inside the source tree.
+        let options = IndexOptions {
+            excluded_directories: vec![index_directory.clone()],
+            default_encoding,
+            binary_handling,
+            max_file_size,
+            follow_symlinks,
+            one_file_system,
+            jobs,
+            git_tracked_files,
+            git_commit_files,
+        };
+        let Some(builder) = parse_tree_builder(&cmd_args) else {
+            return;
+        };
+
+        // Only created once the options check out, so that e.g. '--ref typo' doesn't
+        // leave an empty index directory behind. Other commands never create it.
+        std::fs::create_dir_all(&index_directory).unwrap();
+
+        // Shards are written as indexing goes, see StagingDirectory.
+        let staging_directory = StagingDirectory::create(&index_directory).unwrap();
+
+        // Recorded so that 'update' knows what changed since. Taken up front in case
+        // HEAD moves while indexing.
+        let commit = git_commit
+            .or_else(|| Repository::find(path).and_then(|repository| repository.resolve_revision("HEAD").ok()))
+            .map(|id| to_hex(&id));
+
+        let progress = Arc::new(IndexingProgress::default());
+        let reporter = (!quiet).then(|| tokio::spawn(report_progress(progress.clone())));
 
-        print_with_color("Saving index...".cyan());
+        // Clustering and writing shards is slow, so the writer runs on the blocking pool,
+        // fed through a bounded channel so that it can't fall too far behind.
+        let mut writer = builder.writer(&staging_directory.path);
+        let (entry_sender, entry_receiver) = std::sync::mpsc::sync_channel::<FileEntry>(WRITER_BUFFER);
+        let writing = tokio::task::spawn_blocking(move || {
+            entry_receiver.iter().for_each(|entry| writer.add_file(entry));
+            writer.finish()
+        });
+
+        let indexing_stats = rust_indexer::index::stream_index_directory(path, &options, &progress, |entry| {
+            // Only wait on the writer, blocking this thread, when it's behind.
+            if let Err(TrySendError::Full(entry)) = entry_sender.try_send(entry) {
+                tokio::task::block_in_place(|| entry_sender.send(entry)).unwrap();
+            }
+        }).await;
+        drop(entry_sender);
+
+        if let Some(reporter) = reporter {
+            reporter.abort();
+            finish_progress();
+
+            print_indexing_stats(&indexing_stats, &progress.snapshot(), binary_handling);
+            print_with_color("Saving index...".cyan());
+        }
+
+        let mut index_tree = writing.await.unwrap();
+
+        // Readers wait on this lock, so take it only once the slow part is over.
+        let lock = IndexLock::exclusive(&index_directory).unwrap();
+
+        index_tree.move_shards(&index_directory).unwrap();
+        drop(staging_directory);
         index_tree.save(&index_root_path);
+        let mut manifest = IndexManifest::new(path, options.default_encoding);
+        manifest.commit = commit;
+        manifest.binary_handling = binary_handling;
+        manifest.max_file_size = max_file_size;
+        manifest.follow_symlinks = follow_symlinks;
+        manifest.one_file_system = one_file_system;
+        manifest.git_tracked = git_tracked_only;
+        manifest.git_submodules = git_submodules;
+        manifest.git_ref = git_ref.map(String::from);
+        manifest.save(&index_directory);
+        index_tree.remove_unreferenced_shards(&index_directory).unwrap();
+        lock.bump_generation().unwrap();
+
+        drop(lock);
+
+        if !quiet {
+            print_with_color("Done!".green());
+        }
+    } else if command == "update" {
+        // Held from reading the manifest to writing it back, so that an index rebuilt
+        // in the meantime isn't overwritten with what was read before.
+        let lock = match IndexLock::exclusive_existing(&index_directory) {
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                print_not_indexed(git_ref);
+                return;
+            },
+            lock => lock.unwrap(),
+        };
+
+        let Some(mut manifest) = check_manifest(&index_directory, path, git_ref) else {
+            return;
+        };
+
+        let Some(repository) = Repository::find(path) else {
+            print_with_color(format!("'{}' isn't in a git repository.", path).red());
+            return;
+        };
+
+        let Some(since) = cmd_args.option("--since").map(String::from).or(manifest.commit.clone()) else {
+            print_with_color("No commit was recorded when indexing. Pass --since [commit].".red());
+            return;
+        };
+
+        // The index of a ref catches up with wherever the ref is now.
+        let indexed_ref = manifest.git_ref.clone();
+        let revisions = repository
+            .resolve_revision(&since)
+            .and_then(|old| Ok((old, repository.resolve_revision(indexed_ref.as_deref().unwrap_or("HEAD"))?)));
+
+        let changes = revisions
+            .and_then(|(old, new)| Ok((repository.changes_between(&old, &new)?, new)));
+
+        let (changes, new_commit) = match changes {
+            Ok(changes) => changes,
+            Err(error) => {
+                print_with_color(format!("Couldn't read what changed since '{}': {}", since, error).red());
+                return;
+            },
+        };
+
+        // Changes are relative to the root of the repository, the index to the folder.
+        let prefix = repository.relative_path(path).unwrap();
+        let paths: Vec<String> = changes.added
+            .iter()
+            .chain(changes.modified.iter())
+            .chain(changes.deleted.iter())
+            .filter_map(|changed| strip_path_prefix(changed, &prefix))
+            .collect();
 
-        print_with_color("Done!".green());
+        let git_commit_files = match indexed_ref.as_deref() {
+            Some(name) => match read_commit_files(path, name, &new_commit) {
+                Some(files) => Some(files),
+                None => return,
+            },
+            None => None,
+        };
+
+        // Files are indexed the same way as by 'index', with the options it was given,
+        // leaving out the index itself the same way too. Whatever git says changed is
+        // tracked, so --git-tracked needs nothing more.
+        let options = IndexOptions {
+            excluded_directories: vec![index_directory.clone()],
+            default_encoding: manifest.default_encoding,
+            binary_handling: manifest.binary_handling,
+            max_file_size: manifest.max_file_size,
+            follow_symlinks: manifest.follow_symlinks,
+            one_file_system: manifest.one_file_system,
+            git_commit_files,
+            ..Default::default()
+        };
+
+        let mut index_tree = IndexTree::from_file(&index_root_path);
+        let update_stats = index_tree.update_files(path, &paths, &options, &index_directory);
+        index_tree.save(&index_root_path);
+
+        manifest.commit = Some(to_hex(&new_commit));
+        manifest.save(&index_directory);
+        index_tree.remove_unreferenced_shards(&index_directory).unwrap();
+        lock.bump_generation().unwrap();
+
+        drop(lock);
+
+        print_with_color(format!(
+            "{} files changed since {}: {} reindexed, {} added, {} removed.",
+            paths.len(),
+            since,
+            update_stats.reindexed_count,
+            update_stats.added_count,
+            update_stats.removed_count).cyan());
     } else if command == "search" {
-        if cmd_args.len() != 4 {
+        if cmd_args.positional.len() != 3 {
             print_help();
             return;
         }
 
-        let query = cmd_args.get(3).unwrap();
-        let index_tree = IndexTree::from_file(&index_root_path);
+        let query = parse_query(&cmd_args, &cmd_args.positional[2], languages, region);
 
-        let (matching_files, comparisons) = get_matching_files(&index_tree, &query).await;
+        let Some((lock, manifest)) = lock_index(&index_directory, path, git_ref) else {
+            return;
+        };
 
-        scrape_and_format_matches(&matching_files, &query).await;
+        let Some(commit_files) = load_commit_files(path, &manifest, git_ref) else {
+            return;
+        };
+
+        let index_tree = IndexTree::from_file(&index_root_path);
+        let (matching_files, name_only_paths, comparisons) = get_matching_files(&index_tree, path, &query, jobs).await;
+        drop(lock);
+
+        scrape_and_format_matches(&matching_files, &name_only_paths, commit_files, &query, &scrape_options(&cmd_args, &manifest, jobs, max_results, max_files), cmd_args.switch("--rank")).await;
 
         print_perf_stats(&matching_files, &index_tree, comparisons);
 
-    } else if command == "repl" {
+    } else if command == "symbol" {
+        if cmd_args.positional.len() != 3 {
+            print_help();
+            return;
+        }
+
+        let Some((lock, _)) = lock_index(&index_directory, path, git_ref) else {
+            return;
+        };
+
         let index_tree = IndexTree::from_file(&index_root_path);
+        let symbols = tokio::task::block_in_place(|| index_tree.find_symbols(cmd_args.positional[2].trim(), jobs_count(jobs)));
+        drop(lock);
+
+        print_symbols(&symbols, path, max_results.unwrap_or(DEFAULT_MAX_SYMBOLS));
+    } else if command == "stats" {
+        let Some((lock, _)) = lock_index(&index_directory, path, git_ref) else {
+            return;
+        };
+
+        let stats = IndexTree::from_file(&index_root_path).stats();
+        drop(lock);
+
+        print_index_stats(&stats);
+    } else if command == "repl" {
+        let Some((lock, mut manifest)) = lock_index(&index_directory, path, git_ref) else {
+            return;
+        };
+
+        let Some(mut commit_files) = load_commit_files(path, &manifest, git_ref) else {
+            return;
+        };
+
+        let mut index_tree = IndexTree::from_file(&index_root_path);
+        let mut index_generation = lock.generation();
+        drop(lock);
 
         loop {
-            let query = prompt_for_input("Search >");
-            let (matching_files, comparisons) = get_matching_files(&index_tree, &query).await;
-    
-            scrape_and_format_matches(&matching_files, &query).await;
-    
+            let query = parse_query(&cmd_args, &prompt_for_input("Search >"), languages, region);
+
+            // Another process may have rebuilt the index since the last query,
+            // in which case the shards our tree points at are gone.
+            let lock = IndexLock::shared(&index_directory).unwrap();
+            if lock.generation() != index_generation {
+                print_with_color("Index changed, reloading...".cyan());
+                let Some(reloaded_manifest) = check_manifest(&index_directory, path, git_ref) else {
+                    return;
+                };
+
+                let Some(reloaded_commit_files) = load_commit_files(path, &reloaded_manifest, git_ref) else {
+                    return;
+                };
+
+                manifest = reloaded_manifest;
+                commit_files = reloaded_commit_files;
+                index_tree = IndexTree::from_file(&index_root_path);
+                index_generation = lock.generation();
+            }
+
+            let (matching_files, name_only_paths, comparisons) = get_matching_files(&index_tree, path, &query, jobs).await;
+            drop(lock);
+
+            scrape_and_format_matches(&matching_files, &name_only_paths, commit_files.clone(), &query, &scrape_options(&cmd_args, &manifest, jobs, max_results, max_files), cmd_args.switch("--rank")).await;
+
             print_perf_stats(&matching_files, &index_tree, comparisons);
         }
     } else {
@@ -65,8 +403,181 @@ fn print_help() {
     println!();
     print_with_color("Usage:".white());
     print_with_color("  rust-indexer [index] [path] -- reindex folder.".white());
+    print_with_color("  rust-indexer [update] [path] -- reindex files changed in git since the index was built, with the options it was built with.".white());
     print_with_color("  rust-indexer [search] [path] [query] -- find matches.".white());
+    print_with_color("  rust-indexer [symbol] [path] [name] -- find definitions of functions, methods and types by name.".white());
     print_with_color("  rust-indexer [repl] [path] -- keep alive. Potentially faster.".white());
+    print_with_color("  rust-indexer [stats] [path] -- show the shape of the index and how well it prunes.".white());
+    println!();
+    print_with_color("Options:".white());
+    print_with_color("  --index-dir [path] -- where to keep the index. Defaults to $RUST_INDEXER_INDEX_DIR,".white());
+    print_with_color("                        then a folder per source path under the user's cache folder.".white());
+    print_with_color("  --fan-out [n] -- when indexing, maximum children per index tree node.".white());
+    print_with_color("  --leaf-size [n] -- when indexing, maximum files per index shard.".white());
+    print_with_color("  --cluster [none|directory|similarity] -- when indexing, how to group files in the index tree.".white());
+    print_with_color("  --encoding [latin1|utf-8|utf-16le|utf-16be] -- when indexing, the encoding of files that".white());
+    print_with_color("                        have no BOM and aren't valid UTF-8. Defaults to latin1.".white());
+    print_with_color("  --binary [skip|text|name-only] -- when indexing, what to do with files that look binary.".white());
+    print_with_color("  --max-file-size [n|nK|nM|nG|none] -- when indexing, skip files bigger than this. Defaults to 100M.".white());
+    print_with_color("  --follow-symlinks -- when indexing, include symlinked files and folders.".white());
+    print_with_color("  --one-file-system -- when indexing, don't descend into other mounted file systems.".white());
+    print_with_color("  --git-tracked -- when indexing, only index files tracked by git, like 'git ls-files'.".white());
+    print_with_color("  --git-submodules -- same as --git-tracked, plus files in checked out submodules.".white());
+    print_with_color("  --ref [branch|tag|commit] -- index, update, search or show stats for a commit, read from git".white());
+    print_with_color("                        objects instead of the folder. Each ref has an index of its own.".white());
+    print_with_color("  --since [commit] -- when updating, reindex files changed since this commit instead.".white());
+    print_with_color("  --quiet -- when indexing, don't print progress or anything else but errors.".white());
+    print_with_color("  --jobs [n] -- how many threads to index, search and scrape with. Defaults to one per CPU.".white());
+    print_with_color("  --max-results [n] -- when searching, stop after n matches. When looking up symbols, show n. Defaults to 50.".white());
+    print_with_color("  --max-files [n] -- when searching, stop after n files with matches.".white());
+    print_with_color("  --sorted -- when searching, print files in path order instead of as they're found.".white());
+    print_with_color("  --rank -- when searching, print the most relevant files first.".white());
+    print_with_color("  --lang [names] -- when searching, only search files in these comma separated languages, e.g. rust,ts.".white());
+    print_with_color("  --in [code|comment|string] -- when searching, only show matches in code, comments or string literals.".white());
+    print_with_color("  --any -- when searching, match files containing any of the space separated terms.".white());
+    print_with_color("  --all -- when searching, match files containing all of the space separated terms.".white());
+}
+
+struct CommandLine {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+    switches: HashSet<String>,
+}
+
+impl CommandLine {
+    fn parse(args: impl Iterator<Item = String>) -> CommandLine {
+        let mut command_line = CommandLine {
+            positional: Vec::new(),
+            options: HashMap::new(),
+            switches: HashSet::new(),
+        };
+
+        let mut args = args.peekable();
+
+        while let Some(arg) = args.next() {
+            if !arg.starts_with("--") {
+                command_line.positional.push(arg);
+            } else if let Some((name, value)) = arg.split_once('=') {
+                command_line.options.insert(name.to_string(), value.to_string());
+            } else if VALUE_OPTIONS.contains(&arg.as_str()) {
+                if let Some(value) = args.next() {
+                    command_line.options.insert(arg, value);
+                }
+            } else {
+                command_line.switches.insert(arg);
+            }
+        }
+
+        command_line
+    }
+
+    fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(|value| value.as_str())
+    }
+
+    fn switch(&self, name: &str) -> bool {
+        self.switches.contains(name)
+    }
+}
+
+// Makes sure the index can be used with this version of the app, and lets the user know
+// if it was built somewhere else, since its paths will be resolved against 'source_path'.
+fn check_manifest(index_directory: &str, source_path: &str, git_ref: Option<&str>) -> Option<IndexManifest> {
+    let Some(manifest) = IndexManifest::from_index_directory(index_directory) else {
+        print_not_indexed(git_ref);
+        return None;
+    };
+
+    if !manifest.is_compatible() {
+        print_with_color("Index was built by a different version of rust-indexer. Please reindex.".red());
+        return None;
+    }
+
+    if manifest.is_relocated(source_path) {
+        print_with_color(format!(
+            "Index was built from '{}', resolving paths against '{}'.",
+            manifest.source_root,
+            source_path).cyan());
+    }
+
+    Some(manifest)
+}
+
+// Takes the shared lock and checks the manifest, for reading the index. Readers
+// don't create the index directory, so one that was never indexed is reported
+// rather than left behind empty.
+fn lock_index(index_directory: &str, source_path: &str, git_ref: Option<&str>) -> Option<(IndexLock, IndexManifest)> {
+    let lock = match IndexLock::shared(index_directory) {
+        Err(error) if error.kind() == ErrorKind::NotFound => {
+            print_not_indexed(git_ref);
+            return None;
+        },
+        lock => lock.unwrap(),
+    };
+
+    let manifest = check_manifest(index_directory, source_path, git_ref)?;
+
+    Some((lock, manifest))
+}
+
+fn print_not_indexed(git_ref: Option<&str>) {
+    match git_ref {
+        Some(name) => print_with_color(format!("'{}' isn't indexed. Run 'rust-indexer index [path] --ref {}' first.", name, name).red()),
+        None => print_with_color("No index found. Run 'rust-indexer index [path]' first.".red()),
+    }
+}
+
+fn resolve_git_ref(path: &str, name: &str) -> Option<ObjectId> {
+    let repository = Repository::find(path)?;
+
+    match repository.resolve_revision(name) {
+        Ok(commit) => Some(commit),
+        Err(error) => {
+            print_with_color(format!("Couldn't resolve '{}': {}", name, error).red());
+            None
+        },
+    }
+}
+
+// With --ref, matches are scraped from the commit the ref's index was built from, which
+// may not be where the ref is now. None if that fails, Some(None) without --ref.
+fn load_commit_files(path: &str, manifest: &IndexManifest, git_ref: Option<&str>) -> Option<Option<Arc<CommitFiles>>> {
+    let Some(name) = git_ref else {
+        return Some(None);
+    };
+
+    let Some(commit) = manifest.commit.as_deref().and_then(from_hex) else {
+        print_with_color(format!("The index of '{}' doesn't say which commit it's from. Please reindex.", name).red());
+        return None;
+    };
+
+    let short_commit = &to_hex(&commit)[..7];
+
+    let current = Repository::find(path).and_then(|repository| repository.resolve_revision(name).ok());
+    if current.is_some_and(|current| current != commit) {
+        print_with_color(format!(
+            "'{}' has moved on since it was indexed at {}. Run 'rust-indexer update [path] --ref {}' to catch up.",
+            name,
+            short_commit,
+            name).cyan());
+    }
+
+    let files = read_commit_files(path, name, &commit)?;
+    print_with_color(format!("Searching '{}' at {}.", name, short_commit).cyan());
+
+    Some(Some(files))
+}
+
+// The files of 'commit', which 'name' resolved to, or None if its objects can't be
+// read, e.g. after they were pruned or in a shallow clone.
+fn read_commit_files(path: &str, name: &str, commit: &[u8]) -> Option<Arc<CommitFiles>> {
+    match commit_files(path, commit) {
+        Ok(files) => Some(Arc::new(files)),
+        Err(error) => {
+            print_with_color(format!("Couldn't read '{}' at {}: {}", name, &to_hex(commit)[..7], error).red());
+            None
+        },
+    }
 }
 
 fn prompt_for_input(prompt: &str) -> String {
@@ -77,25 +588,383 @@ fn prompt_for_input(prompt: &str) -> String {
     buffer
 }
 
-async fn get_matching_files(index: &IndexTree, query: &str) -> (Vec<String>, usize) {
-    let matches = index.search_files(&query.trim());
-    let mut ordered_matches: Vec<String> = Vec::from_iter(matches.0);
+// By default the whole query is matched literally. --any and --all split it into
+// terms, of which any or all have to be in a file for it to match. --lang limits it
+// to files in some languages, and --in to matches in code, comments or strings.
+fn parse_query(cmd_args: &CommandLine, text: &str, languages: Option<LanguageSet>, region: Option<Region>) -> Query {
+    let query = if cmd_args.switch("--any") {
+        Query::any_of(text)
+    } else if cmd_args.switch("--all") {
+        Query::all_of(text)
+    } else {
+        Query::literal(text.trim())
+    };
+
+    let query = match languages {
+        Some(languages) => query.in_languages(languages),
+        None => query,
+    };
+
+    match region {
+        Some(region) => query.in_region(region),
+        None => query,
+    }
+}
+
+// The files to scrape, binaries indexed by name only mapped to the path they were
+// indexed under, and how many bloom filters were checked.
+async fn get_matching_files(index: &IndexTree, source_path: &str, query: &Query, jobs: Option<usize>) -> (Vec<String>, HashMap<String, String>, usize) {
+    // The search runs on its own threads; let the runtime know this task blocks
+    // until they're done.
+    let results = tokio::task::block_in_place(|| index.parallel_search_query(query, jobs_count(jobs)));
+
+    // The index stores paths relative to the indexed folder.
+    let resolve = |file: &String| resolve_indexed_path(source_path, file).to_string_lossy().to_string();
+
+    let mut ordered_matches: Vec<String> = results.files.iter().map(resolve).collect();
     ordered_matches.sort();
 
-    (ordered_matches, matches.1)
+    let name_only_paths = results.name_only_files
+        .iter()
+        .map(|file| (resolve(file), file.clone()))
+        .collect();
+
+    (ordered_matches, name_only_paths, results.bloom_filters_checked)
+}
+
+fn scrape_options(cmd_args: &CommandLine, manifest: &IndexManifest, jobs: Option<usize>, max_results: Option<usize>, max_files: Option<usize>) -> ScrapeOptions {
+    ScrapeOptions {
+        max_results,
+        max_files,
+        ordered: cmd_args.switch("--sorted"),
+        default_encoding: manifest.default_encoding,
+        jobs,
+    }
 }
 
-async fn scrape_and_format_matches(files: &Vec<String>, query: &str) {
-    let scrapings = text_scraping::parallel_scrape_files(&files, &query.trim()).await;
+// --fan-out, --leaf-size and --cluster, or None if one of them is invalid.
+fn parse_tree_builder(cmd_args: &CommandLine) -> Option<IndexTreeBuilder> {
+    let mut builder = IndexTreeBuilder::new();
+
+    if let Some(fan_out) = parse_number(cmd_args, "--fan-out", 1)? {
+        builder = builder.fan_out(fan_out);
+    }
+
+    if let Some(leaf_size) = parse_number(cmd_args, "--leaf-size", 1)? {
+        builder = builder.leaf_size(leaf_size);
+    }
+
+    if let Some(name) = cmd_args.option("--cluster") {
+        let Some(clustering) = Clustering::from_name(name) else {
+            print_with_color(format!("Unknown clustering '{}'.", name).red());
+            return None;
+        };
+
+        builder = builder.clustering(clustering);
+    }
+
+    Some(builder)
+}
 
-    for scraped_match in scrapings {
-        println!("In '{}'...", scraped_match.file_path.black().on_cyan());
-        println!("{}", scraped_match.text.italic().yellow());
+// None if the value isn't a number of at least 'minimum', Some(None) if the option
+// isn't given.
+fn parse_number(cmd_args: &CommandLine, option: &str, minimum: usize) -> Option<Option<usize>> {
+    let Some(value) = cmd_args.option(option) else {
+        return Some(None);
+    };
+
+    match value.parse::<usize>() {
+        Ok(number) if number >= minimum => Some(Some(number)),
+        _ => {
+            print_with_color(format!("Invalid value '{}' for {}.", value, option).red());
+            None
+        },
+    }
+}
+
+fn parse_binary_handling(cmd_args: &CommandLine) -> Option<BinaryHandling> {
+    let Some(name) = cmd_args.option("--binary") else {
+        return Some(BinaryHandling::default());
+    };
+
+    let binary_handling = BinaryHandling::from_name(name);
+    if binary_handling.is_none() {
+        print_with_color(format!("Unknown binary handling '{}'.", name).red());
+    }
+
+    binary_handling
+}
+
+// None if the value is invalid, Some(None) if there's no limit.
+fn parse_max_file_size(cmd_args: &CommandLine) -> Option<Option<u64>> {
+    let value = cmd_args.option("--max-file-size").unwrap_or(DEFAULT_MAX_FILE_SIZE);
+
+    if value == "none" {
+        return Some(None);
+    }
+
+    let (number, multiplier) = match value.to_lowercase().chars().last() {
+        Some('k') => (&value[..value.len() - 1], 1024),
+        Some('m') => (&value[..value.len() - 1], 1024 * 1024),
+        Some('g') => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+
+    // Sizes too big to count in bytes are as invalid as ones that aren't numbers.
+    let Some(size) = number.parse::<u64>().ok().and_then(|number| number.checked_mul(multiplier)) else {
+        print_with_color(format!("Invalid file size '{}'.", value).red());
+        return None;
+    };
+
+    Some(Some(size))
+}
+
+// None if the value is invalid, Some(None) if it isn't given.
+fn parse_jobs(cmd_args: &CommandLine) -> Option<Option<usize>> {
+    let Some(value) = cmd_args.option("--jobs") else {
+        return Some(None);
+    };
+
+    match value.parse::<usize>() {
+        Ok(jobs) if jobs > 0 => Some(Some(jobs)),
+        _ => {
+            print_with_color(format!("Invalid number of jobs '{}'.", value).red());
+            None
+        },
+    }
+}
+
+// None if a name is invalid, Some(None) if --lang isn't given.
+fn parse_languages(cmd_args: &CommandLine) -> Option<Option<LanguageSet>> {
+    let Some(names) = cmd_args.option("--lang") else {
+        return Some(None);
+    };
+
+    match LanguageSet::from_names(names) {
+        Ok(languages) if !languages.is_empty() => Some(Some(languages)),
+        Ok(_) => {
+            print_with_color("No languages given to --lang.".red());
+            None
+        },
+        Err(name) => {
+            print_with_color(format!("Unknown language '{}'.", name).red());
+            None
+        },
+    }
+}
+
+// None if it isn't a region, Some(None) if --in isn't given.
+fn parse_region(cmd_args: &CommandLine) -> Option<Option<Region>> {
+    let Some(name) = cmd_args.option("--in") else {
+        return Some(None);
+    };
+
+    match Region::from_name(name) {
+        Some(region) => Some(Some(region)),
+        None => {
+            print_with_color(format!("Unknown region '{}', expected code, comment or string.", name).red());
+            None
+        },
+    }
+}
+
+fn parse_encoding(cmd_args: &CommandLine) -> Option<Encoding> {
+    let Some(name) = cmd_args.option("--encoding") else {
+        return Some(Encoding::default());
+    };
+
+    let encoding = Encoding::from_name(name);
+    if encoding.is_none() {
+        print_with_color(format!("Unknown encoding '{}'.", name).red());
+    }
+
+    encoding
+}
+
+async fn scrape_and_format_matches(files: &[String], name_only_paths: &HashMap<String, String>, commit_files: Option<Arc<CommitFiles>>, query: &Query, options: &ScrapeOptions, rank: bool) {
+    // Ranking needs every match up front, otherwise print as we go, since common
+    // queries can match thousands of files. When ranking, limits apply to the ranked
+    // files rather than to whichever files happened to be scraped first.
+    let mut all_matches = Vec::new();
+
+    let highlight = highlighting_enabled();
+
+    let scrape_options = if rank {
+        ScrapeOptions { max_results: None, max_files: None, ..*options }
+    } else {
+        *options
+    };
+
+    let on_matches = |matches: &[Match]| {
+        if rank {
+            all_matches.extend_from_slice(matches);
+        } else {
+            print_matches(matches, None, highlight);
+        }
+    };
+
+    let mut summary = match commit_files {
+        Some(commit_files) => text_scraping::stream_scrape_commit_files(files, name_only_paths, commit_files, query, &scrape_options, on_matches).await,
+        None => text_scraping::stream_scrape_files(files, name_only_paths, query, &scrape_options, on_matches).await,
+    };
+
+    if rank {
+        summary = ScrapeSummary {
+            unreadable_files_count: summary.unreadable_files_count,
+            ..print_ranked_matches(all_matches, options, highlight)
+        };
+    }
+
+    if summary.truncated {
+        print_with_color(format!(
+            "Stopped after {} matches in {} files.",
+            summary.matches_count,
+            summary.files_count).cyan());
+    }
+
+    if summary.unreadable_files_count > 0 {
+        print_with_color(format!("Couldn't read {} files, which were skipped.", summary.unreadable_files_count).red());
+    }
+}
+
+// Best files first, stopping at --max-results or --max-files.
+fn print_ranked_matches(matches: Vec<Match>, options: &ScrapeOptions, highlight: bool) -> ScrapeSummary {
+    let found_count = matches.len();
+    let mut summary = ScrapeSummary::default();
+
+    for ranked_file in ranking::rank_matches(matches) {
+        if options.max_results.is_some_and(|max| summary.matches_count >= max) ||
+            options.max_files.is_some_and(|max| summary.files_count >= max) {
+            break;
+        }
+
+        let count = options.max_results.map_or(ranked_file.matches.len(), |max| ranked_file.matches.len().min(max - summary.matches_count));
+
+        print_matches(&ranked_file.matches[..count], Some(ranked_file.score), highlight);
+
+        summary.matches_count += count;
+        summary.files_count += 1;
+    }
+
+    summary.truncated = summary.matches_count < found_count;
+    summary
+}
+
+// Matches from a single file, under one heading.
+fn print_matches(matches: &[Match], score: Option<f32>, highlight: bool) {
+    let Some(first) = matches.first() else {
+        return;
+    };
+
+    match score {
+        Some(score) => println!("In '{}' (score {:.2})...", first.file_path.black().on_cyan(), score),
+        None => println!("In '{}'...", first.file_path.black().on_cyan()),
+    }
+
+    for scraped_match in matches {
+        print_context(scraped_match, highlight);
         println!();
     }
 }
 
-fn print_perf_stats(matching_files: &Vec<String>, index: &IndexTree, comparisons: usize) {
+// Colors are left out when output goes to a file or another program, or NO_COLOR is
+// set, as colored itself does.
+fn highlighting_enabled() -> bool {
+    std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+}
+
+// The context lines with line numbers in a gutter, e.g. ' 12 │ let x = 1;', or
+// 'path │' for matches in the path of a binary. When highlighting, they're colored by
+// language and the match itself stands out.
+fn print_context(scraped_match: &Match, highlight: bool) {
+    let text = scraped_match.text.trim_end_matches(['\r', '\n']);
+    let last_line = scraped_match.context_line + text.matches('\n').count();
+    let gutter_width = last_line.to_string().len();
+
+    let tokens = if highlight {
+        highlighting::highlight(scraped_match.language, text, &scraped_match.context_regions)
+    } else {
+        Vec::new()
+    };
+
+    let match_start = scraped_match.context_offset.min(text.len());
+    let match_end = (scraped_match.context_offset + scraped_match.context_length).min(text.len());
+
+    let mut line_start = 0;
+
+    for (i, raw_line) in text.split('\n').enumerate() {
+        let line = raw_line.trim_end_matches('\r');
+        let line_end = line_start + line.len();
+
+        let gutter = if scraped_match.in_path {
+            "path │".to_string()
+        } else {
+            format!("{:>width$} │", scraped_match.context_line + i, width = gutter_width)
+        };
+        let mut rendered = String::new();
+
+        if !highlight {
+            rendered += line;
+        } else {
+            for token in tokens.iter().filter(|token| token.end > line_start && token.start < line_end) {
+                let start = token.start.max(line_start);
+                let end = token.end.min(line_end);
+
+                // The part of the token inside the match is styled apart from the rest.
+                let mut cuts = vec![start, end];
+                cuts.extend([match_start, match_end].into_iter().filter(|cut| *cut > start && *cut < end && text.is_char_boundary(*cut)));
+                cuts.sort();
+
+                for piece in cuts.windows(2) {
+                    let matched = piece[0] >= match_start && piece[1] <= match_end && match_start < match_end;
+                    rendered += &style_token(&text[piece[0]..piece[1]], token.kind, matched).to_string();
+                }
+            }
+        }
+
+        // No trailing space after the gutter of a blank line.
+        if rendered.is_empty() {
+            println!("{}", gutter.dimmed());
+        } else {
+            println!("{} {}", gutter.dimmed(), rendered);
+        }
+
+        line_start += raw_line.len() + 1;
+    }
+}
+
+fn style_token(text: &str, kind: TokenKind, matched: bool) -> ColoredString {
+    if matched {
+        return text.black().on_yellow().bold();
+    }
+
+    match kind {
+        TokenKind::Plain => text.normal(),
+        TokenKind::Keyword => text.magenta(),
+        TokenKind::Number => text.cyan(),
+        TokenKind::Comment => text.bright_black().italic(),
+        TokenKind::String => text.green(),
+    }
+}
+
+// Best matches first, e.g. 'method  start  src/server.rs:12'.
+fn print_symbols(symbols: &[SymbolMatch], source_path: &str, max_symbols: usize) {
+    if symbols.is_empty() {
+        print_with_color("No definitions found.".cyan());
+        return;
+    }
+
+    for found in symbols.iter().take(max_symbols) {
+        let location = format!("{}:{}", resolve_indexed_path(source_path, &found.file_path).display(), found.symbol.line);
+        println!("{:<8}  {}  {}", found.symbol.kind.name(), found.symbol.name.yellow(), location.cyan());
+    }
+
+    if symbols.len() > max_symbols {
+        print_with_color(format!("Showing {} of {} definitions.", max_symbols, symbols.len()).cyan());
+    }
+}
+
+fn print_perf_stats(matching_files: &[String], index: &IndexTree, comparisons: usize) {
     let files_matched_percentage = (matching_files.len() as f32 / index.files_count() as f32) * 100f32;
 
     // Percentage of file bloom filters checked. Not technically accurate because this a count
@@ -113,6 +982,164 @@ fn print_perf_stats(matching_files: &Vec<String>, index: &IndexTree, comparisons
         bloom_comparisons_percentage);
 }
 
+// Redraws a progress bar on stderr while indexing if it's a terminal, or logs a line
+// every so often if it isn't, e.g. in CI. Runs until aborted.
+async fn report_progress(progress: Arc<IndexingProgress>) {
+    let terminal = std::io::stderr().is_terminal();
+    let interval = if terminal { PROGRESS_REDRAW_INTERVAL } else { PROGRESS_LOG_INTERVAL };
+
+    let start = Instant::now();
+    let mut previous = (progress.snapshot(), start);
+
+    loop {
+        tokio::time::sleep(interval).await;
+
+        let snapshot = progress.snapshot();
+        let now = Instant::now();
+
+        let line = format_progress(
+            &snapshot,
+            snapshot.throughput_since(&previous.0, now - previous.1),
+            snapshot.estimated_time_left(now - start),
+            terminal);
+
+        if terminal {
+            eprint!("\r\x1b[K{}", line);
+        } else {
+            eprintln!("{}", line);
+        }
+
+        previous = (snapshot, now);
+    }
+}
+
+// Clears the progress bar, if one was drawn, so what's printed next starts on a clean line.
+fn finish_progress() {
+    if std::io::stderr().is_terminal() {
+        eprint!("\r\x1b[K");
+    }
+}
+
+fn format_progress(snapshot: &ProgressSnapshot, throughput: (f64, f64), time_left: Option<Duration>, bar: bool) -> String {
+    let mut line = String::new();
+
+    // The total isn't known until the walk is over.
+    if snapshot.walk_finished {
+        let done = if snapshot.files_discovered == 0 { 1f64 } else { snapshot.files_indexed as f64 / snapshot.files_discovered as f64 };
+
+        if bar {
+            let filled = (done * PROGRESS_BAR_WIDTH as f64).round() as usize;
+            line.push_str(&format!("[{}{}] ", "#".repeat(filled), " ".repeat(PROGRESS_BAR_WIDTH - filled.min(PROGRESS_BAR_WIDTH))));
+        }
+
+        line.push_str(&format!("Indexed {} of {} files ({:.0}%)", snapshot.files_indexed, snapshot.files_discovered, done * 100f64));
+    } else {
+        line.push_str(&format!("Indexed {} of {}+ files", snapshot.files_indexed, snapshot.files_discovered));
+    }
+
+    line.push_str(&format!(", {} read", format_bytes(snapshot.bytes_processed as f64)));
+
+    if snapshot.errors > 0 {
+        line.push_str(&format!(", {} errors", snapshot.errors));
+    }
+
+    line.push_str(&format!(", {:.0} files/s, {}/s", throughput.0, format_bytes(throughput.1)));
+
+    if let Some(time_left) = time_left {
+        line.push_str(&format!(", {} left", format_duration(time_left)));
+    }
+
+    line
+}
+
+fn format_bytes(bytes: f64) -> String {
+    const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
+
+    let mut value = bytes;
+    let mut unit = 0;
+
+    while value >= 1024f64 && unit < UNITS.len() - 1 {
+        value /= 1024f64;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{:.0} {}", value, UNITS[unit])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+
+    if seconds >= 3600 {
+        format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+    } else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+fn print_indexing_stats(stats: &IndexingStats, progress: &ProgressSnapshot, binary_handling: BinaryHandling) {
+    print_with_color(format!("Indexed {} files ({}).", stats.files_count, format_bytes(progress.bytes_processed as f64)).cyan());
+
+    if stats.binary_files_count > 0 {
+        let handling = match binary_handling {
+            BinaryHandling::Skip => "skipped",
+            BinaryHandling::Text => "indexed as text",
+            BinaryHandling::NameOnly => "indexed by name only",
+        };
+
+        print_with_color(format!("{} files looked binary and were {}.", stats.binary_files_count, handling).cyan());
+    }
+
+    if stats.too_large_files_count > 0 {
+        print_with_color(format!("{} files were bigger than --max-file-size and were skipped.", stats.too_large_files_count).cyan());
+    }
+
+    if progress.errors > 0 {
+        print_with_color(format!("{} files couldn't be read and were skipped.", progress.errors).yellow());
+    }
+}
+
+fn print_index_stats(stats: &IndexTreeStats) {
+    println!("{} files in {} shards", stats.files_count, stats.shards_count);
+    println!();
+
+    // Pass rate is the share of filters at that level that a typical query gets
+    // past. Low rates near the root mean most of the tree is pruned.
+    println!("{:<8} {:>10} {:>10} {:>10}", "Level", "Filters", "Fill", "Pass rate");
+
+    for (i, level) in stats.levels.iter().enumerate() {
+        let name = if i + 1 == stats.levels.len() { String::from("files") } else { i.to_string() };
+
+        println!(
+            "{:<8} {:>10} {:>9.1}% {:>9.3}%",
+            name,
+            level.filters_count(),
+            level.average_fill_ratio() * 100f32,
+            level.estimated_pass_rate(TYPICAL_QUERY_TRIGRAMS) * 100f32);
+    }
+
+    println!();
+    println!("{:<10} {:>10}", "Encoding", "Files");
+
+    for (encoding, files_count) in &stats.encodings {
+        println!("{:<10} {:>10}", encoding, files_count);
+    }
+
+    // Most common first.
+    let mut languages: Vec<(&&str, &usize)> = stats.languages.iter().collect();
+    languages.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+
+    println!();
+    println!("{:<10} {:>10} {:>10}", "Language", "Files", "Share");
+
+    for (language, files_count) in languages {
+        println!("{:<10} {:>10} {:>9.1}%", language, files_count, *files_count as f32 / stats.files_count.max(1) as f32 * 100f32);
+    }
+}
+
 fn print_with_color(colored_str: ColoredString) {
     println!("{}", colored_str);
 }
@@ -0,0 +1,110 @@
+use std::fs::File;
+use std::io::{Read, Write};
+
+use rmp_serde::Serializer;
+use serde::{Deserialize, Serialize};
+
+use crate::binary::BinaryHandling;
+use crate::encoding::Encoding;
+
+// Bump when the on-disk index format changes incompatibly.
+pub const INDEX_FORMAT_VERSION: u32 = 7;
+
+const MANIFEST_FILE_NAME: &str = "manifest.dat";
+
+// Describes an index as a whole, as opposed to IndexTree, which describes its contents.
+// Lives next to root.dat in the index directory.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct IndexManifest {
+    pub format_version: u32,
+
+    // Canonical path of the folder that was indexed, on the machine that indexed it.
+    // File paths in the index are relative to it, so this is informational only: the
+    // index can be used against a copy of the folder anywhere else.
+    pub source_root: String,
+
+    // Encoding assumed for files without a BOM that aren't valid UTF-8. Searches
+    // must decode files the same way they were indexed.
+    pub default_encoding: Encoding,
+
+    // The git commit checked out when the folder was indexed or last updated, if it's
+    // in a repository. 'update' reindexes what changed since.
+    #[serde(default)]
+    pub commit: Option<String>,
+
+    // How the index was built, so that 'update' reindexes changed files the same way.
+    #[serde(default)]
+    pub binary_handling: BinaryHandling,
+    #[serde(default)]
+    pub max_file_size: Option<u64>,
+    #[serde(default)]
+    pub follow_symlinks: bool,
+    #[serde(default)]
+    pub git_tracked: bool,
+    #[serde(default)]
+    pub git_submodules: bool,
+
+    // The branch, tag or commit indexed with --ref, as it was given.
+    #[serde(default)]
+    pub git_ref: Option<String>,
+
+    // Manifests are saved as arrays, so options added later go last, where older
+    // manifests just end early.
+    #[serde(default)]
+    pub one_file_system: bool,
+}
+
+impl IndexManifest {
+    pub fn new(source_root: &str, default_encoding: Encoding) -> IndexManifest {
+        let canonical_source_root = std::fs::canonicalize(source_root)
+            .map(|path| path.to_string_lossy().to_string())
+            .unwrap_or(source_root.to_string());
+
+        IndexManifest {
+            format_version: INDEX_FORMAT_VERSION,
+            source_root: canonical_source_root,
+            default_encoding,
+            commit: None,
+            binary_handling: BinaryHandling::default(),
+            max_file_size: None,
+            follow_symlinks: false,
+            git_tracked: false,
+            git_submodules: false,
+            git_ref: None,
+            one_file_system: false,
+        }
+    }
+
+    // Returns None for indexes that predate manifests or can't be read.
+    pub fn from_index_directory(index_directory: &str) -> Option<IndexManifest> {
+        let mut buf = Vec::new();
+        let mut manifest_read = File::open(manifest_path(index_directory)).ok()?;
+        manifest_read.read_to_end(&mut buf).ok()?;
+
+        rmp_serde::from_slice(&buf).ok()
+    }
+
+    pub fn save(&self, index_directory: &str) {
+        let mut buf = Vec::new();
+        self.serialize(&mut Serializer::new(&mut buf)).unwrap();
+
+        let mut file = File::create(manifest_path(index_directory)).unwrap();
+        file.write_all(&buf).unwrap();
+    }
+
+    pub fn is_compatible(&self) -> bool {
+        self.format_version == INDEX_FORMAT_VERSION
+    }
+
+    // True if the index was built from a different location than the one it is
+    // being used with, e.g. an index built in CI and downloaded to a laptop.
+    pub fn is_relocated(&self, source_path: &str) -> bool {
+        std::fs::canonicalize(source_path)
+            .map(|path| path.to_string_lossy() != self.source_root)
+            .unwrap_or(true)
+    }
+}
+
+fn manifest_path(index_directory: &str) -> String {
+    format!("{}/{}", index_directory, MANIFEST_FILE_NAME)
+}
@@ -0,0 +1,139 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+// Live counters for an indexing run, updated from the walker and indexing threads and
+// read from anywhere through snapshot(), e.g. to draw a progress bar.
+#[derive(Default)]
+pub struct IndexingProgress {
+    files_discovered: AtomicUsize,
+    files_indexed: AtomicUsize,
+    bytes_processed: AtomicU64,
+    errors: AtomicUsize,
+    walk_finished: AtomicBool,
+}
+
+impl IndexingProgress {
+    pub fn snapshot(&self) -> ProgressSnapshot {
+        ProgressSnapshot {
+            files_discovered: self.files_discovered.load(Ordering::Relaxed),
+            files_indexed: self.files_indexed.load(Ordering::Relaxed),
+            bytes_processed: self.bytes_processed.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            walk_finished: self.walk_finished.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn file_discovered(&self) {
+        self.files_discovered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn file_indexed(&self) {
+        self.files_indexed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn bytes_read(&self, bytes: usize) {
+        self.bytes_processed.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn walk_finished(&self) {
+        self.walk_finished.store(true, Ordering::Relaxed);
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ProgressSnapshot {
+    // Files the walk has found so far.
+    pub files_discovered: usize,
+
+    // Files done with, whether they made it into the index, were skipped or
+    // couldn't be read.
+    pub files_indexed: usize,
+
+    // Bytes read from indexed files.
+    pub bytes_processed: u64,
+
+    // Files that couldn't be read.
+    pub errors: usize,
+
+    // Once the walk is over, files_discovered is the total.
+    pub walk_finished: bool,
+}
+
+impl ProgressSnapshot {
+    // Files and bytes per second between an earlier snapshot and this one.
+    pub fn throughput_since(&self, earlier: &ProgressSnapshot, elapsed: Duration) -> (f64, f64) {
+        let seconds = elapsed.as_secs_f64();
+        if seconds <= 0f64 {
+            return (0f64, 0f64);
+        }
+
+        let files = self.files_indexed.saturating_sub(earlier.files_indexed) as f64;
+        let bytes = self.bytes_processed.saturating_sub(earlier.bytes_processed) as f64;
+
+        (files / seconds, bytes / seconds)
+    }
+
+    // How much longer indexing should take at the average rate so far. Unknown
+    // until the walk is over, since until then there's no telling how many files
+    // are left.
+    pub fn estimated_time_left(&self, elapsed: Duration) -> Option<Duration> {
+        if !self.walk_finished || self.files_indexed == 0 {
+            return None;
+        }
+
+        let remaining = self.files_discovered.saturating_sub(self.files_indexed);
+        let seconds_per_file = elapsed.as_secs_f64() / self.files_indexed as f64;
+
+        Some(Duration::from_secs_f64(seconds_per_file * remaining as f64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{IndexingProgress, ProgressSnapshot};
+
+    #[test]
+    fn progress_counts() {
+        let progress = IndexingProgress::default();
+
+        progress.file_discovered();
+        progress.file_discovered();
+        progress.file_indexed();
+        progress.bytes_read(100);
+        progress.bytes_read(28);
+        progress.error();
+
+        assert_eq!(ProgressSnapshot {
+            files_discovered: 2,
+            files_indexed: 1,
+            bytes_processed: 128,
+            errors: 1,
+            walk_finished: false,
+        }, progress.snapshot());
+
+        progress.walk_finished();
+        assert!(progress.snapshot().walk_finished);
+    }
+
+    #[test]
+    fn progress_throughput_and_eta() {
+        let earlier = ProgressSnapshot { files_discovered: 100, files_indexed: 10, bytes_processed: 1000, ..Default::default() };
+        let later = ProgressSnapshot { files_discovered: 200, files_indexed: 50, bytes_processed: 5000, ..Default::default() };
+
+        assert_eq!((20f64, 2000f64), later.throughput_since(&earlier, Duration::from_secs(2)));
+        assert_eq!((0f64, 0f64), later.throughput_since(&earlier, Duration::ZERO));
+
+        // No ETA while the total is still unknown.
+        assert_eq!(None, later.estimated_time_left(Duration::from_secs(10)));
+
+        let finished = ProgressSnapshot { walk_finished: true, ..later };
+        assert_eq!(Some(Duration::from_secs(30)), finished.estimated_time_left(Duration::from_secs(10)));
+        assert_eq!(None, ProgressSnapshot { walk_finished: true, ..Default::default() }.estimated_time_left(Duration::from_secs(1)));
+    }
+}
@@ -0,0 +1,103 @@
+use crate::language::LanguageSet;
+use crate::regions::Region;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QueryMode {
+    // Files containing any of the terms match.
+    Any,
+
+    // Only files containing every term match.
+    All,
+}
+
+// What to search for. A plain search is a single term, matched literally
+// (case-insensitive), spaces and all.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Query {
+    pub terms: Vec<String>,
+    pub mode: QueryMode,
+
+    // Only files in these languages match, if set.
+    pub languages: Option<LanguageSet>,
+
+    // Only matches in this kind of region count, if set, e.g. only in comments.
+    pub region: Option<Region>,
+}
+
+impl Query {
+    pub fn literal(text: &str) -> Query {
+        Query {
+            terms: vec![text.to_string()],
+            mode: QueryMode::All,
+            languages: None,
+            region: None,
+        }
+    }
+
+    // Splits the text into whitespace separated terms.
+    pub fn any_of(text: &str) -> Query {
+        Query {
+            terms: split_terms(text),
+            mode: QueryMode::Any,
+            languages: None,
+            region: None,
+        }
+    }
+
+    pub fn all_of(text: &str) -> Query {
+        Query {
+            terms: split_terms(text),
+            mode: QueryMode::All,
+            languages: None,
+            region: None,
+        }
+    }
+
+    pub fn in_languages(mut self, languages: LanguageSet) -> Query {
+        self.languages = Some(languages);
+        self
+    }
+
+    pub fn in_region(mut self, region: Region) -> Query {
+        self.region = Some(region);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.iter().all(|term| term.is_empty())
+    }
+}
+
+fn split_terms(text: &str) -> Vec<String> {
+    let mut terms: Vec<String> = Vec::new();
+
+    for term in text.split_whitespace() {
+        if !terms.iter().any(|existing| existing == term) {
+            terms.push(term.to_string());
+        }
+    }
+
+    terms
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Query, QueryMode};
+
+    #[test]
+    fn query_literal_keeps_spaces() {
+        let query = Query::literal("fn main");
+
+        assert_eq!(vec!["fn main"], query.terms);
+        assert_eq!(QueryMode::All, query.mode);
+    }
+
+    #[test]
+    fn query_split_terms() {
+        let query = Query::any_of("  foo bar\tfoo ");
+
+        assert_eq!(vec!["foo", "bar"], query.terms);
+        assert_eq!(QueryMode::Any, query.mode);
+        assert!(Query::all_of("  ").is_empty());
+    }
+}
@@ -0,0 +1,245 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+
+use crate::regions::Region;
+use crate::text_scraping::Match;
+
+// Path components that suggest a file is less interesting than regular source.
+const PENALIZED_DIRECTORIES: &[&str] = &[
+    "test", "tests", "__tests__", "spec", "specs", "testdata",
+    "vendor", "third_party", "thirdparty", "external", "node_modules",
+    "generated", "gen", "obj", "bin", "dist", "build", "target", "out",
+];
+
+// File name fragments that suggest test or generated code.
+const PENALIZED_FILE_NAME_PARTS: &[&str] = &[
+    "_test.", ".test.", ".spec.", "tests.",
+    ".generated.", ".g.", ".designer.", ".pb.", ".min.",
+];
+
+const PATH_PENALTY: f32 = 0.5;
+
+// Files changed within about this long get a boost that fades as they age.
+const RECENCY_HALF_LIFE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+const MAX_RECENCY_BOOST: f32 = 0.5;
+
+// Gap, in bytes, at which hits are considered half as close as adjacent ones.
+const PROXIMITY_SCALE: f32 = 200f32;
+const MAX_PROXIMITY_BOOST: f32 = 0.5;
+
+const IDENTIFIER_WEIGHT: f32 = 1.5;
+const CODE_WEIGHT: f32 = 1.0;
+const COMMENT_WEIGHT: f32 = 0.5;
+
+pub struct RankedFile {
+    pub file_path: String,
+    pub score: f32,
+    pub matches: Vec<Match>,
+}
+
+// Groups matches by file and orders the files by how relevant they look, best first.
+// Ties are broken by path so results are stable.
+pub fn rank_matches(matches: Vec<Match>) -> Vec<RankedFile> {
+    let mut files: BTreeMap<String, Vec<Match>> = BTreeMap::new();
+
+    for scraped_match in matches {
+        files.entry(scraped_match.file_path.clone()).or_default().push(scraped_match);
+    }
+
+    let mut ranked: Vec<RankedFile> = files
+        .into_iter()
+        .map(|(file_path, matches)| RankedFile {
+            score: score_file(&file_path, &matches),
+            file_path,
+            matches,
+        })
+        .collect();
+
+    ranked.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.file_path.cmp(&b.file_path)));
+
+    ranked
+}
+
+// Higher is better. Combines how many hits there are and where they are, with
+// multipliers for the file's path, how recently it changed and how close together
+// the hits are.
+pub fn score_file(file_path: &str, matches: &[Match]) -> f32 {
+    if matches.is_empty() {
+        return 0f32;
+    }
+
+    // Diminishing returns, so a file with hundreds of hits doesn't drown out everything.
+    let weighted_hits: f32 = matches.iter().map(match_weight).sum();
+    let hits_score = (1f32 + weighted_hits).ln();
+
+    hits_score *
+        path_multiplier(file_path) *
+        recency_multiplier(file_path, SystemTime::now()) *
+        proximity_multiplier(matches)
+}
+
+// Hits on a whole identifier count for more than hits inside a longer word, and
+// hits inside comments count for less.
+fn match_weight(scraped_match: &Match) -> f32 {
+    if scraped_match.region == Region::Comment {
+        return COMMENT_WEIGHT;
+    }
+
+    let text = scraped_match.text.as_bytes();
+    let start = scraped_match.context_offset.min(text.len());
+    let end = (start + scraped_match.context_length).min(text.len());
+
+    let is_identifier_char = |c: &u8| c.is_ascii_alphanumeric() || *c == b'_';
+    let starts_word = start == 0 || !is_identifier_char(&text[start - 1]);
+    let ends_word = end == text.len() || !is_identifier_char(&text[end]);
+
+    if starts_word && ends_word {
+        IDENTIFIER_WEIGHT
+    } else {
+        CODE_WEIGHT
+    }
+}
+
+fn path_multiplier(file_path: &str) -> f32 {
+    let lowered_path = file_path.replace('\\', "/").to_lowercase();
+    let mut components: Vec<&str> = lowered_path.split('/').collect();
+    let file_name = components.pop().unwrap_or_default();
+
+    let mut multiplier = 1f32;
+
+    if components.iter().any(|component| PENALIZED_DIRECTORIES.contains(component)) {
+        multiplier *= PATH_PENALTY;
+    }
+
+    if PENALIZED_FILE_NAME_PARTS.iter().any(|part| file_name.contains(part)) {
+        multiplier *= PATH_PENALTY;
+    }
+
+    multiplier
+}
+
+fn recency_multiplier(file_path: &str, now: SystemTime) -> f32 {
+    let Some(modified) = std::fs::metadata(file_path).and_then(|metadata| metadata.modified()).ok() else {
+        return 1f32;
+    };
+
+    let age = now.duration_since(modified).unwrap_or_default();
+    let half_lives = age.as_secs_f32() / RECENCY_HALF_LIFE.as_secs_f32();
+
+    1f32 + MAX_RECENCY_BOOST * 0.5f32.powf(half_lives)
+}
+
+// Rewards files where hits are close together. For multi-term queries that means
+// different terms showing up near each other, e.g. in the same function rather
+// than at opposite ends of the file.
+fn proximity_multiplier(matches: &[Match]) -> f32 {
+    let mut hits: Vec<(usize, usize)> = matches
+        .iter()
+        .map(|scraped_match| (scraped_match.offset, scraped_match.pattern))
+        .collect();
+    hits.sort();
+    hits.dedup();
+
+    let multiple_terms = hits.iter().any(|hit| hit.1 != hits[0].1);
+
+    // With hits sorted by offset, the closest pair of different terms is always
+    // adjacent to each other.
+    let Some(smallest_gap) = hits
+        .windows(2)
+        .filter(|pair| !multiple_terms || pair[0].1 != pair[1].1)
+        .map(|pair| pair[1].0 - pair[0].0)
+        .min() else {
+        return 1f32;
+    };
+
+    1f32 + MAX_PROXIMITY_BOOST * PROXIMITY_SCALE / (PROXIMITY_SCALE + smallest_gap as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::regions::Region;
+    use crate::text_scraping::Match;
+
+    use super::{path_multiplier, rank_matches, score_file};
+
+    fn test_match(file_path: &str, offset: usize, text: &str, context_offset: usize, length: usize) -> Match {
+        Match {
+            file_path: file_path.to_string(),
+            offset,
+            length,
+            text: text.to_string(),
+            context_offset,
+            context_length: length,
+            pattern: 0,
+            region: Region::Code,
+            language: None,
+            context_line: 1,
+            context_regions: Vec::new(),
+            in_path: false,
+        }
+    }
+
+    #[test]
+    fn ranking_more_matches_higher() {
+        let one = vec![test_match("a.rs", 0, "let foo = 1;", 4, 3)];
+        let two = vec![test_match("a.rs", 0, "let foo = 1;", 4, 3), test_match("a.rs", 5000, "let foo = 1;", 4, 3)];
+
+        assert!(score_file("a.rs", &two) > score_file("a.rs", &one));
+    }
+
+    #[test]
+    fn ranking_identifiers_over_comments() {
+        let identifier = vec![test_match("a.rs", 0, "let foo = 1;", 4, 3)];
+        let partial = vec![test_match("a.rs", 0, "let foobar = 1;", 4, 3)];
+        let comment = vec![Match { region: Region::Comment, ..test_match("a.rs", 0, "// foo is great", 3, 3) }];
+
+        assert!(score_file("a.rs", &identifier) > score_file("a.rs", &partial));
+        assert!(score_file("a.rs", &partial) > score_file("a.rs", &comment));
+    }
+
+    #[test]
+    fn ranking_path_penalties() {
+        assert_eq!(1f32, path_multiplier("src/index.rs"));
+        assert_eq!(0.5f32, path_multiplier("tests/index.rs"));
+        assert_eq!(0.5f32, path_multiplier("vendor/lib/index.rs"));
+        assert_eq!(0.5f32, path_multiplier("src/index_test.go"));
+        assert_eq!(0.25f32, path_multiplier("src/generated/parser.g.cs"));
+
+        // Only whole folder names count, and monorepo packages are regular code.
+        assert_eq!(1f32, path_multiplier("src/latest/index.rs"));
+        assert_eq!(1f32, path_multiplier("packages/app/src/index.ts"));
+    }
+
+    #[test]
+    fn ranking_proximity() {
+        let close = vec![test_match("a.rs", 0, "foo", 0, 3), test_match("a.rs", 10, "foo", 0, 3)];
+        let far = vec![test_match("a.rs", 0, "foo", 0, 3), test_match("a.rs", 10000, "foo", 0, 3)];
+
+        assert!(score_file("a.rs", &close) > score_file("a.rs", &far));
+    }
+
+    #[test]
+    fn ranking_proximity_of_different_terms() {
+        let term = |offset: usize, pattern: usize| Match { pattern, ..test_match("a.rs", offset, "foo", 0, 3) };
+
+        // Same hits, but only in the first file are the two terms next to each other.
+        let together = vec![term(0, 0), term(10, 1), term(5000, 0), term(5010, 0)];
+        let apart = vec![term(0, 0), term(10, 0), term(5000, 1), term(5010, 1)];
+
+        assert!(score_file("a.rs", &together) > score_file("a.rs", &apart));
+    }
+
+    #[test]
+    fn ranking_orders_files() {
+        let ranked = rank_matches(vec![
+            test_match("tests/a.rs", 0, "foo", 0, 3),
+            test_match("src/b.rs", 0, "foo", 0, 3),
+            test_match("src/c.rs", 0, "foo", 0, 3),
+            test_match("src/c.rs", 100, "foo", 0, 3),
+        ]);
+
+        let order: Vec<&str> = ranked.iter().map(|file| file.file_path.as_str()).collect();
+        assert_eq!(vec!["src/c.rs", "src/b.rs", "tests/a.rs"], order);
+        assert_eq!(2, ranked[0].matches.len());
+    }
+}
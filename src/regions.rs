@@ -0,0 +1,346 @@
+use crate::language::Language;
+
+// What a stretch of a source file is, lexically.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Region {
+    Code,
+    Comment,
+
+    // A string or character literal, quotes included.
+    String,
+}
+
+impl Region {
+    pub fn from_name(name: &str) -> Option<Region> {
+        match name.to_lowercase().as_str() {
+            "code" => Some(Region::Code),
+            "comment" | "comments" => Some(Region::Comment),
+            "string" | "strings" => Some(Region::String),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Region::Code => "code",
+            Region::Comment => "comment",
+            Region::String => "string",
+        }
+    }
+}
+
+// A comment or string literal, in bytes. Everything between spans is code.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RegionSpan {
+    pub start: usize,
+    pub end: usize,
+    pub region: Region,
+}
+
+// How comments and string literals are written in a language.
+struct LexicalSyntax {
+    line_comments: &'static [&'static str],
+    block_comments: &'static [(&'static str, &'static str)],
+
+    // Block comments can contain other block comments, as in Rust.
+    nested_comments: bool,
+
+    // Quotes that open a string and close it again, longest first, and whether the
+    // string can run over several lines. Single line strings left open end with
+    // their line, so that a stray quote doesn't swallow the rest of the file.
+    strings: &'static [(&'static str, bool)],
+
+    // Line comments only start at the start of a word, e.g. so that '$#' in a shell
+    // script isn't one.
+    comments_after_space: bool,
+
+    // Rust: a single quote is a character literal only when it looks like one,
+    // since lifetimes start with one too. r"..." and r#"..."# are raw strings.
+    rust_quotes: bool,
+}
+
+const C_LIKE: LexicalSyntax = LexicalSyntax {
+    line_comments: &["//"],
+    block_comments: &[("/*", "*/")],
+    nested_comments: false,
+    strings: &[("\"", false), ("'", false)],
+    comments_after_space: false,
+    rust_quotes: false,
+};
+
+const HASH_COMMENTS: LexicalSyntax = LexicalSyntax {
+    line_comments: &["#"],
+    block_comments: &[],
+    nested_comments: false,
+    strings: &[("\"", false), ("'", false)],
+    comments_after_space: false,
+    rust_quotes: false,
+};
+
+const MARKUP: LexicalSyntax = LexicalSyntax {
+    line_comments: &[],
+    block_comments: &[("<!--", "-->")],
+    nested_comments: false,
+    strings: &[],
+    comments_after_space: false,
+    rust_quotes: false,
+};
+
+fn lexical_syntax(language: Language) -> LexicalSyntax {
+    match language {
+        Language::Rust => LexicalSyntax { nested_comments: true, strings: &[("\"", true)], rust_quotes: true, ..C_LIKE },
+        Language::C | Language::Cpp | Language::CSharp | Language::Protobuf | Language::Css => C_LIKE,
+        Language::Java | Language::Kotlin | Language::Scala | Language::Swift => LexicalSyntax {
+            strings: &[("\"\"\"", true), ("\"", false), ("'", false)],
+            ..C_LIKE
+        },
+        Language::Go | Language::TypeScript | Language::JavaScript => LexicalSyntax {
+            strings: &[("`", true), ("\"", false), ("'", false)],
+            ..C_LIKE
+        },
+        Language::Php => LexicalSyntax { line_comments: &["//", "#"], ..C_LIKE },
+        Language::Sql => LexicalSyntax { line_comments: &["--"], ..C_LIKE },
+        Language::Lua => LexicalSyntax {
+            line_comments: &["--"],
+            block_comments: &[("--[[", "]]")],
+            strings: &[("[[", true), ("\"", false), ("'", false)],
+            ..C_LIKE
+        },
+        Language::Python | Language::Toml => LexicalSyntax {
+            strings: &[("\"\"\"", true), ("'''", true), ("\"", false), ("'", false)],
+            ..HASH_COMMENTS
+        },
+        Language::Shell | Language::Makefile | Language::Dockerfile => LexicalSyntax { comments_after_space: true, ..HASH_COMMENTS },
+        Language::PowerShell => LexicalSyntax { block_comments: &[("<#", "#>")], ..HASH_COMMENTS },
+        Language::Ruby | Language::Perl | Language::Yaml | Language::CMake => HASH_COMMENTS,
+        Language::Json => LexicalSyntax { line_comments: &[], block_comments: &[], strings: &[("\"", false)], ..C_LIKE },
+        Language::Html | Language::Xml | Language::Markdown => MARKUP,
+    }
+}
+
+// The comments and string literals in 'text', in order. A lexer rather than a parser:
+// it knows how each language writes comments and strings, and nothing else, so
+// quotes inside things it doesn't know about, e.g. shell heredocs, can confuse it.
+// Files in no language we know of are all code. Only ASCII delimiters are looked
+// for, so UTF-8 can be scanned a byte at a time.
+pub fn classify_regions(language: Option<Language>, text: &[u8]) -> Vec<RegionSpan> {
+    let Some(language) = language else {
+        return Vec::new();
+    };
+
+    let syntax = lexical_syntax(language);
+
+    let mut spans = Vec::new();
+    let mut i = 0;
+
+    while i < text.len() {
+        let rest = &text[i..];
+        let word_start = !syntax.comments_after_space || i == 0 || text[i - 1].is_ascii_whitespace();
+
+        // Block comments first, since Lua's start like its line comments.
+        if let Some((open, close)) = syntax.block_comments.iter().find(|(open, _)| rest.starts_with(open.as_bytes())) {
+            let end = block_comment_end(text, i + open.len(), open.as_bytes(), close.as_bytes(), syntax.nested_comments);
+            spans.push(RegionSpan { start: i, end, region: Region::Comment });
+            i = end;
+        } else if word_start && syntax.line_comments.iter().any(|open| rest.starts_with(open.as_bytes())) {
+            let end = line_end(text, i);
+            spans.push(RegionSpan { start: i, end, region: Region::Comment });
+            i = end;
+        } else if let Some(end) = syntax.rust_quotes.then(|| rust_raw_string_end(text, i)).flatten() {
+            spans.push(RegionSpan { start: i, end, region: Region::String });
+            i = end;
+        } else if let Some((quote, multiline)) = syntax.strings.iter().find(|(quote, _)| rest.starts_with(quote.as_bytes())) {
+            let end = string_end(text, i + quote.len(), quote.as_bytes(), *multiline);
+            spans.push(RegionSpan { start: i, end, region: Region::String });
+            i = end;
+        } else if syntax.rust_quotes && text[i] == b'\'' && is_rust_char_literal(text, i) {
+            let end = string_end(text, i + 1, b"'", false);
+            spans.push(RegionSpan { start: i, end, region: Region::String });
+            i = end;
+        } else {
+            i += 1;
+        }
+    }
+
+    spans
+}
+
+// The region 'offset' is in, given the spans classify_regions() found.
+pub fn region_at(spans: &[RegionSpan], offset: usize) -> Region {
+    let next = spans.partition_point(|span| span.end <= offset);
+
+    match spans.get(next) {
+        Some(span) if span.start <= offset => span.region,
+        _ => Region::Code,
+    }
+}
+
+fn line_end(text: &[u8], from: usize) -> usize {
+    text[from..].iter().position(|byte| *byte == b'\n').map_or(text.len(), |position| from + position)
+}
+
+// Just past the comment's close, or the end of the text if it isn't closed.
+fn block_comment_end(text: &[u8], from: usize, open: &[u8], close: &[u8], nested: bool) -> usize {
+    let mut depth = 1;
+    let mut i = from;
+
+    while i < text.len() {
+        if text[i..].starts_with(close) {
+            depth -= 1;
+            i += close.len();
+
+            if depth == 0 {
+                return i;
+            }
+        } else if nested && text[i..].starts_with(open) {
+            depth += 1;
+            i += open.len();
+        } else {
+            i += 1;
+        }
+    }
+
+    text.len()
+}
+
+// Just past the closing quote. Backslashes escape whatever follows them.
+fn string_end(text: &[u8], from: usize, quote: &[u8], multiline: bool) -> usize {
+    let mut i = from;
+
+    while i < text.len() {
+        if text[i] == b'\\' {
+            i += 2;
+        } else if text[i..].starts_with(quote) {
+            return i + quote.len();
+        } else if text[i] == b'\n' && !multiline {
+            return i;
+        } else {
+            i += 1;
+        }
+    }
+
+    text.len()
+}
+
+// E.g. 'a', '\n' or 'é', but not the 'a in <'a>.
+fn is_rust_char_literal(text: &[u8], start: usize) -> bool {
+    match text.get(start + 1) {
+        Some(b'\\') => true,
+        Some(first) => text.get(start + 1 + utf8_length(*first)) == Some(&b'\''),
+        None => false,
+    }
+}
+
+// r"...", br"..." and r#"..."#, which have no escapes. None if there isn't one at
+// 'start'.
+fn rust_raw_string_end(text: &[u8], start: usize) -> Option<usize> {
+    if start > 0 && (text[start - 1].is_ascii_alphanumeric() || text[start - 1] == b'_') {
+        return None;
+    }
+
+    let after_prefix = start + text[start..].iter().take(2).position(|byte| *byte == b'r')? + 1;
+    if after_prefix - start == 2 && text[start] != b'b' {
+        return None;
+    }
+
+    let hashes = text[after_prefix..].iter().take_while(|byte| **byte == b'#').count();
+    if text.get(after_prefix + hashes) != Some(&b'"') {
+        return None;
+    }
+
+    let mut close = vec![b'"'];
+    close.extend(std::iter::repeat_n(b'#', hashes));
+
+    let from = after_prefix + hashes + 1;
+    let end = text[from..]
+        .windows(close.len())
+        .position(|window| window == close.as_slice())
+        .map_or(text.len(), |position| from + position + close.len());
+
+    Some(end)
+}
+
+fn utf8_length(first_byte: u8) -> usize {
+    match first_byte {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::language::Language;
+
+    use super::{classify_regions, region_at, Region};
+
+    // Each span's text, with what it is.
+    fn regions(language: Language, text: &str) -> Vec<(Region, &str)> {
+        classify_regions(Some(language), text.as_bytes())
+            .into_iter()
+            .map(|span| (span.region, &text[span.start..span.end]))
+            .collect()
+    }
+
+    #[test]
+    fn regions_c_like() {
+        let text = "int x = 1; // TODO: two\nchar *s = \"a \\\"quoted\\\" // not a comment\";\n/* block\n   comment */ char c = '\\'';";
+
+        assert_eq!(vec![
+            (Region::Comment, "// TODO: two"),
+            (Region::String, "\"a \\\"quoted\\\" // not a comment\""),
+            (Region::Comment, "/* block\n   comment */"),
+            (Region::String, "'\\''"),
+        ], regions(Language::C, text));
+
+        // A string left open ends with its line.
+        assert_eq!(vec![(Region::String, "\"open"), (Region::Comment, "// closed")], regions(Language::Java, "\"open\n// closed"));
+    }
+
+    #[test]
+    fn regions_rust() {
+        let text = "fn f<'a>(s: &'a str) -> char { /* outer /* inner */ still */ 'x' }\nlet p = r#\"C:\\ \"quoted\"\"#; let q = b'\\n';";
+
+        assert_eq!(vec![
+            (Region::Comment, "/* outer /* inner */ still */"),
+            (Region::String, "'x'"),
+            (Region::String, "r#\"C:\\ \"quoted\"\"#"),
+            (Region::String, "'\\n'"),
+        ], regions(Language::Rust, text));
+
+        // Strings span lines, and 'for' isn't a raw string.
+        assert_eq!(vec![(Region::String, "\"one\ntwo\"")], regions(Language::Rust, "for x in \"one\ntwo\" {}"));
+    }
+
+    #[test]
+    fn regions_scripts() {
+        let text = "# comment\nx = \"\"\"doc\nstring\"\"\" # 'quoted'\n";
+        assert_eq!(vec![
+            (Region::Comment, "# comment"),
+            (Region::String, "\"\"\"doc\nstring\"\"\""),
+            (Region::Comment, "# 'quoted'"),
+        ], regions(Language::Python, text));
+
+        assert_eq!(vec![(Region::String, "'$#'"), (Region::Comment, "# count")], regions(Language::Shell, "echo $# '$#' # count"));
+        assert_eq!(vec![(Region::String, "`multi\nline`")], regions(Language::TypeScript, "const s = `multi\nline`;"));
+        assert_eq!(vec![(Region::Comment, "--[[ block ]]"), (Region::Comment, "-- line")], regions(Language::Lua, "--[[ block ]] x = 1 -- line"));
+        assert_eq!(vec![(Region::Comment, "<!-- note -->")], regions(Language::Html, "<p class=\"it's\"><!-- note --></p>"));
+    }
+
+    #[test]
+    fn regions_lookup() {
+        let text = "let s = \"TODO\"; // TODO\nTODO();";
+        let spans = classify_regions(Some(Language::Rust), text.as_bytes());
+
+        let found: Vec<Region> = text.match_indices("TODO").map(|(offset, _)| region_at(&spans, offset)).collect();
+        assert_eq!(vec![Region::String, Region::Comment, Region::Code], found);
+
+        // Without a language everything is code.
+        assert!(classify_regions(None, text.as_bytes()).is_empty());
+        assert_eq!(Region::Code, region_at(&[], 3));
+
+        assert_eq!(Some(Region::Comment), Region::from_name("comments"));
+        assert_eq!(None, Region::from_name("docs"));
+    }
+}
@@ -0,0 +1,855 @@
+use serde::{Deserialize, Serialize};
+
+use crate::language::Language;
+
+// Lines longer than this, e.g. minified code, are skipped, so that the line being
+// looked at never takes much memory.
+const MAX_LINE_LENGTH: usize = 1000;
+
+// Words that can come before a definition without changing what it defines.
+const RUST_MODIFIERS: &[&str] = &["pub(crate)", "pub(super)", "pub(self)", "pub", "default", "const", "async", "unsafe", "extern", "\"\""];
+const C_LIKE_MODIFIERS: &[&str] = &[
+    "public", "private", "protected", "internal", "static", "abstract", "sealed", "final", "partial", "readonly",
+    "virtual", "override", "extern", "inline", "unsafe", "async", "synchronized", "native", "explicit", "constexpr",
+    "typedef",
+];
+const SCRIPT_MODIFIERS: &[&str] = &[
+    "export", "default", "declare", "async", "abstract", "public", "private", "protected", "static", "readonly",
+    "override", "get", "set",
+];
+
+const C_LIKE_TYPE_KEYWORDS: &[&str] = &["class", "struct", "interface", "enum", "record", "union"];
+const SCRIPT_TYPE_KEYWORDS: &[&str] = &["class", "interface", "enum", "type"];
+
+// Words that can follow a type's name in its definition, e.g. 'class Foo extends Bar'.
+const TYPE_NAME_FOLLOWERS: &[&str] = &["extends", "implements", "where", "final", "sealed", "permits"];
+
+// Words followed by parentheses that aren't functions being defined.
+const NOT_FUNCTIONS: &[&str] = &[
+    "if", "for", "foreach", "while", "switch", "return", "new", "else", "catch", "throw", "do", "case", "using",
+    "lock", "sizeof", "typeof", "await", "yield", "delete", "goto", "fixed", "checked", "unchecked", "super", "this",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SymbolKind {
+    Function,
+
+    // A function defined in the body of a type, e.g. a class or an impl block.
+    Method,
+
+    // Classes, structs, interfaces, traits, enums and the like.
+    Type,
+}
+
+impl SymbolKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SymbolKind::Function => "function",
+            SymbolKind::Method => "method",
+            SymbolKind::Type => "type",
+        }
+    }
+}
+
+// A definition in a file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+
+    // Starting from 1.
+    pub line: u32,
+}
+
+// How well a symbol's name matches what was looked up, best first.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum MatchRank {
+    Exact,
+    ExactIgnoringCase,
+    Prefix,
+
+    // The characters looked up appear in the name in order, ignoring case, with this
+    // many others in between.
+    Fuzzy(usize),
+}
+
+// A definition found by IndexTree::find_symbols().
+#[derive(Clone, Debug, PartialEq)]
+pub struct SymbolMatch {
+    // As stored in the index.
+    pub file_path: String,
+    pub symbol: Symbol,
+    pub rank: MatchRank,
+}
+
+// Finds definitions of functions, methods and types in a file as it's fed in a
+// character at a time. This is a line by line heuristic rather than a parser:
+// definitions are recognized by their keywords and shape, and what they're nested
+// in by braces, or indentation in Python, which is enough to tell methods from
+// functions. Unusual formatting can throw it off.
+pub struct SymbolExtractor {
+    language: Language,
+    line: String,
+    line_number: u32,
+    line_too_long: bool,
+    in_block_comment: bool,
+
+    // Whether each enclosing block is the body of a type, innermost last.
+    blocks: Vec<bool>,
+
+    // A type was defined without opening its body yet, e.g. with the brace on the
+    // next line.
+    pending_type_body: bool,
+
+    // Python: the indentation of each enclosing definition and whether it's a class.
+    python_scopes: Vec<(usize, bool)>,
+
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolExtractor {
+    pub fn new(language: Language) -> SymbolExtractor {
+        SymbolExtractor {
+            language,
+            line: String::new(),
+            line_number: 0,
+            line_too_long: false,
+            in_block_comment: false,
+            blocks: Vec::new(),
+            pending_type_body: false,
+            python_scopes: Vec::new(),
+            symbols: Vec::new(),
+        }
+    }
+
+    // Whether definitions are extracted from files in 'language' at all.
+    pub fn supports(language: Language) -> bool {
+        matches!(language,
+            Language::Rust | Language::C | Language::Cpp | Language::CSharp | Language::Java |
+            Language::TypeScript | Language::JavaScript | Language::Python | Language::Go)
+    }
+
+    pub fn push_char(&mut self, c: char) {
+        if c == '\n' {
+            self.finish_line();
+        } else if self.line.len() < MAX_LINE_LENGTH {
+            self.line.push(c);
+        } else {
+            self.line_too_long = true;
+        }
+    }
+
+    pub fn finish(mut self) -> Vec<Symbol> {
+        if !self.line.is_empty() {
+            self.finish_line();
+        }
+
+        self.symbols
+    }
+
+    fn finish_line(&mut self) {
+        self.line_number += 1;
+
+        let line = std::mem::take(&mut self.line);
+        if !self.line_too_long {
+            self.process_line(&line);
+        }
+
+        // Reuse the buffer.
+        self.line = line;
+        self.line.clear();
+        self.line_too_long = false;
+    }
+
+    fn process_line(&mut self, line: &str) {
+        let code = self.code_only(line);
+        let trimmed = code.trim();
+
+        if trimmed.is_empty() {
+            return;
+        }
+
+        if self.language == Language::Python {
+            self.python_line(&code);
+            return;
+        }
+
+        let in_type = self.blocks.last() == Some(&true);
+
+        let definition = match self.language {
+            Language::Rust => rust_definition(trimmed, in_type),
+            Language::Go => go_definition(trimmed),
+            Language::TypeScript | Language::JavaScript => script_definition(trimmed, in_type),
+            Language::C | Language::Cpp | Language::CSharp | Language::Java => c_like_definition(trimmed, in_type),
+            _ => None,
+        };
+
+        let opens_type = match self.language {
+            Language::Rust => rust_opens_type(trimmed),
+            Language::Go => false,
+            _ => matches!(definition, Some((_, SymbolKind::Type))),
+        };
+
+        if let Some((name, kind)) = definition {
+            self.symbols.push(Symbol { name: name.to_string(), kind, line: self.line_number });
+        }
+
+        let mut pending_type_body = self.pending_type_body || opens_type;
+
+        for c in trimmed.chars() {
+            match c {
+                '{' => {
+                    self.blocks.push(pending_type_body);
+                    pending_type_body = false;
+                },
+                '}' => {
+                    self.blocks.pop();
+                },
+                _ => {},
+            }
+        }
+
+        // Without a body, it was only a declaration.
+        self.pending_type_body = pending_type_body && !trimmed.ends_with(';');
+    }
+
+    // Python nests by indentation, so a definition is in whichever definitions above
+    // it are indented less.
+    fn python_line(&mut self, code: &str) {
+        let indent = code.len() - code.trim_start().len();
+        let trimmed = strip_modifiers(code.trim(), &["async"]);
+
+        self.python_scopes.retain(|(scope_indent, _)| *scope_indent < indent);
+
+        let (rest, is_class) = match (strip_word(trimmed, "def"), strip_word(trimmed, "class")) {
+            (Some(rest), _) => (rest, false),
+            (_, Some(rest)) => (rest, true),
+            _ => return,
+        };
+
+        let in_class = self.python_scopes.last().is_some_and(|(_, is_class)| *is_class);
+
+        if let Some(name) = leading_identifier(rest) {
+            let kind = match (is_class, in_class) {
+                (true, _) => SymbolKind::Type,
+                (false, true) => SymbolKind::Method,
+                (false, false) => SymbolKind::Function,
+            };
+
+            self.symbols.push(Symbol { name: name.to_string(), kind, line: self.line_number });
+        }
+
+        self.python_scopes.push((indent, is_class));
+    }
+
+    // The line without comments, and with what's inside string and character literals
+    // left out, so that neither can be mistaken for code.
+    fn code_only(&mut self, line: &str) -> String {
+        let chars: Vec<char> = line.chars().collect();
+        let mut code = String::with_capacity(line.len());
+        let mut quote = None;
+        let mut i = 0;
+
+        while i < chars.len() {
+            let c = chars[i];
+            let next = chars.get(i + 1).copied();
+
+            if self.in_block_comment {
+                if c == '*' && next == Some('/') {
+                    self.in_block_comment = false;
+                    i += 1;
+                }
+            } else if let Some(quote_char) = quote {
+                if c == '\\' {
+                    i += 1;
+                } else if c == quote_char {
+                    code.push(c);
+                    quote = None;
+                }
+            } else {
+                match c {
+                    '#' if self.language == Language::Python => break,
+                    '/' if next == Some('/') && self.language != Language::Python => break,
+                    '/' if next == Some('*') && self.language != Language::Python => {
+                        self.in_block_comment = true;
+                        i += 1;
+                    },
+                    '"' | '`' => {
+                        code.push(c);
+                        quote = Some(c);
+                    },
+                    // Rust's lifetimes also start with a quote, so only take it for a
+                    // character literal when it looks like one.
+                    '\'' if self.language != Language::Rust || next == Some('\\') || chars.get(i + 2) == Some(&'\'') => {
+                        code.push(c);
+                        quote = Some(c);
+                    },
+                    _ => code.push(c),
+                }
+            }
+
+            i += 1;
+        }
+
+        code
+    }
+}
+
+// Every definition in 'text'.
+pub fn extract_symbols(language: Language, text: &str) -> Vec<Symbol> {
+    let mut extractor = SymbolExtractor::new(language);
+    text.chars().for_each(|c| extractor.push_char(c));
+    extractor.finish()
+}
+
+// None if 'name' doesn't match 'query' at all.
+pub fn match_rank(query: &str, name: &str) -> Option<MatchRank> {
+    if query.is_empty() {
+        return None;
+    }
+
+    if name == query {
+        return Some(MatchRank::Exact);
+    }
+
+    let query = query.to_lowercase();
+    let name = name.to_lowercase();
+
+    if name == query {
+        return Some(MatchRank::ExactIgnoringCase);
+    }
+
+    if name.starts_with(&query) {
+        return Some(MatchRank::Prefix);
+    }
+
+    // Where each of the query's characters is found, taking the earliest each time.
+    let mut first = None;
+    let mut last = 0;
+    let mut name_chars = name.chars().enumerate();
+
+    for query_char in query.chars() {
+        let (position, _) = name_chars.find(|(_, name_char)| *name_char == query_char)?;
+        first.get_or_insert(position);
+        last = position;
+    }
+
+    Some(MatchRank::Fuzzy(last + 1 - first? - query.chars().count()))
+}
+
+fn rust_definition(line: &str, in_type: bool) -> Option<(&str, SymbolKind)> {
+    let line = strip_modifiers(line, RUST_MODIFIERS);
+
+    if let Some(rest) = strip_word(line, "fn") {
+        let kind = if in_type { SymbolKind::Method } else { SymbolKind::Function };
+        return leading_identifier(rest).map(|name| (name, kind));
+    }
+
+    ["struct", "enum", "union", "trait", "type"]
+        .iter()
+        .find_map(|keyword| strip_word(line, keyword))
+        .and_then(leading_identifier)
+        .map(|name| (name, SymbolKind::Type))
+}
+
+// Methods are defined in impl and trait blocks.
+fn rust_opens_type(line: &str) -> bool {
+    let line = strip_modifiers(line, RUST_MODIFIERS);
+
+    line.strip_prefix("impl").is_some_and(|rest| rest.starts_with(['<', ' '])) || strip_word(line, "trait").is_some()
+}
+
+fn go_definition(line: &str) -> Option<(&str, SymbolKind)> {
+    if let Some(rest) = strip_word(line, "func") {
+        // Methods have a receiver before their name, e.g. 'func (s *Server) Start()'.
+        if let Some(receiver) = rest.strip_prefix('(') {
+            let after_receiver = &receiver[receiver.find(')')? + 1..];
+            return leading_identifier(after_receiver.trim_start()).map(|name| (name, SymbolKind::Method));
+        }
+
+        return leading_identifier(rest).map(|name| (name, SymbolKind::Function));
+    }
+
+    strip_word(line, "type")
+        .and_then(leading_identifier)
+        .map(|name| (name, SymbolKind::Type))
+}
+
+// C, C++, C# and Java.
+fn c_like_definition(line: &str, in_type: bool) -> Option<(&str, SymbolKind)> {
+    // Preprocessor directives, annotations and attributes.
+    if line.starts_with(['#', '@', '[']) {
+        return None;
+    }
+
+    let line = strip_modifiers(line, C_LIKE_MODIFIERS);
+
+    // Java's generic methods, e.g. 'public <T> List<T> of(T item)'.
+    let line = strip_type_parameters(line);
+
+    for keyword in C_LIKE_TYPE_KEYWORDS {
+        let Some(rest) = strip_word(line, keyword) else {
+            continue;
+        };
+
+        // C++'s 'enum class'.
+        let rest = strip_word(rest, "class").unwrap_or(rest);
+        let name = leading_identifier(rest)?;
+        let after_name = rest[name.len()..].trim_start();
+
+        // A declaration, or a C function returning a struct, e.g. 'struct node *find(...)'.
+        if line.ends_with(';') && !line.contains('{') {
+            return None;
+        }
+
+        let returns_type = after_name.starts_with(['*', '&']) ||
+            (*keyword != "record" && after_name.contains('(') &&
+                leading_identifier(after_name).is_some_and(|word| !TYPE_NAME_FOLLOWERS.contains(&word)));
+
+        if !returns_type {
+            return Some((name, SymbolKind::Type));
+        }
+    }
+
+    c_like_function(line, in_type)
+}
+
+// A return type and a name followed by parameters, opening a body rather than ending
+// with ';'. Constructors have no return type, but are in a type's body or qualified
+// with their type's name in C++.
+fn c_like_function(line: &str, in_type: bool) -> Option<(&str, SymbolKind)> {
+    if line.ends_with(';') {
+        return None;
+    }
+
+    let first_word = leading_identifier(line)?;
+    if NOT_FUNCTIONS.contains(&first_word) {
+        return None;
+    }
+
+    let before_parameters = strip_generic_arguments(line[..line.find('(')?].trim_end());
+
+    // The name, possibly qualified, e.g. 'Widget::draw' or 'Widget::~Widget'.
+    let name_start = before_parameters
+        .char_indices()
+        .rev()
+        .find(|(_, c)| !(is_identifier_char(*c) || *c == ':' || *c == '~'))
+        .map_or(0, |(i, c)| i + c.len_utf8());
+
+    let qualified_name = &before_parameters[name_start..];
+    let return_type = before_parameters[..name_start].trim();
+
+    // Anything but types and modifiers before the name means it's an expression.
+    let type_like = |c: char| is_identifier_char(c) || c.is_whitespace() || "<>,*&[]:?".contains(c);
+    if !return_type.chars().all(type_like) {
+        return None;
+    }
+
+    let qualified = qualified_name.contains("::");
+    let name = qualified_name.rsplit("::").next()?.trim_start_matches('~');
+
+    if leading_identifier(name) != Some(name) || NOT_FUNCTIONS.contains(&name) {
+        return None;
+    }
+
+    if return_type.is_empty() && !in_type && !qualified {
+        return None;
+    }
+
+    let kind = if in_type || qualified { SymbolKind::Method } else { SymbolKind::Function };
+    Some((name, kind))
+}
+
+// TypeScript and JavaScript.
+fn script_definition(line: &str, in_type: bool) -> Option<(&str, SymbolKind)> {
+    let line = strip_modifiers(line, SCRIPT_MODIFIERS);
+    let line = strip_word(line, "const").filter(|rest| rest.starts_with("enum")).unwrap_or(line);
+
+    if let Some(rest) = strip_word(line, "function").or_else(|| line.strip_prefix("function*").map(str::trim_start)) {
+        return leading_identifier(rest).map(|name| (name, SymbolKind::Function));
+    }
+
+    if let Some(name) = SCRIPT_TYPE_KEYWORDS
+        .iter()
+        .find_map(|keyword| strip_word(line, keyword))
+        .and_then(leading_identifier) {
+        return Some((name, SymbolKind::Type));
+    }
+
+    // Functions assigned to variables, e.g. 'const handler = async (event) => {'.
+    if let Some(rest) = ["const", "let", "var"].iter().find_map(|keyword| strip_word(line, keyword)) {
+        let name = leading_identifier(rest)?;
+        let value = rest[name.len()..].split_once('=')?.1.trim_start();
+        let value = strip_word(value, "async").unwrap_or(value);
+
+        let is_function = value.starts_with("function") ||
+            (value.starts_with('(') && value.contains("=>")) ||
+            leading_identifier(value).is_some_and(|parameter| value[parameter.len()..].trim_start().starts_with("=>"));
+
+        return is_function.then_some((name, SymbolKind::Function));
+    }
+
+    // Methods in class bodies, e.g. 'render() {', 'map<T>(f: F): T {' or 'onClick = () => {'.
+    if in_type && !line.ends_with(';') {
+        let name = leading_identifier(line)?;
+        let after_name = line[name.len()..].trim_start();
+
+        let is_method = after_name.starts_with(['(', '<']) || (after_name.starts_with('=') && after_name.contains("=>"));
+
+        if is_method && !NOT_FUNCTIONS.contains(&name) {
+            return Some((name, SymbolKind::Method));
+        }
+    }
+
+    None
+}
+
+// 'Foo<T, U>' without the '<T, U>', if it ends with one.
+fn strip_generic_arguments(text: &str) -> &str {
+    if !text.ends_with('>') {
+        return text;
+    }
+
+    let mut depth = 0;
+
+    for (i, c) in text.char_indices().rev() {
+        match c {
+            '>' => depth += 1,
+            '<' => {
+                depth -= 1;
+
+                if depth == 0 {
+                    return text[..i].trim_end();
+                }
+            },
+            _ => {},
+        }
+    }
+
+    text
+}
+
+// 'text' without the '<T, U>' it starts with, if any.
+fn strip_type_parameters(text: &str) -> &str {
+    if !text.starts_with('<') {
+        return text;
+    }
+
+    let mut depth = 0;
+
+    for (i, c) in text.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' => {
+                depth -= 1;
+
+                if depth == 0 {
+                    return text[i + 1..].trim_start();
+                }
+            },
+            _ => {},
+        }
+    }
+
+    text
+}
+
+// 'line' without any of the leading 'modifiers'.
+fn strip_modifiers<'a>(mut line: &'a str, modifiers: &[&str]) -> &'a str {
+    while let Some(rest) = modifiers.iter().find_map(|modifier| strip_word(line, modifier)) {
+        line = rest;
+    }
+
+    line
+}
+
+// What follows 'word' in 'line', if it starts with that word.
+fn strip_word<'a>(line: &'a str, word: &str) -> Option<&'a str> {
+    let rest = line.strip_prefix(word)?;
+
+    if rest.starts_with(is_identifier_char) {
+        return None;
+    }
+
+    Some(rest.trim_start())
+}
+
+// The identifier 'text' starts with, if any.
+fn leading_identifier(text: &str) -> Option<&str> {
+    let end = text.find(|c: char| !is_identifier_char(c)).unwrap_or(text.len());
+    let identifier = &text[..end];
+
+    (!identifier.is_empty() && !identifier.starts_with(|c: char| c.is_ascii_digit())).then_some(identifier)
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$'
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::language::Language;
+
+    use super::{extract_symbols, match_rank, MatchRank, SymbolKind};
+
+    fn definitions(language: Language, text: &str) -> Vec<(String, SymbolKind, u32)> {
+        extract_symbols(language, text)
+            .into_iter()
+            .map(|symbol| (symbol.name, symbol.kind, symbol.line))
+            .collect()
+    }
+
+    fn symbol(name: &str, kind: SymbolKind, line: u32) -> (String, SymbolKind, u32) {
+        (name.to_string(), kind, line)
+    }
+
+    #[test]
+    fn symbols_rust() {
+        let text = "\
+pub struct Server<'a> {
+    name: &'a str,
+}
+
+impl<'a> Server<'a> {
+    pub(crate) async fn start(&self, port: u16) -> Result<(), Error> {
+        let handler = |request| { respond(request) };
+    }
+}
+
+// fn commented_out() {}
+pub const fn parse(text: &str) -> Server<'_> {
+    let s = \"fn in_a_string() {\";
+}
+
+pub trait Handler {
+    fn handle(&self);
+}
+
+enum State { Idle, Busy }
+const MAX: usize = 10;
+extern \"C\" fn callback() {}
+";
+
+        assert_eq!(vec![
+            symbol("Server", SymbolKind::Type, 1),
+            symbol("start", SymbolKind::Method, 6),
+            symbol("parse", SymbolKind::Function, 12),
+            symbol("Handler", SymbolKind::Type, 16),
+            symbol("handle", SymbolKind::Method, 17),
+            symbol("State", SymbolKind::Type, 20),
+            symbol("callback", SymbolKind::Function, 22),
+        ], definitions(Language::Rust, text));
+    }
+
+    #[test]
+    fn symbols_python() {
+        let text = "\
+import os
+
+class Parser(Base):
+    \"\"\"Parses things.\"\"\"
+
+    def __init__(self):
+        self.x = 1
+
+    async def parse(self, text):  # def not_this():
+        def helper():
+            pass
+
+def main():
+    pass
+";
+
+        assert_eq!(vec![
+            symbol("Parser", SymbolKind::Type, 3),
+            symbol("__init__", SymbolKind::Method, 6),
+            symbol("parse", SymbolKind::Method, 9),
+            symbol("helper", SymbolKind::Function, 10),
+            symbol("main", SymbolKind::Function, 13),
+        ], definitions(Language::Python, text));
+    }
+
+    #[test]
+    fn symbols_go() {
+        let text = "\
+package main
+
+type Server struct {
+    port int
+}
+
+func (s *Server) Start() error {
+    return listen(s.port)
+}
+
+func main() {
+}
+";
+
+        assert_eq!(vec![
+            symbol("Server", SymbolKind::Type, 3),
+            symbol("Start", SymbolKind::Method, 7),
+            symbol("main", SymbolKind::Function, 11),
+        ], definitions(Language::Go, text));
+    }
+
+    #[test]
+    fn symbols_c_and_cpp() {
+        let text = "\
+#include <stdio.h>
+#define MAX(a, b) ((a) > (b) ? (a) : (b))
+
+struct node;
+struct node {
+    int value;
+};
+
+static struct node *find(struct node *root, int value)
+{
+    if (root == NULL) {
+        return NULL;
+    }
+    printf(\"%d\", value);
+}
+
+int main(int argc, char **argv) {
+    return 0;
+}
+
+class Widget : public Base {
+public:
+    Widget();
+    virtual void draw() const {
+    }
+};
+
+Widget::Widget() : Base() {
+}
+
+enum class Color { Red, Green };
+";
+
+        assert_eq!(vec![
+            symbol("node", SymbolKind::Type, 5),
+            symbol("find", SymbolKind::Function, 9),
+            symbol("main", SymbolKind::Function, 17),
+            symbol("Widget", SymbolKind::Type, 21),
+            symbol("draw", SymbolKind::Method, 24),
+            symbol("Widget", SymbolKind::Method, 28),
+            symbol("Color", SymbolKind::Type, 31),
+        ], definitions(Language::Cpp, text));
+    }
+
+    #[test]
+    fn symbols_java_and_csharp() {
+        let java = "\
+@Service
+public final class OrderService extends Base implements Service {
+    private final Repository repository;
+
+    public OrderService(Repository repository) {
+        this.repository = repository;
+    }
+
+    @Override
+    public <T> List<T> findAll(Class<T> type) {
+        for (int i = 0; i < 10; i++) {
+            repository.load(i);
+        }
+        return new ArrayList<>();
+    }
+
+    public record Order(int id) {}
+}
+";
+
+        assert_eq!(vec![
+            symbol("OrderService", SymbolKind::Type, 2),
+            symbol("OrderService", SymbolKind::Method, 5),
+            symbol("findAll", SymbolKind::Method, 10),
+            symbol("Order", SymbolKind::Type, 17),
+        ], definitions(Language::Java, java));
+
+        let csharp = "\
+namespace Shop
+{
+    [Serializable]
+    public partial class Cart<T> : IEnumerable<T> where T : new()
+    {
+        public async Task<int> CountAsync(
+            CancellationToken token)
+        {
+            var total = Sum(items);
+            return total;
+        }
+
+        public int Total => items.Count;
+    }
+
+    public interface IStore { }
+}
+";
+
+        assert_eq!(vec![
+            symbol("Cart", SymbolKind::Type, 4),
+            symbol("CountAsync", SymbolKind::Method, 6),
+            symbol("IStore", SymbolKind::Type, 16),
+        ], definitions(Language::CSharp, csharp));
+    }
+
+    #[test]
+    fn symbols_typescript() {
+        let text = "\
+export interface Props { name: string; }
+
+export default class View extends Component<Props> {
+    private count = 0;
+
+    render(): Element {
+        if (this.count > 0) {
+            return null;
+        }
+    }
+
+    onClick = (event: Event) => {
+        this.count++;
+    }
+}
+
+export async function load(url: string) {
+}
+
+const handler = async (request) => {
+};
+
+const helper = x => x * 2;
+const total = items.map(item => item.price);
+type Id = string;
+";
+
+        assert_eq!(vec![
+            symbol("Props", SymbolKind::Type, 1),
+            symbol("View", SymbolKind::Type, 3),
+            symbol("render", SymbolKind::Method, 6),
+            symbol("onClick", SymbolKind::Method, 12),
+            symbol("load", SymbolKind::Function, 17),
+            symbol("handler", SymbolKind::Function, 20),
+            symbol("helper", SymbolKind::Function, 23),
+            symbol("Id", SymbolKind::Type, 25),
+        ], definitions(Language::TypeScript, text));
+    }
+
+    #[test]
+    fn symbols_match_rank() {
+        assert_eq!(Some(MatchRank::Exact), match_rank("parse", "parse"));
+        assert_eq!(Some(MatchRank::ExactIgnoringCase), match_rank("parse", "Parse"));
+        assert_eq!(Some(MatchRank::Prefix), match_rank("parse", "parse_query"));
+        assert_eq!(Some(MatchRank::Fuzzy(0)), match_rank("query", "parse_query"));
+        assert_eq!(Some(MatchRank::Fuzzy(5)), match_rank("pquery", "parse_query"));
+        assert_eq!(None, match_rank("zebra", "parse_query"));
+        assert_eq!(None, match_rank("", "parse_query"));
+
+        assert!(MatchRank::Prefix < MatchRank::Fuzzy(0));
+        assert!(MatchRank::Fuzzy(1) < MatchRank::Fuzzy(2));
+    }
+}
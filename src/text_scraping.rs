@@ -1,30 +1,76 @@
-use std::str::FromStr;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
 
+use aho_corasick::{AhoCorasick, AhoCorasickBuilder, MatchKind};
 use tokio::task::JoinSet;
 
-use crate::batching::batch_items_by_cpu_count;
+use crate::batching::{batch_items, jobs_count};
+use crate::case_folding::fold_case;
+use crate::encoding::{detect_encoding, DecodedText, Encoding};
+use crate::git::CommitFiles;
+use crate::language::Language;
+use crate::query::{Query, QueryMode};
+use crate::regions::{classify_regions, region_at, Region, RegionSpan};
 
 #[derive(Clone)]
 pub struct Match {
     pub file_path: String,
     pub offset: usize,
     pub length: usize,
+
+    // The match plus a few surrounding lines of context.
     pub text: String,
+
+    // Where the match starts within 'text', and how long it is there. Files that
+    // aren't UTF-8 are decoded for 'text', so this can differ from 'length'.
+    pub context_offset: usize,
+    pub context_length: usize,
+
+    // Which of the query's terms matched.
+    pub pattern: usize,
+
+    // Whether the match starts in code, a comment or a string literal.
+    pub region: Region,
+
+    // What the file is written in, if known.
+    pub language: Option<Language>,
+
+    // The line number of the first line of 'text', starting from 1.
+    pub context_line: usize,
+
+    // The comments and strings in 'text', in bytes of 'text', for highlighting it.
+    pub context_regions: Vec<RegionSpan>,
+
+    // True if the match is in the file's path rather than its contents, for binaries
+    // indexed by name only. 'text' is then the path the file was indexed under.
+    pub in_path: bool,
 }
 
-pub async fn parallel_scrape_files(files: &[String], query: &str) -> Vec<Match> {
+pub async fn parallel_scrape_files(files: &[String], query: &str, jobs: Option<usize>) -> Vec<Match> {
+    let Some(verifier) = Verifier::new(&Query::literal(query), Encoding::default()) else {
+        return vec![];
+    };
+
+    let verifier = Arc::new(verifier);
 
     let mut set = JoinSet::new();
 
-    let batches = batch_items_by_cpu_count(files);
+    let batches = batch_items(files, jobs_count(jobs));
 
+    // Each batch is read and matched on the blocking pool, one file after another.
     for batch in batches {
-        let task_query = String::from_str(query).unwrap();
+        let task_verifier = verifier.clone();
 
-        set.spawn(
-            async move {
-                scrape_files(&batch, &task_query).await
-            });
+        set.spawn_blocking(move || {
+            let mut matches = Vec::new();
+
+            for file in batch {
+                let file_bytes = std::fs::read(&file).unwrap();
+                matches.extend(task_verifier.scrape_bytes(&file, &file_bytes));
+            }
+
+            matches
+        });
     }
 
     let mut all_matches = Vec::new();
@@ -38,61 +84,395 @@ pub async fn parallel_scrape_files(files: &[String], query: &str) -> Vec<Match>
     all_matches
 }
 
+#[derive(Clone, Copy, Default)]
+pub struct ScrapeOptions {
+    // Stop once this many matches have been reported.
+    pub max_results: Option<usize>,
+
+    // Stop once this many files with matches have been reported.
+    pub max_files: Option<usize>,
+
+    // Report files in the order they were given in, rather than as soon as they're
+    // scraped, so that output is reproducible.
+    pub ordered: bool,
+
+    // Assumed for files without a BOM that aren't valid UTF-8.
+    pub default_encoding: Encoding,
+
+    // How many files are scraped at once. Defaults to one per CPU.
+    pub jobs: Option<usize>,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct ScrapeSummary {
+    pub matches_count: usize,
+    pub files_count: usize,
+
+    // True if a limit was hit and the remaining files were skipped.
+    pub truncated: bool,
+
+    // Files that couldn't be read and were skipped, e.g. deleted since they were
+    // indexed, or blobs missing from the object database.
+    pub unreadable_files_count: usize,
+}
+
+// Scrapes files concurrently, handing each file's matches to 'on_matches' as soon
+// as they're available instead of waiting for every file. Outstanding work is
+// cancelled as soon as one of the limits in 'options' is hit. Files that are keys of
+// 'name_only_paths' aren't read; the path they map to, as they were indexed under,
+// is matched instead.
+pub async fn stream_scrape_files(
+    files: &[String],
+    name_only_paths: &HashMap<String, String>,
+    query: &Query,
+    options: &ScrapeOptions,
+    on_matches: impl FnMut(&[Match])) -> ScrapeSummary {
+
+    stream_scrape(files, name_only_paths, None, query, options, on_matches).await
+}
+
+// Same as stream_scrape_files(), but reads the files from a commit in the git object
+// database instead of from disk. 'files' are paths as commit_files lists them.
+pub async fn stream_scrape_commit_files(
+    files: &[String],
+    name_only_paths: &HashMap<String, String>,
+    commit_files: Arc<CommitFiles>,
+    query: &Query,
+    options: &ScrapeOptions,
+    on_matches: impl FnMut(&[Match])) -> ScrapeSummary {
+
+    stream_scrape(files, name_only_paths, Some(commit_files), query, options, on_matches).await
+}
+
+async fn stream_scrape(
+    files: &[String],
+    name_only_paths: &HashMap<String, String>,
+    commit_files: Option<Arc<CommitFiles>>,
+    query: &Query,
+    options: &ScrapeOptions,
+    mut on_matches: impl FnMut(&[Match])) -> ScrapeSummary {
+
+    let mut summary = ScrapeSummary::default();
+
+    let Some(verifier) = Verifier::new(query, options.default_encoding) else {
+        return summary;
+    };
+
+    let verifier = Arc::new(verifier);
+
+    if options.max_results == Some(0) || options.max_files == Some(0) {
+        summary.truncated = !files.is_empty();
+        return summary;
+    }
+
+    let max_in_flight = jobs_count(options.jobs);
+
+    let mut set = JoinSet::new();
+    let mut next_to_spawn = 0;
+
+    // Results that arrived ahead of their turn, when reporting in order.
+    let mut next_to_report = 0;
+    let mut pending: BTreeMap<usize, Vec<Match>> = BTreeMap::new();
+
+    loop {
+        while next_to_spawn < files.len() && set.len() < max_in_flight {
+            let file = files[next_to_spawn].clone();
+            let task_verifier = verifier.clone();
+            let task_commit_files = commit_files.clone();
+            let position = next_to_spawn;
+
+            match name_only_paths.get(&file).cloned() {
+                Some(indexed_path) => set.spawn(async move { (position, Ok(task_verifier.scrape_path(&file, &indexed_path))) }),
+                None => set.spawn(async move { (position, task_verifier.scrape_file(file, task_commit_files).await) }),
+            };
+            next_to_spawn += 1;
+        }
+
+        let Some(res) = set.join_next().await else {
+            break;
+        };
+
+        let (position, scraped) = res.unwrap();
+
+        let matches = scraped.unwrap_or_else(|_| {
+            summary.unreadable_files_count += 1;
+            Vec::new()
+        });
+
+        let ready = if options.ordered {
+            pending.insert(position, matches);
+
+            let mut ready = Vec::new();
+            while let Some(matches) = pending.remove(&next_to_report) {
+                ready.push(matches);
+                next_to_report += 1;
+            }
+
+            ready
+        } else {
+            vec![matches]
+        };
+
+        let mut ready = ready.into_iter().filter(|matches| !matches.is_empty());
+
+        while let Some(mut matches) = ready.next() {
+            let found_count = matches.len();
+
+            if let Some(max_results) = options.max_results {
+                matches.truncate(max_results - summary.matches_count);
+            }
+
+            summary.matches_count += matches.len();
+            summary.files_count += 1;
+
+            on_matches(&matches);
+
+            if options.max_results.is_some_and(|max| summary.matches_count >= max) ||
+                options.max_files.is_some_and(|max| summary.files_count >= max) {
+                // Only truncated if something was actually left out: matches cut from
+                // this file, or files that are unscraped, in flight or not yet reported.
+                summary.truncated = matches.len() < found_count ||
+                    ready.next().is_some() ||
+                    pending.values().any(|matches| !matches.is_empty()) ||
+                    !set.is_empty() ||
+                    next_to_spawn < files.len();
+
+                set.abort_all();
+                return summary;
+            }
+        }
+    }
+
+    summary
+}
+
 pub async fn scrape_files(files: &[String], query: &str) -> Vec<Match> {
-    if query.len() == 0 {
+    scrape_files_for_query(files, &Query::literal(query)).await
+}
+
+pub async fn scrape_files_for_query(files: &[String], query: &Query) -> Vec<Match> {
+    let Some(verifier) = Verifier::new(query, Encoding::default()) else {
         return vec![];
-    }
-    
+    };
+
+    let verifier = Arc::new(verifier);
     let mut matches = Vec::new();
 
     for file in files {
-        // TODO: I'm sure that we can do this faster by doing case-insensitive comparisons
-        // instead of a to_lowercase().
-        let file_text = String::from_utf8(tokio::fs::read(file).await.unwrap()).unwrap();
-        let file_text_without_bom = drop_bom(&file_text);
-        let lowered_file_text = file_text_without_bom.to_lowercase();
-        let lowered_query = query.to_lowercase();
-
-        // TODO: hard coding 1 to skip BOM.
-        for i in 0..file_text.len() {
-            if lowered_file_text.is_char_boundary(i) &&
-                lowered_file_text[i..].starts_with(&lowered_query) {
-                matches.push(Match {
-                    file_path: file.to_string(),
-                    offset: i,
-                    length: lowered_query.len(),
-                    text: format_match(&file_text_without_bom, &lowered_file_text, i, lowered_query.len(), 5)
-                });
-            }
-        }
+        matches.extend(verifier.clone().scrape_file(file.clone(), None).await.unwrap_or_default());
     }
 
     matches
 }
 
-fn drop_bom(text: &str) -> &str {
-    let bytes = text.as_bytes();
+// Finds every occurrence of every term of a query in a single pass over a file,
+// ignoring case. Aho-Corasick handles many terms at once, and for a single term its
+// prefilter skips ahead memchr-style to candidate bytes. Terms are case folded up
+// front; ASCII files are then searched in place, ASCII case-insensitively, and
+// anything else is decoded and case folded first, with matches mapped back to the
+// original bytes.
+struct Verifier {
+    searcher: AhoCorasick,
+    terms_count: usize,
+
+    // Maps the searcher's pattern ids back to the query's term indexes, since
+    // empty terms are left out.
+    term_indexes: Vec<usize>,
+    mode: QueryMode,
+
+    // Only matches starting in this region are kept, if set.
+    region: Option<Region>,
+
+    // Assumed for files without a BOM that aren't valid UTF-8.
+    default_encoding: Encoding,
+}
+
+impl Verifier {
+    // None if there is nothing to search for.
+    fn new(query: &Query, default_encoding: Encoding) -> Option<Verifier> {
+        let term_indexes: Vec<usize> = query.terms
+            .iter()
+            .enumerate()
+            .filter(|(_, term)| !term.is_empty())
+            .map(|(i, _)| i)
+            .collect();
+
+        if term_indexes.is_empty() {
+            return None;
+        }
+
+        let searcher = AhoCorasickBuilder::new()
+            .ascii_case_insensitive(true)
+            .match_kind(MatchKind::Standard)
+            .build(term_indexes.iter().map(|i| fold_case(&query.terms[*i])))
+            .unwrap();
+
+        Some(Verifier {
+            searcher,
+            terms_count: term_indexes.len(),
+            term_indexes,
+            mode: query.mode,
+            region: query.region,
+            default_encoding,
+        })
+    }
+
+    // Reading is async, but matching is CPU bound and runs on the blocking pool
+    // so that it doesn't hold up the runtime. Blobs are read there too, since
+    // they have to be inflated.
+    async fn scrape_file(self: Arc<Self>, file: String, commit_files: Option<Arc<CommitFiles>>) -> Result<Vec<Match>, std::io::Error> {
+        if let Some(commit_files) = commit_files {
+            return tokio::task::spawn_blocking(move || {
+                let file_bytes = commit_files.read(&file)?;
+                Ok(self.scrape_bytes(&file, &file_bytes))
+            }).await.unwrap();
+        }
+
+        let file_bytes = tokio::fs::read(&file).await?;
+
+        Ok(tokio::task::spawn_blocking(move || self.scrape_bytes(&file, &file_bytes)).await.unwrap())
+    }
+
+    // Binaries indexed by name only can't be searched by their contents, so the path
+    // they were indexed under is matched instead. Offsets are into that path.
+    fn scrape_path(&self, file_path: &str, indexed_path: &str) -> Vec<Match> {
+        self.scrape_bytes(file_path, indexed_path.as_bytes())
+            .into_iter()
+            .map(|scraped_match| Match { in_path: true, ..scraped_match })
+            .collect()
+    }
+
+    // Offsets and lengths of the returned matches are into 'file_bytes' as given,
+    // BOM included, and always fall on character boundaries.
+    fn scrape_bytes(&self, file_path: &str, file_bytes: &[u8]) -> Vec<Match> {
+        let (encoding, bom_length) = detect_encoding(file_bytes, self.default_encoding);
+        let text_bytes = &file_bytes[bom_length..];
+
+        let hits = self.find_terms(text_bytes, encoding);
+
+        // Context is cut from UTF-8, so anything else is decoded once for all the hits.
+        let decoded = (encoding != Encoding::Utf8 && !hits.is_empty())
+            .then(|| DecodedText::new(text_bytes, encoding));
+
+        let utf8_bytes = decoded.as_ref().map_or(text_bytes, |decoded| decoded.text.as_bytes());
+
+        // Comments, strings and lines are only looked for in files with hits.
+        let language = Language::detect(file_path, utf8_bytes);
 
-    if bytes.len() >= 3 &&
-        bytes[0] == 0xef && bytes[1] == 0xbb && bytes[2] == 0xbf {
-            return &text[3..];
+        let (spans, line_breaks) = if hits.is_empty() {
+            (Vec::new(), Vec::new())
+        } else {
+            let line_breaks: Vec<usize> = utf8_bytes.iter().enumerate().filter(|(_, byte)| **byte == b'\n').map(|(i, _)| i).collect();
+            (classify_regions(language, utf8_bytes), line_breaks)
+        };
+
+        let mut matches = Vec::new();
+        let mut terms_found = vec![false; self.terms_count];
+
+        for (start, end, pattern) in hits {
+            let (utf8_start, utf8_end) = match &decoded {
+                Some(decoded) => (decoded.decoded_offset(start), decoded.decoded_offset(end)),
+                None => (start, end),
+            };
+
+            let region = region_at(&spans, utf8_start);
+            if self.region.is_some_and(|wanted| wanted != region) {
+                continue;
+            }
+
+            let (text, context_offset, context_start) = format_match(utf8_bytes, utf8_start, utf8_end - utf8_start, 5);
+            let context_regions = context_regions(&spans, utf8_bytes, context_start, &text);
+
+            terms_found[pattern] = true;
+
+            matches.push(Match {
+                file_path: file_path.to_string(),
+                offset: bom_length + start,
+                length: end - start,
+                text,
+                context_offset,
+                context_length: utf8_end - utf8_start,
+                pattern: self.term_indexes[pattern],
+                region,
+                language,
+                context_line: line_breaks.partition_point(|line_break| *line_break < context_start) + 1,
+                context_regions,
+                in_path: false,
+            });
+        }
+
+        if self.mode == QueryMode::All && !terms_found.iter().all(|found| *found) {
+            return vec![];
+        }
+
+        matches.sort_by_key(|scraped_match| (scraped_match.offset, scraped_match.pattern));
+
+        // Hits inside the folded form of one character (e.g. both 's' of 'ß') widen
+        // to the same character.
+        matches.dedup_by_key(|scraped_match| (scraped_match.offset, scraped_match.length, scraped_match.pattern));
+
+        matches
+    }
+
+    // Returns the start, end and pattern id of every hit, in bytes of 'text_bytes'.
+    // Overlapping, so that every offset at which a term starts is reported.
+    fn find_terms(&self, text_bytes: &[u8], encoding: Encoding) -> Vec<(usize, usize, usize)> {
+        if encoding == Encoding::Utf8 && text_bytes.is_ascii() {
+            return self.searcher
+                .find_overlapping_iter(text_bytes)
+                .map(|found| (found.start(), found.end(), found.pattern().as_usize()))
+                .collect();
         }
 
-    return text;
+        let folded = DecodedText::folded(text_bytes, encoding);
+
+        self.searcher
+            .find_overlapping_iter(&folded.text)
+            .map(|found| {
+                let (start, end) = folded.original_range(found.start(), found.end());
+                (start, end, found.pattern().as_usize())
+            })
+            .collect()
+    }
 }
 
-fn format_match(file_text: &str, lowered_file_text: &str, offset: usize, length: usize, surrounding_lines: usize) -> String {
+// The parts of 'spans' within the context starting at 'context_start', relative to
+// it. Empty if invalid UTF-8 was replaced in the context, since offsets would be off.
+fn context_regions(spans: &[RegionSpan], utf8_bytes: &[u8], context_start: usize, text: &str) -> Vec<RegionSpan> {
+    if !utf8_bytes[context_start..].starts_with(text.as_bytes()) {
+        return Vec::new();
+    }
+
+    let context_end = context_start + text.len();
+
+    let first = spans.partition_point(|span| span.end <= context_start);
+
+    spans[first..]
+        .iter()
+        .take_while(|span| span.start < context_end)
+        .map(|span| RegionSpan {
+            start: span.start.max(context_start) - context_start,
+            end: span.end.min(context_end) - context_start,
+            region: span.region,
+        })
+        .collect()
+}
+
+// Returns the match with surrounding context, the offset of the match within it, and
+// where the context starts in 'file_bytes'. Line breaks are single bytes in UTF-8, so
+// scanning bytes never splits a character.
+fn format_match(file_bytes: &[u8], offset: usize, length: usize, surrounding_lines: usize) -> (String, usize, usize) {
     let per_direction_line_budget = surrounding_lines / 2;
     let mut expanded_offset = offset;
-    let mut expanded_length = length;
+    let mut expanded_end = offset + length;
 
     // Start at the offset and iterate backwards.
     let mut lines: usize = 0;
-    for c in lowered_file_text[0..offset].chars().rev().peekable() {
-        if c == '\r' || c == '\n' {
+    for c in file_bytes[0..offset].iter().rev() {
+        if *c == b'\r' || *c == b'\n' {
             lines += 1;
-            
-            if lines >= per_direction_line_budget + 1 {
+
+            if lines > per_direction_line_budget {
                 break;
             }
         }
@@ -100,30 +480,64 @@ fn format_match(file_text: &str, lowered_file_text: &str, offset: usize, length:
         expanded_offset -= 1;
     }
 
-    // Start at the length and iterate forwards.
+    // Start at the end of the match and iterate forwards.
     let mut lines: usize = 0;
-    for c in lowered_file_text[offset + length..].chars() {
-        if c == '\r' || c == '\n' {
+    for c in file_bytes[offset + length..].iter() {
+        if *c == b'\r' || *c == b'\n' {
             lines += 1;
-                
-            if lines >= per_direction_line_budget + 1 {
+
+            if lines > per_direction_line_budget {
                 break;
             }
         }
 
-        expanded_length += 1;
+        expanded_end += 1;
     }
 
-    file_text[expanded_offset..offset + expanded_length].to_string()
+    // Invalid UTF-8 is replaced, which can change lengths, so the offset is measured in the replaced text.
+    let mut text = String::from_utf8_lossy(&file_bytes[expanded_offset..offset]).to_string();
+    let context_offset = text.len();
+    text.push_str(&String::from_utf8_lossy(&file_bytes[offset..expanded_end]));
+
+    (text, context_offset, expanded_offset)
 }
 
 // TODO: add test coverage for CRLF line endings. Right now we retrieve only
 // half as many lines for those due to counting CR and LF as a separate lines.
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
     use std::str::FromStr;
 
-    use super::scrape_files;
+    use crate::encoding::Encoding;
+    use crate::language::Language;
+    use crate::query::Query;
+    use crate::regions::Region;
+
+    use super::{parallel_scrape_files, scrape_files, scrape_files_for_query, stream_scrape_files, Match, ScrapeOptions, Verifier};
+
+    fn scrape_text(text: &str, query: &str) -> Vec<Match> {
+        let matches = Verifier::new(&Query::literal(query), Encoding::default()).unwrap().scrape_bytes("test", text.as_bytes());
+
+        // Whatever matched, offset and length must slice the original text.
+        for scraped_match in &matches {
+            assert!(text.is_char_boundary(scraped_match.offset));
+            assert!(text.is_char_boundary(scraped_match.offset + scraped_match.length));
+        }
+
+        matches
+    }
+
+    fn matched_text<'a>(text: &'a str, scraped_match: &Match) -> &'a str {
+        &text[scraped_match.offset..scraped_match.offset + scraped_match.length]
+    }
+
+    fn test_files() -> Vec<String> {
+        vec![
+            String::from("test-assets/test-file-lf.txt"),
+            String::from("test-assets/test-file-lf-BOM.txt"),
+        ]
+    }
 
     #[tokio::test]
     async fn scrape_emptystring() {
@@ -131,9 +545,9 @@ mod tests {
             println!("{}", file.unwrap().path().display());
         }
 
-        let matches = scrape_files(&vec![String::from_str("test-assets/test-file-lf.txt").unwrap()], "").await;
+        let matches = scrape_files(&[String::from_str("test-assets/test-file-lf.txt").unwrap()], "").await;
 
-        assert!(matches.len() == 0);
+        assert!(matches.is_empty());
     }
 
     #[tokio::test]
@@ -142,7 +556,7 @@ mod tests {
             println!("{}", file.unwrap().path().display());
         }
 
-        let matches = scrape_files(&vec![String::from_str("test-assets/test-file-lf.txt").unwrap()], "abc").await;
+        let matches = scrape_files(&[String::from_str("test-assets/test-file-lf.txt").unwrap()], "abc").await;
 
         assert_eq!(3, matches.len());
 
@@ -150,16 +564,19 @@ mod tests {
         assert_eq!(0, matches[0].offset);
         assert_eq!(3, matches[0].length);
         assert_eq!("ABCDEFGH\nIJKLMNOP\nQRSTUVWX", matches[0].text);
+        assert_eq!(0, matches[0].context_offset);
 
         assert_eq!("test-assets/test-file-lf.txt", matches[1].file_path);
         assert_eq!(36, matches[1].offset);
         assert_eq!(3, matches[1].length);
         assert_eq!("QRSTUVWX\nYZ012345\nABCDEFGH ABCDEFGH\nIJKLMNOP IJKLMNOP\nQRSTUVWX QRSTUVWX", matches[1].text);
+        assert_eq!(18, matches[1].context_offset);
 
         assert_eq!("test-assets/test-file-lf.txt", matches[2].file_path);
         assert_eq!(45, matches[2].offset);
         assert_eq!(3, matches[2].length);
         assert_eq!("QRSTUVWX\nYZ012345\nABCDEFGH ABCDEFGH\nIJKLMNOP IJKLMNOP\nQRSTUVWX QRSTUVWX", matches[2].text);
+        assert_eq!(27, matches[2].context_offset);
     }
 
     #[tokio::test]
@@ -168,24 +585,33 @@ mod tests {
             println!("{}", file.unwrap().path().display());
         }
 
-        let matches = scrape_files(&vec![String::from_str("test-assets/test-file-lf-BOM.txt").unwrap()], "abc").await;
+        let matches = scrape_files(&[String::from_str("test-assets/test-file-lf-BOM.txt").unwrap()], "abc").await;
 
         assert_eq!(3, matches.len());
 
         assert_eq!("test-assets/test-file-lf-BOM.txt", matches[0].file_path);
-        assert_eq!(0, matches[0].offset);
+        assert_eq!(3, matches[0].offset);
         assert_eq!(3, matches[0].length);
         assert_eq!("ABCDEFGH\nIJKLMNOP\nQRSTUVWX", matches[0].text);
+        assert_eq!(0, matches[0].context_offset);
 
         assert_eq!("test-assets/test-file-lf-BOM.txt", matches[1].file_path);
-        assert_eq!(36, matches[1].offset);
+        assert_eq!(39, matches[1].offset);
         assert_eq!(3, matches[1].length);
         assert_eq!("QRSTUVWX\nYZ012345\nABCDEFGH ABCDEFGH\nIJKLMNOP IJKLMNOP\nQRSTUVWX QRSTUVWX", matches[1].text);
+        assert_eq!(18, matches[1].context_offset);
 
         assert_eq!("test-assets/test-file-lf-BOM.txt", matches[2].file_path);
-        assert_eq!(45, matches[2].offset);
+        assert_eq!(48, matches[2].offset);
         assert_eq!(3, matches[2].length);
         assert_eq!("QRSTUVWX\nYZ012345\nABCDEFGH ABCDEFGH\nIJKLMNOP IJKLMNOP\nQRSTUVWX QRSTUVWX", matches[2].text);
+        assert_eq!(27, matches[2].context_offset);
+
+        // Offsets are into the file as it is on disk, BOM included.
+        let file_bytes = std::fs::read("test-assets/test-file-lf-BOM.txt").unwrap();
+        for scraped_match in matches {
+            assert_eq!(b"ABC", &file_bytes[scraped_match.offset..scraped_match.offset + scraped_match.length]);
+        }
     }
 
     #[tokio::test]
@@ -194,8 +620,285 @@ mod tests {
             println!("{}", file.unwrap().path().display());
         }
 
-        let matches = scrape_files(&vec![String::from_str("test-assets/test-file-lf.txt").unwrap()], "cba").await;
+        let matches = scrape_files(&[String::from_str("test-assets/test-file-lf.txt").unwrap()], "cba").await;
 
         assert_eq!(0, matches.len());
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn parallel_scrape_any_jobs_count() {
+        for jobs in [None, Some(1), Some(2), Some(8)] {
+            let mut offsets: Vec<(String, usize)> = parallel_scrape_files(&test_files(), "abc", jobs)
+                .await
+                .into_iter()
+                .map(|scraped_match| (scraped_match.file_path, scraped_match.offset))
+                .collect();
+            offsets.sort();
+
+            assert_eq!(6, offsets.len());
+            assert_eq!(("test-assets/test-file-lf-BOM.txt".to_string(), 3), offsets[0]);
+            assert_eq!(("test-assets/test-file-lf.txt".to_string(), 45), offsets[5]);
+        }
+    }
+
+    #[tokio::test]
+    async fn stream_scrape_reports_everything() {
+        let mut reported = Vec::new();
+
+        let summary = stream_scrape_files(&test_files(), &HashMap::new(), &Query::literal("abc"), &ScrapeOptions::default(), |matches| {
+            reported.extend_from_slice(matches);
+        }).await;
+
+        assert_eq!(6, reported.len());
+        assert_eq!(6, summary.matches_count);
+        assert_eq!(2, summary.files_count);
+        assert!(!summary.truncated);
+    }
+
+    #[tokio::test]
+    async fn stream_scrape_ordered() {
+        let options = ScrapeOptions { ordered: true, ..Default::default() };
+
+        for _ in 0..10 {
+            let mut reported_files = Vec::new();
+
+            stream_scrape_files(&test_files(), &HashMap::new(), &Query::literal("abc"), &options, |matches| {
+                reported_files.push(matches[0].file_path.clone());
+            }).await;
+
+            assert_eq!(test_files(), reported_files);
+        }
+    }
+
+    #[tokio::test]
+    async fn stream_scrape_max_results() {
+        let options = ScrapeOptions { max_results: Some(4), ordered: true, ..Default::default() };
+        let mut reported = Vec::new();
+
+        let summary = stream_scrape_files(&test_files(), &HashMap::new(), &Query::literal("abc"), &options, |matches| {
+            reported.extend_from_slice(matches);
+        }).await;
+
+        // All three from the first file, then cut off partway through the second.
+        assert_eq!(4, reported.len());
+        assert_eq!("test-assets/test-file-lf-BOM.txt", reported[3].file_path);
+        assert_eq!(4, summary.matches_count);
+        assert!(summary.truncated);
+    }
+
+    #[tokio::test]
+    async fn stream_scrape_limit_hit_on_last_file() {
+        // Both files and all six matches are reported, so nothing was cut off.
+        for options in [
+            ScrapeOptions { max_results: Some(6), ..Default::default() },
+            ScrapeOptions { max_files: Some(2), ordered: true, ..Default::default() },
+        ] {
+            let summary = stream_scrape_files(&test_files(), &HashMap::new(), &Query::literal("abc"), &options, |_| {}).await;
+
+            assert_eq!(6, summary.matches_count);
+            assert!(!summary.truncated);
+        }
+    }
+
+    #[tokio::test]
+    async fn stream_scrape_unreadable_files() {
+        // A file deleted since it was indexed is skipped rather than ending the search.
+        let mut files = test_files();
+        files.insert(1, String::from("test-assets/deleted-since-indexing.txt"));
+
+        let summary = stream_scrape_files(&files, &HashMap::new(), &Query::literal("abc"), &ScrapeOptions::default(), |_| {}).await;
+
+        assert_eq!((6, 2, 1), (summary.matches_count, summary.files_count, summary.unreadable_files_count));
+    }
+
+    #[tokio::test]
+    async fn stream_scrape_max_files() {
+        let options = ScrapeOptions { max_files: Some(1), ..Default::default() };
+        let mut reported = Vec::new();
+
+        let summary = stream_scrape_files(&test_files(), &HashMap::new(), &Query::literal("abc"), &options, |matches| {
+            reported.extend_from_slice(matches);
+        }).await;
+
+        assert_eq!(3, reported.len());
+        assert_eq!(1, summary.files_count);
+        assert!(summary.truncated);
+    }
+
+    #[tokio::test]
+    async fn scrape_case_insensitive() {
+        let matches = scrape_files(&[String::from("test-assets/test-file-lf.txt")], "aBc").await;
+
+        assert_eq!(3, matches.len());
+    }
+
+    #[tokio::test]
+    async fn scrape_overlapping() {
+        // 'ABCDEFGH ABCDEFGH' has the query twice; 'H A' spans the space.
+        let matches = scrape_files(&[String::from("test-assets/test-file-lf.txt")], "gh abc").await;
+
+        assert_eq!(1, matches.len());
+        assert_eq!(42, matches[0].offset);
+    }
+
+    #[tokio::test]
+    async fn scrape_any_terms_in_one_pass() {
+        let matches = scrape_files_for_query(
+            &[String::from("test-assets/test-file-lf.txt")],
+            &Query::any_of("abc yz0 cba")).await;
+
+        // Three of each term that exists, in file order, tagged with the term.
+        assert_eq!(6, matches.len());
+        assert!(matches.windows(2).all(|pair| pair[0].offset < pair[1].offset));
+        assert_eq!(3, matches.iter().filter(|scraped_match| scraped_match.pattern == 0).count());
+        assert_eq!(3, matches.iter().filter(|scraped_match| scraped_match.pattern == 1).count());
+        assert_eq!(0, matches[0].pattern);
+        assert_eq!("ABC", &matches[0].text[matches[0].context_offset..matches[0].context_offset + 3]);
+        assert_eq!("YZ0", &matches[1].text[matches[1].context_offset..matches[1].context_offset + 3]);
+    }
+
+    #[tokio::test]
+    async fn scrape_all_terms_required() {
+        let file = [String::from("test-assets/test-file-lf.txt")];
+
+        assert_eq!(6, scrape_files_for_query(&file, &Query::all_of("abc yz0")).await.len());
+        assert!(scrape_files_for_query(&file, &Query::all_of("abc cba")).await.is_empty());
+    }
+
+    #[test]
+    fn scrape_regions() {
+        let text = "// TODO: handle errors\nfn todo() { panic!(\"todo: not yet\"); }\n";
+        let scrape = |query: Query| Verifier::new(&query, Encoding::default()).unwrap().scrape_bytes("src/lib.rs", text.as_bytes());
+
+        let regions: Vec<Region> = scrape(Query::literal("todo")).iter().map(|m| m.region).collect();
+        assert_eq!(vec![Region::Comment, Region::Code, Region::String], regions);
+
+        for region in [Region::Code, Region::Comment, Region::String] {
+            let matches = scrape(Query::literal("todo").in_region(region));
+
+            assert_eq!(1, matches.len());
+            assert_eq!(region, matches[0].region);
+        }
+
+        // Every term has to be in the region.
+        assert_eq!(2, scrape(Query::all_of("todo handle").in_region(Region::Comment)).len());
+        assert!(scrape(Query::all_of("todo panic").in_region(Region::Comment)).is_empty());
+
+        // Files in no known language are all code.
+        let matches = Verifier::new(&Query::literal("todo").in_region(Region::Comment), Encoding::default()).unwrap().scrape_bytes("notes.txt", text.as_bytes());
+        assert!(matches.is_empty());
+    }
+
+    #[test]
+    fn scrape_context_lines_and_regions() {
+        let text = "/* one\ntwo\nthree\nfour */ let target = \"x\";\nfive\n";
+        let matches = Verifier::new(&Query::literal("target"), Encoding::default()).unwrap().scrape_bytes("src/lib.rs", text.as_bytes());
+
+        assert_eq!(1, matches.len());
+        assert_eq!(Some(Language::Rust), matches[0].language);
+        assert_eq!("two\nthree\nfour */ let target = \"x\";\nfive\n", matches[0].text);
+        assert_eq!(2, matches[0].context_line);
+
+        // The comment started before the context, and is cut to fit it.
+        let regions: Vec<(Region, &str)> = matches[0].context_regions
+            .iter()
+            .map(|span| (span.region, &matches[0].text[span.start..span.end]))
+            .collect();
+
+        assert_eq!(vec![(Region::Comment, "two\nthree\nfour */"), (Region::String, "\"x\"")], regions);
+    }
+
+    #[test]
+    fn scrape_turkish_dotted_i() {
+        // 'İ' lowers to 'i' plus a combining dot, one byte longer than the original.
+        let text = "x İSTANBUL istanbul İstanbul";
+        let matches = scrape_text(text, "istanbul");
+
+        assert_eq!(1, matches.len());
+        assert_eq!("istanbul", matched_text(text, &matches[0]));
+
+        let matches = scrape_text(text, "İSTANBUL");
+
+        assert_eq!(vec!["İSTANBUL", "İstanbul"], matches.iter().map(|m| matched_text(text, m)).collect::<Vec<_>>());
+        assert_eq!("İstanbul", &matches[1].text[matches[1].context_offset..matches[1].context_offset + matches[1].length]);
+    }
+
+    #[test]
+    fn scrape_german_sharp_s() {
+        let text = "Straße, STRASSE und STRAẞE";
+
+        for query in ["strasse", "STRASSE", "straße", "STRAẞE"] {
+            let matches = scrape_text(text, query);
+
+            assert_eq!(vec!["Straße", "STRASSE", "STRAẞE"], matches.iter().map(|m| matched_text(text, m)).collect::<Vec<_>>());
+        }
+
+        // A hit on part of the folded form still reports the whole character, once.
+        let matches = scrape_text("aßb", "s");
+        assert_eq!(1, matches.len());
+        assert_eq!((1, 2), (matches[0].offset, matches[0].length));
+    }
+
+    #[test]
+    fn scrape_multibyte() {
+        let text = "日本語のテキスト\nÜBER über\n日本語";
+
+        let matches = scrape_text(text, "日本語");
+        assert_eq!(vec![0, text.rfind("日本語").unwrap()], matches.iter().map(|m| m.offset).collect::<Vec<_>>());
+        assert_eq!(9, matches[0].length);
+
+        let matches = scrape_text(text, "über");
+        assert_eq!(vec!["ÜBER", "über"], matches.iter().map(|m| matched_text(text, m)).collect::<Vec<_>>());
+        assert_eq!("ÜBER", &matches[0].text[matches[0].context_offset..matches[0].context_offset + matches[0].length]);
+    }
+
+    #[test]
+    fn scrape_bom_and_non_ascii() {
+        let text = "\u{feff}// Größe\nGRÖSSE";
+        let matches = scrape_text(text, "größe");
+
+        assert_eq!(vec!["Größe", "GRÖSSE"], matches.iter().map(|m| matched_text(text, m)).collect::<Vec<_>>());
+        assert_eq!(6, matches[0].offset);
+        assert_eq!("// Größe\nGRÖSSE", matches[0].text);
+    }
+
+    #[test]
+    fn scrape_invalid_utf8() {
+        let matches = Verifier::new(&Query::literal("abc"), Encoding::Utf8).unwrap().scrape_bytes("test", b"\xfe\xfdABC\xc3");
+
+        assert_eq!(1, matches.len());
+        assert_eq!((2, 3), (matches[0].offset, matches[0].length));
+
+        // Each invalid byte becomes a three byte replacement character in the context.
+        let context_end = matches[0].context_offset + matches[0].context_length;
+        assert_eq!("ABC", &matches[0].text[matches[0].context_offset..context_end]);
+    }
+
+    #[test]
+    fn scrape_utf16() {
+        let mut file_bytes = vec![0xff, 0xfe];
+        file_bytes.extend("line one\nGrüße, world\n".encode_utf16().flat_map(|unit| unit.to_le_bytes()));
+
+        let matches = Verifier::new(&Query::literal("GRÜSSE"), Encoding::default()).unwrap().scrape_bytes("test", &file_bytes);
+
+        // Offsets are into the UTF-16 bytes, context is decoded.
+        assert_eq!(1, matches.len());
+        assert_eq!((20, 10), (matches[0].offset, matches[0].length));
+        assert_eq!("line one\nGrüße, world\n", matches[0].text);
+        assert_eq!(9, matches[0].context_offset);
+        assert_eq!("Grüße", &matches[0].text[9..9 + matches[0].context_length]);
+    }
+
+    #[test]
+    fn scrape_latin1() {
+        let file_bytes = b"caf\xe9 CAF\xc9";
+
+        let matches = Verifier::new(&Query::literal("café"), Encoding::Latin1).unwrap().scrape_bytes("test", file_bytes);
+
+        assert_eq!(vec![(0, 4), (5, 4)], matches.iter().map(|m| (m.offset, m.length)).collect::<Vec<_>>());
+        assert_eq!("café CAFÉ", matches[0].text);
+
+        // Unless told otherwise, in which case it's UTF-8 with invalid bytes.
+        assert!(Verifier::new(&Query::literal("café"), Encoding::Utf8).unwrap().scrape_bytes("test", file_bytes).is_empty());
+    }
+}
@@ -1,4 +1,13 @@
-use std::{fmt::Debug, hash::{Hash, Hasher}, io::SeekFrom, string::FromUtf8Error};
+use std::{fmt::Debug, hash::Hash, string::FromUtf8Error};
+
+use crate::bloom::BloomFilter;
+use crate::case_folding::for_each_folded;
+
+// Indexed text is folded down to lowercase ASCII letters and digits, so there are
+// only this many different trigrams.
+const ALPHABET_SIZE: usize = 36;
+const DISTINCT_TRIGRAMS: usize = ALPHABET_SIZE * ALPHABET_SIZE * ALPHABET_SIZE;
+const ALPHABET: &[u8; ALPHABET_SIZE] = b"0123456789abcdefghijklmnopqrstuvwxyz";
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct Trigram {
@@ -37,7 +46,7 @@ impl Trigram {
     }
 
     pub fn to_u32(&self) -> u32 {
-        ((self.first as u32) << 16) | ((self.second as u32) << 8) | ((self.third as u32) << 0)
+        ((self.first as u32) << 16) | ((self.second as u32) << 8) | (self.third as u32)
     }
 
     pub fn to_string(&self) -> Result<String, FromUtf8Error> {
@@ -55,13 +64,88 @@ impl PartialEq<Trigram> for str {
     }
 }
 
+// Collects the distinct trigrams of text as it's fed in a character at a time, giving
+// the same trigrams as Trigram::from_str(&lowercase_alphanumeric_only(text)) without
+// holding on to the text or a trigram per position.
+pub struct TrigramExtractor {
+    // The last two letters or digits seen.
+    previous: [u8; 2],
+    previous_count: usize,
+
+    // One bit per possible trigram.
+    seen: Vec<u64>,
+}
+
+impl TrigramExtractor {
+    pub fn new() -> TrigramExtractor {
+        TrigramExtractor {
+            previous: [0; 2],
+            previous_count: 0,
+            seen: vec![0; DISTINCT_TRIGRAMS.div_ceil(64)],
+        }
+    }
+
+    pub fn push_char(&mut self, c: char) {
+        for_each_folded(c, |folded| {
+            if folded.is_ascii_alphanumeric() {
+                self.push_byte(folded as u8);
+            }
+        });
+    }
+
+    fn push_byte(&mut self, byte: u8) {
+        if self.previous_count == 2 {
+            let index = (alphabet_index(self.previous[0]) * ALPHABET_SIZE +
+                alphabet_index(self.previous[1])) * ALPHABET_SIZE +
+                alphabet_index(byte);
+
+            self.seen[index / 64] |= 1 << (index % 64);
+        }
+
+        self.previous = [self.previous[1], byte];
+        self.previous_count = (self.previous_count + 1).min(2);
+    }
+
+    pub fn bloom_filter(&self, filter_size: usize) -> BloomFilter {
+        let mut bloom_filter = BloomFilter::new(&[], filter_size);
+
+        for (word_index, word) in self.seen.iter().enumerate() {
+            let mut bits = *word;
+
+            while bits != 0 {
+                let index = word_index * 64 + bits.trailing_zeros() as usize;
+                bits &= bits - 1;
+
+                let trigram = Trigram {
+                    first: ALPHABET[index / (ALPHABET_SIZE * ALPHABET_SIZE)],
+                    second: ALPHABET[index / ALPHABET_SIZE % ALPHABET_SIZE],
+                    third: ALPHABET[index % ALPHABET_SIZE],
+                };
+
+                bloom_filter.insert(trigram.to_u32());
+            }
+        }
+
+        bloom_filter
+    }
+}
+
+fn alphabet_index(byte: u8) -> usize {
+    match byte {
+        b'0'..=b'9' => (byte - b'0') as usize,
+        _ => (byte - b'a') as usize + 10,
+    }
+}
+
 fn count_trigrams(bytes: &[u8]) -> usize {
      ((bytes.len() as isize) - 2).max(0) as usize
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::trigram::Trigram;
+    use crate::bloom::BloomFilter;
+    use crate::compression_utils::lowercase_alphanumeric_only;
+    use crate::trigram::{Trigram, TrigramExtractor};
 
     #[test]
     fn trigram_empty() {
@@ -82,7 +166,7 @@ mod tests {
     fn trigram_one() {
         let trigram = Trigram::from_str("abc");
         assert_eq!(1, trigram.len());
-        assert_eq!("abc", trigram.get(0).unwrap());
+        assert_eq!("abc", trigram.first().unwrap());
     }
 
     #[test]
@@ -93,4 +177,19 @@ mod tests {
         assert_eq!(*"bcd", trigram[1]);
         assert_eq!(*"cde", trigram[2]);
     }
+
+    #[test]
+    fn trigram_extractor_matches_from_str() {
+        for text in ["", "ab", "fn main() { println!(\"Hello\"); }", "Straße İstanbul 0xFF_ff", "aaaaaaa"] {
+            let mut extractor = TrigramExtractor::new();
+            text.chars().for_each(|c| extractor.push_char(c));
+
+            let u32s: Vec<u32> = Trigram::from_str(&lowercase_alphanumeric_only(text))
+                .iter()
+                .map(|trigram| trigram.to_u32())
+                .collect();
+
+            assert_eq!(BloomFilter::new(&u32s, 64).filter_array, extractor.bloom_filter(64).filter_array, "{}", text);
+        }
+    }
 }
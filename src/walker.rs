@@ -0,0 +1,384 @@
+use std::collections::HashSet;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crossbeam_deque::{Injector, Stealer, Worker};
+
+#[derive(Clone, Default)]
+pub struct WalkOptions {
+    // Canonical paths of folders to leave out.
+    pub excluded_directories: Vec<PathBuf>,
+
+    // Descend into symlinked folders and include symlinked files, instead of
+    // ignoring symlinks altogether.
+    pub follow_symlinks: bool,
+
+    // Stay on the file system the walk started on, e.g. to keep out of network
+    // shares mounted inside the tree.
+    pub one_file_system: bool,
+}
+
+// Identifies a file or folder however it was reached. Where there are no inodes,
+// canonical paths do the job for symlinks, but hardlinks can't be told apart.
+#[cfg(unix)]
+type FileId = (u64, u64);
+
+#[cfg(not(unix))]
+type FileId = PathBuf;
+
+// How long idle walker threads wait before looking for work again.
+const IDLE_WAIT: Duration = Duration::from_micros(100);
+
+// Returns the paths of all files under 'path', each file only once however many
+// links or symlinks lead to it.
+pub fn walk_directory(path: &str, options: &WalkOptions) -> Vec<String> {
+    let file_paths = Mutex::new(Vec::new());
+
+    walk_directory_parallel(path, options, 1, |file_path| file_paths.lock().unwrap().push(file_path));
+
+    file_paths.into_inner().unwrap()
+}
+
+// Walks 'path' on 'threads' threads, calling 'on_file' with each file as soon as it's
+// found, from whichever thread found it. Each thread walks folders depth first from
+// its own queue and steals folders from the others when it runs out, so one deep
+// subtree doesn't leave the rest of the threads idle.
+pub fn walk_directory_parallel(path: &str, options: &WalkOptions, threads: usize, on_file: impl Fn(String) + Sync) {
+    let root_metadata = std::fs::metadata(path).unwrap();
+
+    let walker = Walker {
+        options,
+        root_device: device(&root_metadata),
+        visited_directories: Mutex::new(HashSet::new()),
+        seen_files: Mutex::new(HashSet::new()),
+        injector: Injector::new(),
+        pending_directories: AtomicUsize::new(1),
+        on_file,
+    };
+
+    if let Some(root_id) = file_id(Path::new(path), &root_metadata) {
+        walker.visited_directories.lock().unwrap().insert(root_id);
+    }
+
+    walker.injector.push(PathBuf::from(path));
+
+    let queues: Vec<Worker<PathBuf>> = (0..threads.max(1)).map(|_| Worker::new_lifo()).collect();
+    let stealers: Vec<Stealer<PathBuf>> = queues.iter().map(|queue| queue.stealer()).collect();
+
+    std::thread::scope(|scope| {
+        for queue in queues {
+            let walker = &walker;
+            let stealers = &stealers;
+
+            scope.spawn(move || walker.run(queue, stealers));
+        }
+    });
+}
+
+struct Walker<'a, F: Fn(String) + Sync> {
+    options: &'a WalkOptions,
+    root_device: Option<u64>,
+
+    // Folders already walked, so symlink cycles end and folders linked from more
+    // than one place are walked once. Only kept when following symlinks.
+    visited_directories: Mutex<HashSet<FileId>>,
+
+    // Files that could be reached by more than one path.
+    seen_files: Mutex<HashSet<FileId>>,
+
+    // Where the walk starts from.
+    injector: Injector<PathBuf>,
+
+    // Folders queued or being walked. The walk is over once this drops to zero.
+    pending_directories: AtomicUsize,
+
+    on_file: F,
+}
+
+impl<F: Fn(String) + Sync> Walker<'_, F> {
+    fn run(&self, queue: Worker<PathBuf>, stealers: &[Stealer<PathBuf>]) {
+        loop {
+            match self.find_work(&queue, stealers) {
+                Some(directory) => {
+                    self.walk(&directory, &queue);
+                    self.pending_directories.fetch_sub(1, Ordering::SeqCst);
+                },
+                None if self.pending_directories.load(Ordering::SeqCst) == 0 => return,
+
+                // Others are still walking and may queue more folders.
+                None => std::thread::sleep(IDLE_WAIT),
+            }
+        }
+    }
+
+    fn find_work(&self, queue: &Worker<PathBuf>, stealers: &[Stealer<PathBuf>]) -> Option<PathBuf> {
+        queue.pop().or_else(|| {
+            std::iter::repeat_with(|| self.injector
+                .steal_batch_and_pop(queue)
+                .or_else(|| stealers.iter().map(|stealer| stealer.steal()).collect()))
+                .find(|steal| !steal.is_retry())
+                .and_then(|steal| steal.success())
+        })
+    }
+
+    fn walk(&self, directory: &Path, queue: &Worker<PathBuf>) {
+        // Unreadable folders and entries are skipped rather than panicking, which
+        // would leave the other threads waiting for this folder to finish.
+        let Ok(entries) = std::fs::read_dir(directory) else {
+            return;
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+
+            let metadata = if file_type.is_symlink() {
+                if !self.options.follow_symlinks {
+                    continue;
+                }
+
+                // It's the target that counts. Dangling links are skipped.
+                match std::fs::metadata(&path) {
+                    Ok(metadata) => metadata,
+                    Err(_) => continue,
+                }
+            } else if file_type.is_dir() || file_type.is_file() {
+                match entry.metadata() {
+                    Ok(metadata) => metadata,
+                    Err(_) => continue,
+                }
+            } else {
+                continue;
+            };
+
+            if self.options.one_file_system && device(&metadata) != self.root_device {
+                continue;
+            }
+
+            if metadata.is_dir() {
+                // Exclude the dot git folder in repos.
+                if path.file_name().is_some_and(|name| name == ".git") ||
+                    is_excluded(&path, &self.options.excluded_directories) {
+                    continue;
+                }
+
+                if self.options.follow_symlinks && !first_time(&self.visited_directories, &path, &metadata) {
+                    continue;
+                }
+
+                self.pending_directories.fetch_add(1, Ordering::SeqCst);
+                queue.push(path);
+            } else if metadata.is_file() {
+                // A file with several hardlinks, or any file when symlinks could lead to
+                // it too, might have been seen already.
+                if (self.options.follow_symlinks || links_count(&metadata) > 1) &&
+                    !first_time(&self.seen_files, &path, &metadata) {
+                    continue;
+                }
+
+                (self.on_file)(path.to_string_lossy().to_string());
+            }
+        }
+    }
+}
+
+// Whether walking 'root' would reach 'file_path', e.g. to treat a single changed file
+// the way indexing the whole folder would. Deleted files aren't reached.
+pub fn is_walked(root: &str, file_path: &str, options: &WalkOptions) -> bool {
+    let path = Path::new(file_path);
+
+    let Ok(link_metadata) = std::fs::symlink_metadata(path) else {
+        return false;
+    };
+
+    if link_metadata.file_type().is_symlink() && !options.follow_symlinks {
+        return false;
+    }
+
+    let root_device = std::fs::metadata(root).ok().and_then(|metadata| device(&metadata));
+    let file_device = std::fs::metadata(path).ok().and_then(|metadata| device(&metadata));
+
+    if options.one_file_system && file_device != root_device {
+        return false;
+    }
+
+    !path
+        .ancestors()
+        .skip(1)
+        .take_while(|ancestor| *ancestor != Path::new(root))
+        .any(|ancestor| is_excluded(ancestor, &options.excluded_directories))
+}
+
+fn first_time(seen: &Mutex<HashSet<FileId>>, path: &Path, metadata: &Metadata) -> bool {
+    file_id(path, metadata).is_none_or(|id| seen.lock().unwrap().insert(id))
+}
+
+fn is_excluded(directory: &Path, excluded_directories: &[PathBuf]) -> bool {
+    // Only pay for canonicalization when the folder name could possibly match.
+    excluded_directories
+        .iter()
+        .any(|excluded| excluded.file_name() == directory.file_name() &&
+            std::fs::canonicalize(directory).is_ok_and(|canonical| canonical == *excluded))
+}
+
+#[cfg(unix)]
+fn file_id(_path: &Path, metadata: &Metadata) -> Option<FileId> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(path: &Path, _metadata: &Metadata) -> Option<FileId> {
+    std::fs::canonicalize(path).ok()
+}
+
+#[cfg(unix)]
+fn device(metadata: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some(metadata.dev())
+}
+
+#[cfg(not(unix))]
+fn device(_metadata: &Metadata) -> Option<u64> {
+    None
+}
+
+#[cfg(unix)]
+fn links_count(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+
+    metadata.nlink()
+}
+
+#[cfg(not(unix))]
+fn links_count(_metadata: &Metadata) -> u64 {
+    1
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use uuid::Uuid;
+
+    use super::{walk_directory, walk_directory_parallel, WalkOptions};
+
+    // Builds:
+    //   src/main.rs
+    //   src/lib.rs
+    //   src/hardlink.rs -> same file as src/lib.rs
+    //   linked -> src (symlinked folder)
+    //   src/loop -> .. (cycle)
+    //   main_link.rs -> src/main.rs
+    //   dangling -> missing
+    fn test_tree() -> String {
+        let root = format!("{}/rust-indexer-walk-{}", std::env::temp_dir().display(), Uuid::new_v4());
+        std::fs::create_dir_all(format!("{}/src", root)).unwrap();
+        std::fs::write(format!("{}/src/main.rs", root), "fn main() {}").unwrap();
+        std::fs::write(format!("{}/src/lib.rs", root), "pub fn lib() {}").unwrap();
+        std::fs::hard_link(format!("{}/src/lib.rs", root), format!("{}/src/hardlink.rs", root)).unwrap();
+        symlink(format!("{}/src", root), format!("{}/linked", root)).unwrap();
+        symlink("..", format!("{}/src/loop", root)).unwrap();
+        symlink(format!("{}/src/main.rs", root), format!("{}/main_link.rs", root)).unwrap();
+        symlink(format!("{}/missing", root), format!("{}/dangling", root)).unwrap();
+        root
+    }
+
+    fn relative_paths(root: &str, files: Vec<String>) -> Vec<String> {
+        let mut relative: Vec<String> = files
+            .iter()
+            .map(|file| file.strip_prefix(root).unwrap().trim_start_matches('/').to_string())
+            .collect();
+        relative.sort();
+        relative
+    }
+
+    #[test]
+    fn walker_ignores_symlinks_by_default() {
+        let root = test_tree();
+
+        let files = walk_directory(&root, &WalkOptions::default());
+
+        // Hardlinked files are indexed once.
+        let files = relative_paths(&root, files);
+        assert_eq!(2, files.len());
+        assert!(files.contains(&"src/main.rs".to_string()));
+        assert!(files.contains(&"src/lib.rs".to_string()) ^ files.contains(&"src/hardlink.rs".to_string()));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn walker_follows_symlinks_once() {
+        let root = test_tree();
+
+        let options = WalkOptions { follow_symlinks: true, ..Default::default() };
+        let files = relative_paths(&root, walk_directory(&root, &options));
+
+        // The cycle ends, the linked folder and file aren't indexed twice, and the
+        // dangling link is skipped.
+        assert_eq!(2, files.len(), "{:?}", files);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn walker_follows_symlinks_outside_the_tree() {
+        let root = test_tree();
+        let outside = test_tree();
+        symlink(format!("{}/src", outside), format!("{}/outside", root)).unwrap();
+
+        let options = WalkOptions { follow_symlinks: true, ..Default::default() };
+        let files = relative_paths(&root, walk_directory(&root, &options));
+
+        assert_eq!(4, files.len(), "{:?}", files);
+        assert!(files.iter().any(|file| file.starts_with("outside/")));
+
+        // Nothing to cross on a single file system.
+        let options = WalkOptions { follow_symlinks: true, one_file_system: true, ..Default::default() };
+        assert_eq!(4, walk_directory(&root, &options).len());
+
+        std::fs::remove_dir_all(&root).unwrap();
+        std::fs::remove_dir_all(&outside).unwrap();
+    }
+
+    #[test]
+    fn walker_parallel_finds_everything_once() {
+        let root = format!("{}/rust-indexer-walk-{}", std::env::temp_dir().display(), Uuid::new_v4());
+
+        // A few wide and deep folders, so threads have something to steal.
+        let mut expected = Vec::new();
+        for i in 0..6 {
+            let mut directory = format!("{}/{}", root, i);
+            for depth in 0..i * 3 {
+                directory = format!("{}/{}", directory, depth);
+                std::fs::create_dir_all(&directory).unwrap();
+
+                for j in 0..5 {
+                    let file_path = format!("{}/{}.txt", directory, j);
+                    std::fs::write(&file_path, "text").unwrap();
+                    expected.push(file_path);
+                }
+            }
+        }
+        expected.sort();
+
+        for threads in [1, 2, 8] {
+            let found = std::sync::Mutex::new(Vec::new());
+            walk_directory_parallel(&root, &WalkOptions::default(), threads, |file_path| found.lock().unwrap().push(file_path));
+
+            let mut found = found.into_inner().unwrap();
+            found.sort();
+            assert_eq!(expected, found);
+        }
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}